@@ -0,0 +1,149 @@
+use chrono::{Duration, Utc};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use dross::{
+    core::{datatypes::Page, helpers::TraversalStrategy},
+    notion::{
+        fake::{FakeNotionApi, FAKE_USER_ID},
+        Notion, TokenBucket,
+    },
+};
+use futures::{pin_mut, StreamExt};
+use tokio::runtime::Runtime;
+
+const PAGE_ID: &str = "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb";
+
+/// Every Block has this many children until the deepest level, which makes a Page that's
+/// both wide and deep, e.g. a long outline with a few levels of nesting
+const CHILDREN_PER_BLOCK: usize = 20;
+const DEPTH: usize = 3;
+
+/// A fake serving a synthetic Page where every Block above the deepest level is old, and
+/// every Block at the deepest level was edited recently, so the search has to go all the way
+/// down to find anything. Only the block children endpoint is used by the search.
+fn wide_and_deep_page() -> FakeNotionApi {
+    let api = FakeNotionApi::new();
+    let mut parents = vec![PAGE_ID.to_string()];
+    let mut next_id = 0;
+    for level in 1..=DEPTH {
+        let mut next_parents = Vec::new();
+        for parent_id in parents {
+            let blocks = (0..CHILDREN_PER_BLOCK)
+                .map(|_| {
+                    next_id += 1;
+                    let id = format!("00000000-0000-4000-8000-{:012x}", next_id);
+                    next_parents.push(id.clone());
+                    block_json(&id, &parent_id, level == DEPTH)
+                })
+                .collect();
+            api.set_children(&parent_id, blocks);
+        }
+        parents = next_parents;
+    }
+    api
+}
+
+fn block_json(id: &str, parent_id: &str, recent: bool) -> serde_json::Value {
+    let edited = if recent {
+        Utc::now()
+    } else {
+        Utc::now() - Duration::days(365)
+    };
+    serde_json::json!({
+        "object": "block",
+        "id": id,
+        "parent": {"type": "block_id", "block_id": parent_id},
+        "created_time": edited.to_rfc3339(),
+        "last_edited_time": edited.to_rfc3339(),
+        "created_by": {"object": "user", "id": FAKE_USER_ID},
+        "last_edited_by": {"object": "user", "id": FAKE_USER_ID},
+        "has_children": !recent,
+        "archived": false,
+        "in_trash": false,
+        "type": "paragraph",
+        "paragraph": {
+            "rich_text": [{
+                "type": "text",
+                "text": {"content": "Remember to follow up with Dana about the invoice", "link": null},
+                "annotations": {
+                    "bold": false,
+                    "italic": false,
+                    "strikethrough": false,
+                    "underline": false,
+                    "code": false,
+                    "color": "default"
+                },
+                "plain_text": "Remember to follow up with Dana about the invoice",
+                "href": null
+            }],
+            "color": "default"
+        }
+    })
+}
+
+fn notion(strategy: TraversalStrategy) -> Notion {
+    // nothing here talks to the real API, so there's no reason to wait on the rate limiter
+    let mut notion = Notion::with_api(wide_and_deep_page(), TokenBucket::new(u32::MAX, 1e9));
+    // every Block above the deepest level is old, which would otherwise end the search early
+    notion.set_consecutive_old_blocks_abort_threshold(usize::MAX);
+    notion.set_traversal_strategy(strategy);
+    notion
+}
+
+fn page() -> Page {
+    Page {
+        id: PAGE_ID.to_string(),
+        title: "Wide and deep".to_string(),
+        url: format!("https://www.notion.so/{}", PAGE_ID),
+        creation_date: Utc::now(),
+        update_date: Utc::now(),
+        child_blocks: Vec::new(),
+        created_by_id: FAKE_USER_ID.to_string(),
+        parent_database_id: None,
+        parent_page_id: None,
+    }
+}
+
+/// Counts the Block roots found, stopping after the first one if `first_only` is set
+async fn search(notion: &Notion, page: &Page, first_only: bool) -> usize {
+    let abort_time = Utc::now() + Duration::hours(1);
+    let stream = notion.stream_page_block_roots(page, Duration::days(1), abort_time);
+    pin_mut!(stream);
+    let mut found = 0;
+    while let Some(block) = stream.next().await {
+        black_box(block.unwrap());
+        found += 1;
+        if first_only {
+            break;
+        }
+    }
+    found
+}
+
+fn bench_block_root_search(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let page = page();
+    let strategies = [TraversalStrategy::Bfs, TraversalStrategy::Dfs];
+
+    let mut group = c.benchmark_group("block_root_search_first_result");
+    for strategy in strategies {
+        let notion = notion(strategy);
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{:?}", strategy)),
+            |b| b.iter(|| runtime.block_on(search(&notion, &page, true))),
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("block_root_search_all_results");
+    for strategy in strategies {
+        let notion = notion(strategy);
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{:?}", strategy)),
+            |b| b.iter(|| runtime.block_on(search(&notion, &page, false))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_block_root_search);
+criterion_main!(benches);
@@ -0,0 +1,117 @@
+use chrono::{Duration, TimeZone, Utc};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use dendron::{tree_node, Node};
+use dross::core::{
+    datatypes::{Block, BlockTree},
+    helpers::build_markdown_from_trees,
+};
+use notion_client::objects::block::BlockType;
+
+/// How many children each node gets when building the larger trees, which makes them
+/// about as wide and deep as a long, well-organized Notion Page
+const CHILDREN_PER_NODE: usize = 5;
+
+const SENTENCES: [&str; 4] = [
+    "Met with the team to go over the Q3 roadmap and agreed to cut the mobile redesign.",
+    "Remember to follow up with Dana about the invoice",
+    "The key insight is that spaced repetition works best when the intervals grow exponentially, so review after a day, then three days, then a week.",
+    "TODO",
+];
+
+fn block(i: usize) -> Block {
+    let block_type = match i % 4 {
+        0 => BlockType::Paragraph {
+            paragraph: Default::default(),
+        },
+        1 => BlockType::BulletedListItem {
+            bulleted_list_item: Default::default(),
+        },
+        2 => BlockType::NumberedListItem {
+            numbered_list_item: Default::default(),
+        },
+        _ => BlockType::Heading2 {
+            heading_2: Default::default(),
+        },
+    };
+    let created = Utc.with_ymd_and_hms(2024, 8, 1, 9, 0, 0).unwrap() + Duration::minutes(i as i64);
+
+    Block {
+        id: format!("{:08x}-14cb-45a6-a4b6-d2b48faecccb", i),
+        page_id: "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb".to_string(),
+        block_type,
+        text: SENTENCES[i % SENTENCES.len()].to_string(),
+        creation_date: created,
+        update_date: created + Duration::hours(1),
+        parent_block_id: None,
+        has_children: false,
+    }
+}
+
+/// A single root with 10 leaves, written out the way it'd look on a short Page
+fn small_tree() -> BlockTree {
+    BlockTree::from(tree_node! {
+        block(0), [
+            block(1), block(2), block(3), block(4), block(5),
+            block(6), block(7), block(8), block(9), block(10),
+        ]
+    })
+}
+
+/// A tree of `node_count` nodes, where each node has `CHILDREN_PER_NODE` children until
+/// there are no nodes left
+fn tree_with_nodes(node_count: usize) -> BlockTree {
+    let root = Node::new_tree(block(0));
+    let grant = root.tree().grant_hierarchy_edit().unwrap();
+
+    // nodes are added breadth first, so node i's parent is always node (i - 1) / CHILDREN_PER_NODE
+    let mut nodes = vec![root.clone()];
+    for i in 1..node_count {
+        let child = nodes[(i - 1) / CHILDREN_PER_NODE].create_as_last_child(&grant, block(i));
+        nodes.push(child);
+    }
+
+    BlockTree::from(root)
+}
+
+fn forests() -> Vec<(&'static str, Vec<BlockTree>)> {
+    vec![
+        ("1 root x 10 leaves", vec![small_tree()]),
+        (
+            "10 roots x 100 nodes",
+            (0..10).map(|_| tree_with_nodes(100)).collect(),
+        ),
+        (
+            "100 roots x 1000 nodes",
+            (0..100).map(|_| tree_with_nodes(1000)).collect(),
+        ),
+    ]
+}
+
+fn bench_markdown_building(c: &mut Criterion) {
+    let forests = forests();
+
+    // walking the trees on their own, to compare against the full markdown build
+    let mut group = c.benchmark_group("dfs_traversal");
+    for (name, trees) in &forests {
+        group.bench_with_input(BenchmarkId::from_parameter(name), trees, |b, trees| {
+            b.iter(|| {
+                trees
+                    .iter()
+                    .map(|tree| black_box(tree.flatten()).len())
+                    .sum::<usize>()
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("build_markdown_from_trees");
+    for (name, trees) in &forests {
+        group.bench_with_input(BenchmarkId::from_parameter(name), trees, |b, trees| {
+            b.iter(|| build_markdown_from_trees(black_box(trees.clone())))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_markdown_building);
+criterion_main!(benches);
@@ -1,2 +1,20 @@
+pub mod access_history;
+pub mod benchmark;
+pub mod config;
 pub mod datatypes;
+pub mod error;
+pub mod export;
+pub mod gist;
+pub mod graph;
 pub mod helpers;
+pub mod ingest;
+pub mod markdown;
+pub mod output;
+pub mod page_map;
+pub mod performance;
+pub mod search_cache;
+pub mod selector;
+pub mod sink;
+pub mod state;
+pub mod stats;
+pub mod webhook;
@@ -0,0 +1,158 @@
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+
+use super::{
+    datatypes::{Page, PageID},
+    stats::format_table,
+};
+
+/// How long each run's Pages are remembered for. `dross stats --access-history` only looks at
+/// the last 30 days, so this leaves room to look further back without the file growing forever.
+const ACCESS_HISTORY_RETENTION_DAYS: i64 = 365;
+
+/// Which Pages were included in each run, for finding the Pages that are most actively used
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessHistory {
+    pub accesses: Vec<PageAccess>,
+}
+
+/// A Page that was included in a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageAccess {
+    pub page_id: PageID,
+    pub page_title: String,
+    pub run_at: DateTime<Utc>,
+}
+
+/// How often a Page was included in runs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PageAccessCount {
+    pub page_id: PageID,
+    /// The Page's title the last time it was included, in case it was renamed
+    pub page_title: String,
+    pub runs: usize,
+    pub last_run_at: DateTime<Utc>,
+}
+
+impl AccessHistory {
+    /// Loads the access history from `path`, or an empty history if there isn't a readable one
+    #[must_use]
+    pub fn load(path: &Path) -> Self {
+        let Ok(contents) = fs::read_to_string(path) else {
+            return AccessHistory::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            debug!(target: "notion", "ignoring unreadable access history at {}: {}", path.display(), e);
+            AccessHistory::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    /// Records that `pages` were included in the run at `run_at`, and forgets runs from more
+    /// than a year before it
+    pub fn record_run(&mut self, pages: &[Page], run_at: DateTime<Utc>) {
+        let retained_since = run_at - Duration::days(ACCESS_HISTORY_RETENTION_DAYS);
+        self.accesses
+            .retain(|access| access.run_at >= retained_since);
+        self.accesses.extend(pages.iter().map(|page| PageAccess {
+            page_id: page.id.clone(),
+            page_title: page.title.clone(),
+            run_at,
+        }));
+    }
+
+    /// How many runs since `since` each Page was included in, most often included first
+    #[must_use]
+    pub fn most_accessed(&self, since: DateTime<Utc>) -> Vec<PageAccessCount> {
+        let mut counts: HashMap<&PageID, PageAccessCount> = HashMap::new();
+        for access in self.accesses.iter().filter(|access| access.run_at >= since) {
+            let count = counts
+                .entry(&access.page_id)
+                .or_insert_with(|| PageAccessCount {
+                    page_id: access.page_id.clone(),
+                    page_title: access.page_title.clone(),
+                    runs: 0,
+                    last_run_at: access.run_at,
+                });
+            count.runs += 1;
+            if access.run_at >= count.last_run_at {
+                count.last_run_at = access.run_at;
+                count.page_title = access.page_title.clone();
+            }
+        }
+
+        let mut counts = counts.into_values().collect::<Vec<_>>();
+        counts.sort_by(|a, b| {
+            b.runs
+                .cmp(&a.runs)
+                .then_with(|| a.page_title.cmp(&b.page_title))
+        });
+        counts
+    }
+}
+
+#[must_use]
+pub fn access_counts_to_table(counts: &[PageAccessCount]) -> String {
+    let rows = counts
+        .iter()
+        .map(|count| {
+            [
+                count.page_title.clone(),
+                count.runs.to_string(),
+                count.last_run_at.format("%Y-%m-%d %H:%M").to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    format_table(["Page", "Runs", "Last included"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(id: &str, title: &str) -> Page {
+        Page::for_test(id, title)
+    }
+
+    #[test]
+    fn test_most_accessed_counts_runs_since_the_cutoff() {
+        let now = Utc::now();
+        let mut history = AccessHistory::default();
+        history.record_run(&[page("journal", "Journal")], now - Duration::days(40));
+        history.record_run(
+            &[page("journal", "Journal"), page("inbox", "Inbox")],
+            now - Duration::days(2),
+        );
+        history.record_run(&[page("journal", "Daily Journal")], now);
+
+        let counts = history.most_accessed(now - Duration::days(30));
+
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[0].page_title, "Daily Journal");
+        assert_eq!(counts[0].runs, 2);
+        assert_eq!(counts[0].last_run_at, now);
+        assert_eq!(counts[1].page_title, "Inbox");
+        assert_eq!(counts[1].runs, 1);
+    }
+
+    #[test]
+    fn test_record_run_forgets_runs_older_than_a_year() {
+        let now = Utc::now();
+        let mut history = AccessHistory::default();
+        history.record_run(&[page("journal", "Journal")], now - Duration::days(400));
+
+        history.record_run(&[page("inbox", "Inbox")], now);
+
+        assert_eq!(history.accesses.len(), 1);
+        assert_eq!(history.accesses[0].page_id, "inbox");
+    }
+}
@@ -0,0 +1,211 @@
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{fs, io::ErrorKind, path::Path};
+use tokio::time::Instant;
+
+use super::{error::DrossError, helpers::build_markdown_from_trees, stats::format_table};
+use crate::notion::Notion;
+
+/// How long each phase of a limited ingest took, for finding where a run spends its time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub run_at: DateTime<Utc>,
+    pub page_count: usize,
+    pub api_calls: u32,
+    /// Searching for the recently edited Pages, including fetching their top-level Blocks
+    pub list_pages_ms: f64,
+    /// Searching every Page for its recently edited Blocks
+    pub block_roots_ms: f64,
+    /// The same search, per Page
+    pub block_roots_p50_ms: f64,
+    pub block_roots_p95_ms: f64,
+    pub block_roots_p99_ms: f64,
+    /// Growing the recently edited Blocks into trees
+    pub grow_trees_ms: f64,
+    pub build_markdown_ms: f64,
+}
+
+impl BenchmarkReport {
+    #[must_use]
+    pub fn total_ms(&self) -> f64 {
+        self.list_pages_ms + self.block_roots_ms + self.grow_trees_ms + self.build_markdown_ms
+    }
+}
+
+/// Runs the ingest pipeline on the `page_count` most recently edited Pages within `dur`, timing
+/// each phase. Linked Page titles aren't resolved and no output is written, since neither
+/// tells us much about the pipeline itself.
+pub async fn run_benchmark(
+    notion: &Notion,
+    dur: Duration,
+    page_count: usize,
+) -> Result<BenchmarkReport, DrossError> {
+    let api_calls_before = notion.api_calls_made();
+
+    let start = Instant::now();
+    let pages = notion.get_last_n_edited_pages(dur, page_count).await?;
+    let list_pages_ms = elapsed_ms(start);
+
+    let mut per_page_block_roots_ms = Vec::new();
+    let mut block_roots = Vec::new();
+    for page in &pages {
+        let start = Instant::now();
+        block_roots.push(notion.get_page_block_roots(page, dur).await?);
+        per_page_block_roots_ms.push(elapsed_ms(start));
+    }
+
+    let start = Instant::now();
+    let mut trees = Vec::new();
+    for roots in block_roots {
+        trees.push(notion.grow_the_roots(roots).await?);
+    }
+    let grow_trees_ms = elapsed_ms(start);
+
+    let start = Instant::now();
+    for page_trees in trees {
+        let markdown = build_markdown_from_trees(page_trees);
+        debug!(target: "notion", "built {} bytes of markdown", markdown.len());
+    }
+    let build_markdown_ms = elapsed_ms(start);
+
+    Ok(BenchmarkReport {
+        run_at: Utc::now(),
+        page_count: pages.len(),
+        api_calls: notion.api_calls_made() - api_calls_before,
+        list_pages_ms,
+        block_roots_ms: per_page_block_roots_ms.iter().sum(),
+        block_roots_p50_ms: percentile(&per_page_block_roots_ms, 50.0),
+        block_roots_p95_ms: percentile(&per_page_block_roots_ms, 95.0),
+        block_roots_p99_ms: percentile(&per_page_block_roots_ms, 99.0),
+        grow_trees_ms,
+        build_markdown_ms,
+    })
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+/// The nearest-rank `p`th percentile of `values`, or 0 if there are none
+fn percentile(values: &[f64], p: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Each phase's time and share of the total, followed by the per-Page block root percentiles
+#[must_use]
+pub fn benchmark_to_table(report: &BenchmarkReport) -> String {
+    let total = report.total_ms();
+    let phase = |name: &str, ms: f64| {
+        let share = if total > 0.0 { ms / total * 100.0 } else { 0.0 };
+        [name.to_string(), format_ms(ms), format!("{:.1}%", share)]
+    };
+    let per_page = |name: &str, ms: f64| [name.to_string(), format_ms(ms), "-".to_string()];
+
+    let rows = [
+        phase("List Pages", report.list_pages_ms),
+        phase("Find block roots", report.block_roots_ms),
+        phase("Grow trees", report.grow_trees_ms),
+        phase("Build markdown", report.build_markdown_ms),
+        phase("Total", total),
+        per_page("Block roots per Page p50", report.block_roots_p50_ms),
+        per_page("Block roots per Page p95", report.block_roots_p95_ms),
+        per_page("Block roots per Page p99", report.block_roots_p99_ms),
+    ];
+
+    format_table(["Phase", "Time", "Share"], &rows)
+}
+
+pub(crate) fn format_ms(ms: f64) -> String {
+    if ms >= 1000.0 {
+        format!("{:.2} s", ms / 1000.0)
+    } else {
+        format!("{:.1} ms", ms)
+    }
+}
+
+/// Adds `report` to the history of benchmark runs at `path`, starting a new history if there
+/// isn't one yet. A history that can't be read is left as it is and returned as an error,
+/// rather than replaced.
+pub fn save_benchmark(report: &BenchmarkReport, path: &Path) -> std::io::Result<()> {
+    let mut history: Vec<BenchmarkReport> = match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    history.push(report.clone());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&history)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let values = [5.0, 1.0, 4.0, 2.0, 3.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+
+        assert_eq!(percentile(&values, 50.0), 5.0);
+        assert_eq!(percentile(&values, 95.0), 10.0);
+        assert_eq!(percentile(&[2.0], 99.0), 2.0);
+        assert_eq!(percentile(&[], 50.0), 0.0);
+    }
+
+    fn report() -> BenchmarkReport {
+        BenchmarkReport {
+            run_at: Utc::now(),
+            page_count: 2,
+            api_calls: 12,
+            list_pages_ms: 250.0,
+            block_roots_ms: 1500.0,
+            block_roots_p50_ms: 700.0,
+            block_roots_p95_ms: 800.0,
+            block_roots_p99_ms: 800.0,
+            grow_trees_ms: 250.0,
+            build_markdown_ms: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_benchmark_to_table() {
+        let report = report();
+
+        let table = benchmark_to_table(&report);
+
+        assert!(table.starts_with("Phase"));
+        assert!(table.contains("Find block roots          1.50 s    75.0%"));
+        assert!(table.contains("Total                     2.00 s    100.0%"));
+        assert!(table.contains("Block roots per Page p50  700.0 ms  -"));
+    }
+
+    #[test]
+    fn test_save_benchmark_keeps_an_unreadable_history() {
+        let path =
+            std::env::temp_dir().join(format!("dross-benchmark-{}.json", std::process::id()));
+        fs::remove_file(&path).ok();
+
+        save_benchmark(&report(), &path).unwrap();
+        save_benchmark(&report(), &path).unwrap();
+        let history: Vec<BenchmarkReport> =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(history.len(), 2);
+
+        fs::write(&path, "not a history").unwrap();
+        let saved = save_benchmark(&report(), &path);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        assert!(saved.is_err());
+        assert_eq!(contents, "not a history");
+    }
+}
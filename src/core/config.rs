@@ -0,0 +1,465 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{env, fmt, fs, path::Path};
+
+use super::{
+    helpers::{BlockSort, TraversalStrategy},
+    output::OutputEncoding,
+};
+
+/// Every key that can be read or written with `dross config get/set`, in the order they're shown
+pub const CONFIG_KEYS: &[&str] = &[
+    "notion.token",
+    "ingest.days",
+    "ingest.page_map_ttl_hours",
+    "ingest.search_cache_ttl_minutes",
+    "ingest.sort_blocks",
+    "ingest.consecutive_old_blocks_abort_threshold",
+    "ingest.traversal_strategy",
+    "ingest.max_blocks_per_page",
+    "ingest.track_access",
+    "output.encoding",
+    "output.webhook_url",
+    "output.webhook_secret",
+];
+
+/// Keys whose values are hidden by `dross config show`
+const SECRET_KEYS: &[&str] = &["notion.token", "output.webhook_secret"];
+
+/// dross's settings, as stored in `~/.config/dross/config.toml`. Every setting is optional so
+/// that a `Config` can also hold just the settings from one source (the file, the environment,
+/// or the CLI flags) before they're merged; unset settings fall back to the CLI's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub notion: NotionConfig,
+    pub ingest: IngestConfig,
+    pub output: OutputConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotionConfig {
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IngestConfig {
+    pub days: Option<i64>,
+    pub page_map_ttl_hours: Option<i64>,
+    /// How long a search for recently edited Pages is reused by later runs, 0 to never reuse it
+    pub search_cache_ttl_minutes: Option<i64>,
+    pub sort_blocks: Option<BlockSort>,
+    /// How many old Blocks in a row to see before assuming the rest of a Page is old too
+    pub consecutive_old_blocks_abort_threshold: Option<usize>,
+    pub traversal_strategy: Option<TraversalStrategy>,
+    /// The most old Blocks to search below on a single Page
+    pub max_blocks_per_page: Option<usize>,
+    /// Record which Pages each run includes, for `dross stats --access-history`
+    pub track_access: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub encoding: Option<OutputEncoding>,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+}
+
+/// Where the effective value of a setting came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::File => write!(f, "config file"),
+            ConfigSource::Env => write!(f, "environment"),
+            ConfigSource::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownKey(String),
+    InvalidValue {
+        key: String,
+        value: String,
+        reason: String,
+    },
+    /// The config file isn't valid TOML, or doesn't match the shape of `Config`
+    Parse(toml::de::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownKey(key) => write!(
+                f,
+                "unknown config key {}, expected one of: {}",
+                key,
+                CONFIG_KEYS.join(", ")
+            ),
+            ConfigError::InvalidValue { key, value, reason } => {
+                write!(f, "invalid value {:?} for {}: {}", value, key, reason)
+            }
+            ConfigError::Parse(e) => write!(f, "invalid config file: {}", e),
+            ConfigError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Parse(e) => Some(e),
+            ConfigError::Io(e) => Some(e),
+            ConfigError::UnknownKey(_) | ConfigError::InvalidValue { .. } => None,
+        }
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl Config {
+    /// Reads the config file at `path`, or returns an empty `Config` if there isn't one
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let config: Config = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e.into()),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks every setting against the rules `set` applies, since a hand-edited config file
+    /// can hold values like `days = 0` that `set` would have rejected
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut checked = Config::default();
+        for key in CONFIG_KEYS {
+            if let Some(value) = self.get(key)? {
+                checked.set(key, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the config to `path` atomically, by writing a temporary file next to it and
+    /// renaming it over the old one, so a failed write never leaves a truncated config behind
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = toml::to_string_pretty(self).expect("Config always serializes");
+        let tmp_path = path.with_extension("toml.tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads the settings from the environment. `notion.token` comes from `NOTION_TOKEN`, and
+    /// every key can be set with its `DROSS_` variable, e.g. `DROSS_INGEST_DAYS` for `ingest.days`.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+        if let Ok(token) = env::var("NOTION_TOKEN") {
+            config.set("notion.token", &token)?;
+        }
+        for key in CONFIG_KEYS {
+            if let Ok(value) = env::var(env_var_for_key(key)) {
+                config.set(key, &value)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// The value of the setting at the dot-separated `key`, or `None` if it isn't set
+    pub fn get(&self, key: &str) -> Result<Option<String>, ConfigError> {
+        let value = match key {
+            "notion.token" => self.notion.token.clone(),
+            "ingest.days" => self.ingest.days.map(|days| days.to_string()),
+            "ingest.page_map_ttl_hours" => self.ingest.page_map_ttl_hours.map(|h| h.to_string()),
+            "ingest.search_cache_ttl_minutes" => {
+                self.ingest.search_cache_ttl_minutes.map(|m| m.to_string())
+            }
+            "ingest.sort_blocks" => self.ingest.sort_blocks.map(value_enum_name),
+            "ingest.consecutive_old_blocks_abort_threshold" => self
+                .ingest
+                .consecutive_old_blocks_abort_threshold
+                .map(|n| n.to_string()),
+            "ingest.traversal_strategy" => self.ingest.traversal_strategy.map(value_enum_name),
+            "ingest.max_blocks_per_page" => self.ingest.max_blocks_per_page.map(|n| n.to_string()),
+            "ingest.track_access" => self.ingest.track_access.map(|track| track.to_string()),
+            "output.encoding" => self.output.encoding.map(value_enum_name),
+            "output.webhook_url" => self.output.webhook_url.clone(),
+            "output.webhook_secret" => self.output.webhook_secret.clone(),
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        };
+        Ok(value)
+    }
+
+    /// Parses `value` and sets the setting at the dot-separated `key` to it
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let invalid = |reason: String| ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+            reason,
+        };
+        let hours_or_days = || {
+            value
+                .parse::<i64>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| invalid("expected a positive whole number".to_string()))
+        };
+
+        match key {
+            "notion.token" => self.notion.token = Some(value.to_string()),
+            "ingest.days" => self.ingest.days = Some(hours_or_days()?),
+            "ingest.page_map_ttl_hours" => self.ingest.page_map_ttl_hours = Some(hours_or_days()?),
+            "ingest.search_cache_ttl_minutes" => {
+                self.ingest.search_cache_ttl_minutes = Some(
+                    value
+                        .parse::<i64>()
+                        .ok()
+                        .filter(|n| *n >= 0)
+                        .ok_or_else(|| {
+                            invalid("expected 0 or a positive whole number".to_string())
+                        })?,
+                )
+            }
+            "ingest.sort_blocks" => {
+                self.ingest.sort_blocks = Some(BlockSort::from_str(value, true).map_err(invalid)?)
+            }
+            "ingest.consecutive_old_blocks_abort_threshold" => {
+                self.ingest.consecutive_old_blocks_abort_threshold = Some(
+                    value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| invalid("expected a positive whole number".to_string()))?,
+                )
+            }
+            "ingest.traversal_strategy" => {
+                self.ingest.traversal_strategy =
+                    Some(TraversalStrategy::from_str(value, true).map_err(invalid)?)
+            }
+            "ingest.max_blocks_per_page" => {
+                self.ingest.max_blocks_per_page = Some(
+                    value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| invalid("expected a positive whole number".to_string()))?,
+                )
+            }
+            "ingest.track_access" => {
+                self.ingest.track_access = Some(
+                    value
+                        .parse::<bool>()
+                        .map_err(|_| invalid("expected true or false".to_string()))?,
+                )
+            }
+            "output.encoding" => {
+                self.output.encoding = Some(OutputEncoding::from_str(value, true).map_err(invalid)?)
+            }
+            "output.webhook_url" => self.output.webhook_url = Some(value.to_string()),
+            "output.webhook_secret" => self.output.webhook_secret = Some(value.to_string()),
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Merges the settings from each source, with later sources taking precedence over earlier
+    /// ones, and records which source each setting's value came from. Fails if a winning value
+    /// isn't valid, e.g. a `Config` built by hand with `days` set to 0.
+    pub fn merge(
+        layers: &[(ConfigSource, &Config)],
+    ) -> Result<(Config, Vec<(&'static str, ConfigSource)>), ConfigError> {
+        let mut merged = Config::default();
+        let mut sources = Vec::new();
+        for key in CONFIG_KEYS {
+            let winner = layers.iter().rev().find_map(|(source, config)| {
+                let value = config.get(key).expect("CONFIG_KEYS are all known keys")?;
+                Some((*source, value))
+            });
+            if let Some((source, value)) = winner {
+                merged.set(key, &value)?;
+                sources.push((*key, source));
+            }
+        }
+        Ok((merged, sources))
+    }
+
+    /// Renders every setting as `key = value  # source`, hiding secrets, for `dross config show`
+    #[must_use]
+    pub fn to_annotated_string(&self, sources: &[(&'static str, ConfigSource)]) -> String {
+        let mut lines = Vec::new();
+        for key in CONFIG_KEYS {
+            let value = self.get(key).expect("CONFIG_KEYS are all known keys");
+            let source = sources.iter().find(|(k, _)| k == key).map(|(_, s)| *s);
+            lines.push(match (value, source) {
+                (Some(_), Some(source)) if SECRET_KEYS.contains(key) => {
+                    format!("{} = \"********\"  # {}", key, source)
+                }
+                (Some(value), Some(source)) => format!("{} = {:?}  # {}", key, value, source),
+                _ => format!("# {} is not set, using the default", key),
+            });
+        }
+        lines.join("\n")
+    }
+}
+
+/// The environment variable that overrides `key`, e.g. `DROSS_INGEST_DAYS` for `ingest.days`
+#[must_use]
+pub fn env_var_for_key(key: &str) -> String {
+    format!("DROSS_{}", key.replace('.', "_").to_uppercase())
+}
+
+fn value_enum_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .expect("no variants are skipped")
+        .get_name()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_get_round_trip() {
+        let mut config = Config::default();
+        config.set("ingest.days", "14").unwrap();
+        config.set("ingest.sort_blocks", "alpha").unwrap();
+        config.set("output.encoding", "utf-16").unwrap();
+
+        assert_eq!(config.get("ingest.days").unwrap().as_deref(), Some("14"));
+        assert_eq!(
+            config.get("ingest.sort_blocks").unwrap().as_deref(),
+            Some("alpha")
+        );
+        assert_eq!(
+            config.get("output.encoding").unwrap().as_deref(),
+            Some("utf16le")
+        );
+        assert_eq!(config.get("output.webhook_url").unwrap(), None);
+    }
+
+    #[test]
+    fn test_set_rejects_unknown_keys_and_invalid_values() {
+        let mut config = Config::default();
+
+        assert!(matches!(
+            config.set("notion.max_retries", "3"),
+            Err(ConfigError::UnknownKey(_))
+        ));
+        assert!(matches!(
+            config.set("ingest.days", "a week"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.set("ingest.sort_blocks", "random"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.set("ingest.search_cache_ttl_minutes", "-5"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_merge_prefers_later_sources() {
+        let mut file = Config::default();
+        file.set("ingest.days", "7").unwrap();
+        file.set("output.webhook_url", "https://example.com/hook")
+            .unwrap();
+        let mut cli = Config::default();
+        cli.set("ingest.days", "3").unwrap();
+
+        let (merged, sources) = Config::merge(&[
+            (ConfigSource::File, &file),
+            (ConfigSource::Env, &Config::default()),
+            (ConfigSource::Cli, &cli),
+        ])
+        .unwrap();
+
+        assert_eq!(merged.ingest.days, Some(3));
+        assert_eq!(
+            merged.output.webhook_url.as_deref(),
+            Some("https://example.com/hook")
+        );
+        assert_eq!(
+            sources,
+            vec![
+                ("ingest.days", ConfigSource::Cli),
+                ("output.webhook_url", ConfigSource::File),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("dross-config-{}.toml", std::process::id()));
+        let mut config = Config::default();
+        config.set("ingest.page_map_ttl_hours", "48").unwrap();
+        config.set("output.encoding", "utf8").unwrap();
+
+        config.save(&path).unwrap();
+        let loaded = Config::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, config);
+    }
+
+    #[test]
+    fn test_merge_rejects_invalid_values() {
+        let mut cli = Config::default();
+        cli.ingest.days = Some(0);
+
+        assert!(matches!(
+            Config::merge(&[(ConfigSource::Cli, &cli)]),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_rejects_invalid_values() {
+        let path =
+            std::env::temp_dir().join(format!("dross-invalid-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "[ingest]\ndays = 0\n").unwrap();
+
+        let loaded = Config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(loaded, Err(ConfigError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn test_env_var_for_key() {
+        assert_eq!(
+            env_var_for_key("ingest.page_map_ttl_hours"),
+            "DROSS_INGEST_PAGE_MAP_TTL_HOURS"
+        );
+    }
+}
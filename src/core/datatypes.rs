@@ -1,12 +1,56 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
+use dendron::{Node, Tree};
 use notion_client::objects::block::{Block as NotionBlock, BlockType};
 use notion_client::objects::parent::Parent;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    cell::Ref,
+    collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::OnceLock,
+};
+
+use super::{
+    error::DrossError,
+    helpers::{
+        last_section_start, markdown_to_slack_mrkdwn, strip_markdown, truncate_for_log,
+        ContextWindow, PagePriority,
+    },
+    markdown::BlockTypeRegistry,
+    performance::PerformanceReport,
+};
+
+pub type PageID = String;
+pub type BlockID = String;
+
+/// The most characters Notion accepts in the content of a single rich text run
+/// https://developers.notion.com/reference/request-limits#limits-for-property-values
+const MAX_RICH_TEXT_CHARS: usize = 2000;
+
+/// `text` as the rich text of a request that creates Blocks: unformatted runs of at most
+/// `MAX_RICH_TEXT_CHARS` characters each, since Notion rejects longer ones
+pub(crate) fn rich_text_runs(text: &str) -> Vec<serde_json::Value> {
+    text.chars()
+        .collect::<Vec<_>>()
+        .chunks(MAX_RICH_TEXT_CHARS)
+        .map(|chunk| {
+            serde_json::json!({
+                "type": "text",
+                "text": {"content": chunk.iter().collect::<String>()},
+            })
+        })
+        .collect()
+}
+
+/// The longest `Page::preview` can be, so it fits on one line next to the Page's title
+pub const PAGE_PREVIEW_MAX_CHARS: usize = 100;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
-    pub id: String,
-    pub page_id: String,
+    pub id: BlockID,
+    pub page_id: PageID,
     pub block_type: BlockType,
     pub text: String,
     pub creation_date: DateTime<Utc>,
@@ -18,6 +62,17 @@ pub struct Block {
 impl Block {
     #[must_use]
     pub fn from_notion_block(notion_block: NotionBlock, page_id: String) -> Self {
+        let text = notion_block
+            .block_type
+            // TODO: notion-client mushes all of the text of certain BlockTypes (NumberedListItem, BulletListItem, Toggle, ToDo,
+            // maybe some others) into a single Vec<Option<String>>, which is not great. When there's a need we should go back here
+            // and do our own, more markdown-friendly way of extractin text for the different BlockTypes
+            .plain_text()
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect::<Vec<String>>()
+            .join(join_separator(&notion_block.block_type));
+
         Block {
             id: notion_block.id.unwrap_or_default(),
             // TODO: consider removing this, since it is stored multiple times
@@ -26,16 +81,11 @@ impl Block {
             page_id,
             block_type: notion_block.block_type.clone(),
             // this is where the actual Block data is
-            text: notion_block
-                .block_type
-                // TODO: notion-client mushes all of the text of certain BlockTypes (NumberedListItem, BulletListItem, Toggle, ToDo,
-                // maybe some others) into a single Vec<Option<String>>, which is not great. When there's a need we should go back here
-                // and do our own, more markdown-friendly way of extractin text for the different BlockTypes
-                .plain_text()
-                .into_iter()
-                .map(Option::unwrap_or_default)
-                .collect::<Vec<String>>()
-                .join(" "), // TODO: a space " " separator is not always appropriate, but works for now. Find a better way to join the text
+            text: match notion_block.block_type {
+                // whitespace is meaningful in code, so leave it as-is
+                BlockType::Code { .. } => text,
+                _ => normalize_text(text),
+            },
             creation_date: notion_block.created_time.unwrap_or_default(),
             update_date: notion_block.last_edited_time.unwrap_or_default(),
             parent_block_id: notion_block.parent.and_then(|parent| match parent {
@@ -46,79 +96,1453 @@ impl Block {
         }
     }
 
+    /// Parses a single block object in the shape the Notion API returns it. Block objects
+    /// don't say which Page they're on, so `page_id` is only filled in for Blocks that sit
+    /// directly on their Page, and is empty otherwise.
+    pub fn from_notion_json(raw: &str) -> Result<Self, DrossError> {
+        let notion_block: NotionBlock = serde_json::from_str(raw)?;
+        let page_id = match &notion_block.parent {
+            Some(Parent::PageId { page_id }) => page_id.clone(),
+            _ => String::new(),
+        };
+        Ok(Block::from_notion_block(notion_block, page_id))
+    }
+
+    /// The Block in dross's own JSON shape, the inverse of `from_json`
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Block always serializes")
+    }
+
+    /// The Block as the Notion API expects it in a request that creates Blocks, e.g.
+    /// `PATCH /blocks/{block_id}/children`, which is roughly the inverse of `from_notion_block`.
+    /// Only `text` is sent, as a single unformatted run split to fit Notion's limit per run, so
+    /// formatting and links are lost. Paragraphs, headings, list items, to-dos, and code keep
+    /// their type; every other type is sent as a paragraph of its text.
+    #[must_use]
+    pub fn to_notion_api_block(&self) -> serde_json::Value {
+        let rich_text = rich_text_runs(&self.text);
+
+        let type_name = self.type_name();
+        let type_value = match type_name.as_str() {
+            "paragraph" | "heading_1" | "heading_2" | "heading_3" | "bulleted_list_item"
+            | "numbered_list_item" => serde_json::json!({"rich_text": rich_text}),
+            "to_do" => {
+                let checked = self.notion_type_field("checked").and_then(|v| v.as_bool());
+                serde_json::json!({"rich_text": rich_text, "checked": checked.unwrap_or(false)})
+            }
+            "code" => {
+                let language = self.notion_type_field("language");
+                serde_json::json!({
+                    "rich_text": rich_text,
+                    "language": language.unwrap_or_else(|| "plain text".into()),
+                })
+            }
+            _ => {
+                return serde_json::json!({
+                    "object": "block",
+                    "type": "paragraph",
+                    "paragraph": {"rich_text": rich_text},
+                })
+            }
+        };
+        serde_json::json!({
+            "object": "block",
+            "type": type_name,
+            type_name.as_str(): type_value,
+        })
+    }
+
+    /// A field of the serialized `block_type`'s inner object, e.g. a code Block's `language`
+    fn notion_type_field(&self, field: &str) -> Option<serde_json::Value> {
+        let value = serde_json::to_value(&self.block_type).ok()?;
+        let type_name = value.get("type")?.as_str()?;
+        value.get(type_name)?.get(field).cloned()
+    }
+
+    /// Parses a Block from dross's own JSON shape, as written by `to_json`. Use
+    /// `from_notion_json` for Blocks straight from the Notion API.
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, serde_json::Error> {
+        Block::deserialize(value)
+    }
+
     #[must_use]
     pub fn to_markdown(&self) -> String {
-        match &self.block_type {
-            BlockType::Heading1 { heading_1: _ } => format!("# {}", self.text),
-            BlockType::Heading2 { heading_2: _ } => format!("## {}", self.text),
-            BlockType::Heading3 { heading_3: _ } => format!("### {}", self.text),
-            BlockType::BulletedListItem {
-                bulleted_list_item: _,
-            } => format!("- {}", self.text),
-            BlockType::NumberedListItem {
-                numbered_list_item: _,
-            } => format!("1. {}", self.text),
-            BlockType::ToDo { to_do: _ } => format!("- [ ] {}", self.text),
-            BlockType::Toggle { toggle: _ } => format!("> {}", self.text),
-            _ => format!("{}", self.text),
-        }
+        self.to_markdown_with_number(1)
+    }
+
+    /// Same as `to_markdown`, except a `NumberedListItem` is prefixed with `number` instead of
+    /// always being `1.`. Callers track `number` across runs of sibling `NumberedListItem`s.
+    #[must_use]
+    pub fn to_markdown_with_number(&self, number: usize) -> String {
+        BlockTypeRegistry::global().format(self, number)
+    }
+
+    /// Same as `to_markdown`, except with the markdown formatting stripped (see
+    /// `strip_markdown`), for places that can't render markdown
+    #[must_use]
+    pub fn to_plain_text(&self) -> String {
+        strip_markdown(&self.to_markdown())
+    }
+
+    /// Same as `to_markdown`, except in Slack's "mrkdwn" formatting (see
+    /// `markdown_to_slack_mrkdwn`), for posting to Slack
+    #[must_use]
+    pub fn to_slack_mrkdwn(&self) -> String {
+        markdown_to_slack_mrkdwn(&self.to_markdown())
     }
 
+    #[inline]
+    #[must_use]
+    pub fn is_numbered_list_item(&self) -> bool {
+        matches!(self.block_type, BlockType::NumberedListItem { .. })
+    }
+
+    /// Whether the Block only exists to lay out other Blocks or the Page, and has no
+    /// meaningful text of its own. These are:
+    ///
+    /// - `Divider`, a horizontal line
+    /// - `TableOfContents` and `Breadcrumb`, which Notion fills in from the rest of the workspace
+    /// - `ColumnList` and `Column`, which only hold the Blocks laid out side by side in them
+    /// - `Template`, a button that inserts copies of its children
+    #[must_use]
+    pub fn is_structural(&self) -> bool {
+        matches!(
+            self.block_type,
+            BlockType::Divider { .. }
+                | BlockType::TableOfContents { .. }
+                | BlockType::Breadcrumb { .. }
+                | BlockType::ColumnList { .. }
+                | BlockType::Column { .. }
+                | BlockType::Template { .. }
+        )
+    }
+
+    /// Whether the Block has nothing worth outputting, either because it has no text or
+    /// because it's structural (see `is_structural`)
     #[inline]
     #[must_use]
     pub fn is_empty(&self) -> bool {
-        self.text.is_empty()
+        self.is_structural() || self.text.is_empty()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn word_count(&self) -> usize {
+        self.text.split_whitespace().count()
+    }
+
+    /// The Notion API's name for this Block's type, e.g. `"numbered_list_item"`
+    #[must_use]
+    pub fn type_name(&self) -> String {
+        serde_json::to_value(&self.block_type)
+            .ok()
+            .and_then(|value| value.get("type")?.as_str().map(str::to_string))
+            .unwrap_or_else(|| "unsupported".to_string())
+    }
+
+    /// A hash of what the Block says rather than when it was edited: its type, text, and
+    /// whether it has children. Two runs that see the same content get the same hash, so a
+    /// Block whose `update_date` moved but whose hash didn't wasn't really edited. It's a
+    /// SHA-256 digest as 64 hex characters, so it's the same across builds of dross and can be
+    /// stored between runs.
+    #[must_use]
+    pub fn content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        // each field is prefixed with its length, so text can't run over into the next field
+        for field in [self.type_name().as_bytes(), self.text.as_bytes()] {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field);
+        }
+        hasher.update([u8::from(self.has_children)]);
+        hex::encode(hasher.finalize())
+    }
+
+    /// Whether `other` is the same Block with the same content and place in its Page,
+    /// ignoring its `creation_date` and `update_date`. Types are compared by name only, so a
+    /// difference in their inner data (e.g. rich text annotations) doesn't count.
+    #[must_use]
+    pub fn structurally_equal(&self, other: &Block) -> bool {
+        self.id == other.id
+            && self.text == other.text
+            && self.type_name() == other.type_name()
+            && self.has_children == other.has_children
+            && self.parent_block_id == other.parent_block_id
+    }
+
+    /// The title of the Page this Block is on, looked up in `page_map`, which maps Page IDs
+    /// to titles like `PageMap::titles` does
+    #[must_use]
+    pub fn page_title<'a>(&self, page_map: &'a HashMap<PageID, String>) -> Option<&'a String> {
+        page_map.get(&self.page_id)
+    }
+
+    /// The URL of the Page this Block is on, looked up in `page_id_to_url`
+    #[must_use]
+    pub fn page_url<'a>(&self, page_id_to_url: &'a HashMap<PageID, String>) -> Option<&'a String> {
+        page_id_to_url.get(&self.page_id)
+    }
+
+    /// All of the URLs linked to from this Block: the `href`s of its rich text, and the
+    /// URL of a bookmark. `text` only holds the plain text, so we get at the rich text
+    /// through the serialized `block_type`, without descending into nested children.
+    #[must_use]
+    pub fn urls(&self) -> Vec<String> {
+        let mut urls = Vec::new();
+        if let BlockType::Bookmark { bookmark } = &self.block_type {
+            urls.push(bookmark.url.clone());
+        }
+        if let Ok(block_type) = serde_json::to_value(&self.block_type) {
+            collect_hrefs(&block_type, &mut urls);
+        }
+        urls
+    }
+
+    #[must_use]
+    pub fn contains_url(&self) -> bool {
+        !self.urls().is_empty()
+    }
+}
+
+fn collect_hrefs(value: &serde_json::Value, urls: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, v) in map {
+                match (key.as_str(), v.as_str()) {
+                    ("children", _) => {}
+                    ("href", Some(url)) => {
+                        // consecutive rich text segments of the same link share its href
+                        if !urls.iter().any(|existing| existing == url) {
+                            urls.push(url.to_string());
+                        }
+                    }
+                    _ => collect_hrefs(v, urls),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect_hrefs(item, urls);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A tree of `Block`s, where each `Block`'s children are the `Block`s nested under it in Notion.
+///
+/// This wraps dendron so the rest of the codebase doesn't need to know its API. It holds on to
+/// the root `Node` rather than the `Tree` so that `root_block` can hand out a borrow of it.
+#[derive(Debug, Clone)]
+pub struct BlockTree(Node<Block>);
+
+impl BlockTree {
+    /// Creates a tree containing only `root`
+    #[must_use]
+    pub fn new(root: Block) -> Self {
+        BlockTree(Node::new_tree(root))
+    }
+
+    #[must_use]
+    pub fn root(&self) -> Node<Block> {
+        self.0.clone()
+    }
+
+    #[must_use]
+    pub fn root_block(&self) -> Ref<'_, Block> {
+        self.0.borrow_data()
+    }
+
+    /// The number of levels of content in the tree, so a tree with only a root has a depth of 1.
+    /// Structural Blocks (see `Block::is_structural`) don't add a level, so a paragraph in a
+    /// column in a column list is only one level deep.
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        fn content_depth(node: &Node<Block>) -> usize {
+            let own_level = usize::from(!node.borrow_data().is_structural());
+            own_level
+                + node
+                    .children()
+                    .map(|child| content_depth(&child))
+                    .max()
+                    .unwrap_or_default()
+        }
+        content_depth(&self.0)
+    }
+
+    #[must_use]
+    pub fn leaf_count(&self) -> usize {
+        self.nodes_with_depth()
+            .iter()
+            .filter(|(node, _)| node.first_child().is_none())
+            .count()
+    }
+
+    #[must_use]
+    pub fn contains_block_id(&self, id: &BlockID) -> bool {
+        self.nodes_with_depth()
+            .iter()
+            .any(|(node, _)| &node.borrow_data().id == id)
+    }
+
+    /// All of the tree's `Block`s in depth-first order, i.e. the order they appear on the Page
+    #[must_use]
+    pub fn flatten(&self) -> Vec<Block> {
+        self.nodes_with_depth()
+            .iter()
+            .map(|(node, _)| node.borrow_data().clone())
+            .collect()
+    }
+
+    #[must_use]
+    pub fn word_count(&self) -> usize {
+        self.nodes_with_depth()
+            .iter()
+            .map(|(node, _)| node.borrow_data().word_count())
+            .sum()
+    }
+
+    /// How many `Block`s are in the tree, counting the root
+    #[must_use]
+    pub fn block_count(&self) -> usize {
+        self.nodes_with_depth().len()
+    }
+
+    /// Like `flatten`, but pairs each `Block` with its depth below the root, which is at depth 0
+    #[must_use]
+    pub fn flatten_with_depth(&self) -> Vec<(Block, usize)> {
+        self.nodes_with_depth()
+            .iter()
+            .map(|(node, depth)| (node.borrow_data().clone(), *depth))
+            .collect()
+    }
+
+    /// Every node in depth-first (pre-)order, along with its depth below the root
+    fn nodes_with_depth(&self) -> Vec<(Node<Block>, usize)> {
+        let mut nodes = Vec::new();
+        let mut stack = vec![(self.root(), 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let children = node.children().collect::<Vec<_>>();
+            stack.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+            nodes.push((node, depth));
+        }
+        nodes
+    }
+}
+
+impl From<Node<Block>> for BlockTree {
+    fn from(root: Node<Block>) -> Self {
+        BlockTree(root)
+    }
+}
+
+impl From<Tree<Block>> for BlockTree {
+    fn from(tree: Tree<Block>) -> Self {
+        BlockTree(tree.root())
     }
 }
 
+#[cfg(test)]
+impl Block {
+    /// A paragraph `Block` with the given text and a unique, UUID-shaped ID, for tests that
+    /// only care about a `Block`'s text. Override any other fields with struct update syntax:
+    /// `Block { has_children: true, ..Block::from_text("parent") }`.
+    pub(crate) fn from_text(text: &str) -> Self {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let now = Utc::now();
+        Block {
+            id: format!(
+                "00000000-0000-4000-8000-{:012x}",
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ),
+            page_id: "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb".to_string(),
+            block_type: BlockType::Paragraph {
+                paragraph: Default::default(),
+            },
+            text: text.to_string(),
+            creation_date: now,
+            update_date: now,
+            parent_block_id: None,
+            has_children: false,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Page {
+    /// A Page with the given ID and title and no `Block`s, created and edited just now by
+    /// `FAKE_USER_ID`, for tests. Override any other fields with struct update syntax:
+    /// `Page { parent_page_id: Some(parent_id), ..Page::for_test("child", "Child") }`.
+    pub(crate) fn for_test(id: &str, title: &str) -> Self {
+        let now = Utc::now();
+        Page {
+            id: id.to_string(),
+            title: title.to_string(),
+            url: format!("https://www.notion.so/{}", id.replace('-', "")),
+            creation_date: now,
+            update_date: now,
+            child_blocks: Vec::new(),
+            created_by_id: crate::notion::fake::FAKE_USER_ID.to_string(),
+            parent_database_id: None,
+            parent_page_id: None,
+        }
+    }
+}
+
+/// Builds `BlockTree`s for tests without going through dendron or spelling out every `Block`
+/// field: `BlockTreeBuilder::new("root").child(BlockTreeBuilder::new("child")).build()`.
+/// Blocks are paragraphs with unique IDs unless told otherwise.
+#[cfg(test)]
+pub(crate) struct BlockTreeBuilder {
+    block: Block,
+    children: Vec<BlockTreeBuilder>,
+}
+
+#[cfg(test)]
+impl BlockTreeBuilder {
+    pub(crate) fn new(text: &str) -> Self {
+        BlockTreeBuilder {
+            block: Block::from_text(text),
+            children: Vec::new(),
+        }
+    }
+
+    /// A `NumberedListItem`
+    pub(crate) fn numbered(text: &str) -> Self {
+        BlockTreeBuilder::new(text).block_type(BlockType::NumberedListItem {
+            numbered_list_item: Default::default(),
+        })
+    }
+
+    pub(crate) fn id(mut self, id: &str) -> Self {
+        self.block.id = id.to_string();
+        self
+    }
+
+    pub(crate) fn block_type(mut self, block_type: BlockType) -> Self {
+        self.block.block_type = block_type;
+        self
+    }
+
+    /// Sets both the creation and update dates
+    pub(crate) fn created_at(mut self, date: DateTime<Utc>) -> Self {
+        self.block.creation_date = date;
+        self.block.update_date = date;
+        self
+    }
+
+    pub(crate) fn updated_at(mut self, date: DateTime<Utc>) -> Self {
+        self.block.update_date = date;
+        self
+    }
+
+    pub(crate) fn child(mut self, child: BlockTreeBuilder) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub(crate) fn build(self) -> BlockTree {
+        let BlockTreeBuilder {
+            mut block,
+            children,
+        } = self;
+        block.has_children = !children.is_empty();
+        let root = Node::new_tree(block);
+        let grant = root.tree().grant_hierarchy_edit().unwrap();
+
+        let mut stack = vec![(root.clone(), children)];
+        while let Some((parent, children)) = stack.pop() {
+            for BlockTreeBuilder {
+                mut block,
+                children,
+            } in children
+            {
+                block.parent_block_id = Some(parent.borrow_data().id.clone());
+                block.has_children = !children.is_empty();
+                let child = parent.create_as_last_child(&grant, block);
+                stack.push((child, children));
+            }
+        }
+
+        BlockTree::from(root)
+    }
+}
+
+/// Wraps a `Block` so that it is hashed and compared only by its ID.
+///
+/// The same Block fetched twice can come back with different `update_date`s (clock skew,
+/// or Notion touching it between requests), and we still want to treat those as duplicates.
+#[derive(Debug, Clone)]
+pub struct BlockById(pub Block);
+
+impl PartialEq for BlockById {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.id == other.0.id
+    }
+}
+
+impl Eq for BlockById {}
+
+impl Hash for BlockById {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.id.hash(state);
+    }
+}
+
+/// Wraps a `Block` so that it is hashed and compared with `Block::structurally_equal`.
+///
+/// Unlike `BlockById`, the same Block is only a duplicate if its content and parent haven't
+/// changed either, while Notion's spurious timestamp updates are still ignored.
+#[derive(Debug, Clone)]
+pub struct StructuralDeduplicate(pub Block);
+
+impl PartialEq for StructuralDeduplicate {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.structurally_equal(&other.0)
+    }
+}
+
+impl Eq for StructuralDeduplicate {}
+
+impl Hash for StructuralDeduplicate {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.id.hash(state);
+        self.0.text.hash(state);
+        self.0.type_name().hash(state);
+        self.0.has_children.hash(state);
+        self.0.parent_block_id.hash(state);
+    }
+}
+
+/// The separator to join the plain text segments of a `BlockType` with.
+///
+/// A paragraph's rich text segments are contiguous runs of differently formatted text, so
+/// they're joined with nothing, code is joined line by line, and everything else with a space.
+#[must_use]
+pub fn join_separator(block_type: &BlockType) -> &'static str {
+    match block_type {
+        BlockType::Paragraph { .. } => "",
+        BlockType::Code { .. } => "\n",
+        _ => " ",
+    }
+}
+
+/// Cleans up the inconsistent whitespace Notion returns in block text: leading and trailing
+/// whitespace is trimmed, non-breaking spaces (U+00A0) become regular spaces, and runs of
+/// whitespace are collapsed into a single space.
+#[must_use]
+pub fn normalize_text(s: String) -> String {
+    s.replace('\u{a0}', " ")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Page {
-    pub id: String,
+    pub id: PageID,
     pub title: String,
     pub url: String,
     pub creation_date: DateTime<Utc>,
     pub update_date: DateTime<Utc>,
+    /// The Page's top-level `Block`s, in the order they appear on the Page from top to bottom.
+    /// They're fetched with `Notion::retrieve_all_block_children`, which keeps the API's order,
+    /// and the API returns children in the order they're laid out.
     pub child_blocks: Vec<Block>,
+    /// The ID of the Notion user who created the Page
+    pub created_by_id: String,
+    /// Set when the Page is a row in a Notion database
+    pub parent_database_id: Option<String>,
+    /// Set when the Page is nested directly inside another Page
+    pub parent_page_id: Option<PageID>,
+}
+
+impl Page {
+    /// Extracts the Page ID from a Notion Page URL, either
+    /// `https://www.notion.so/Workspace-Page-Title-{32 hex chars}` or
+    /// `https://www.notion.so/{32 hex chars}`, and formats it as a dashed UUID the way the API
+    /// does. Query strings and fragments (e.g. a Block anchor) are ignored.
+    #[must_use]
+    pub fn url_to_id(url: &str) -> Option<PageID> {
+        static TRAILING_ID: OnceLock<Regex> = OnceLock::new();
+        let trailing_id = TRAILING_ID.get_or_init(|| {
+            Regex::new(r"(?i)(?:^|-)([0-9a-f]{32})$").expect("the Page ID regex is valid")
+        });
+
+        let path = url.split(['?', '#']).next()?;
+        let last_segment = path.trim_end_matches('/').rsplit('/').next()?;
+        let hex = trailing_id.captures(last_segment)?[1].to_lowercase();
+        Some(format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        ))
+    }
+
+    /// The Page's top-level `Block`s from top to bottom, the order they're shown in Notion.
+    /// Prefer this over reaching into `child_blocks` when the order matters, so that code
+    /// relying on the order is easy to find.
+    pub fn blocks_in_order(&self) -> impl Iterator<Item = &Block> {
+        self.child_blocks.iter()
+    }
+
+    /// The IDs of the Pages nested directly inside this one. A child page `Block` has the same
+    /// ID as the Page it contains, so these can be passed straight to `Notion::get_page_by_id`.
+    #[must_use]
+    pub fn child_page_ids(&self) -> Vec<PageID> {
+        self.blocks_in_order()
+            .filter(|block| matches!(block.block_type, BlockType::ChildPage { .. }))
+            .map(|block| block.id.clone())
+            .collect()
+    }
+
+    /// The IDs of the databases embedded directly in this Page. Like child pages, a child
+    /// database `Block` has the same ID as the database it contains.
+    #[must_use]
+    pub fn child_database_ids(&self) -> Vec<String> {
+        self.blocks_in_order()
+            .filter(|block| matches!(block.block_type, BlockType::ChildDatabase { .. }))
+            .map(|block| block.id.clone())
+            .collect()
+    }
+
+    /// The total word count of the Page's top-level `Block`s
+    #[must_use]
+    pub fn word_count(&self) -> usize {
+        self.child_blocks.iter().map(Block::word_count).sum()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn block_count(&self) -> usize {
+        self.child_blocks.len()
+    }
+
+    /// Whether none of the Page's top-level `Block`s have any text
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.child_blocks.iter().all(Block::is_empty)
+    }
+
+    /// A one-line glimpse of the Page: the text of its first `max_blocks` non-empty top-level
+    /// `Block`s, separated by ` | ` and cut off at `PAGE_PREVIEW_MAX_CHARS`
+    #[must_use]
+    pub fn preview(&self, max_blocks: usize) -> String {
+        let preview = self
+            .blocks_in_order()
+            .filter(|block| !block.is_empty())
+            .take(max_blocks)
+            .map(|block| block.text.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        if preview.chars().count() > PAGE_PREVIEW_MAX_CHARS {
+            // leave room for the "…" marking the cut
+            truncate_for_log(&preview, PAGE_PREVIEW_MAX_CHARS - 1)
+        } else {
+            preview
+        }
+    }
+
+    /// How much valuable content the Page has, as a weighted sum of its word, `Block`,
+    /// heading, and link counts. Used to decide which Pages to keep when the output is too long.
+    #[must_use]
+    pub fn richness_score(&self) -> f64 {
+        let heading_count = self
+            .child_blocks
+            .iter()
+            .filter(|block| {
+                matches!(
+                    block.block_type,
+                    BlockType::Heading1 { .. }
+                        | BlockType::Heading2 { .. }
+                        | BlockType::Heading3 { .. }
+                )
+            })
+            .count();
+        let link_count = self
+            .child_blocks
+            .iter()
+            .map(|block| block.urls().len())
+            .sum::<usize>();
+
+        0.4 * self.word_count() as f64
+            + 0.3 * self.block_count() as f64
+            + 0.2 * heading_count as f64
+            + 0.1 * link_count as f64
+    }
+}
+
+/// The output of ingesting a single `Page`
+#[derive(Debug, Clone)]
+pub struct PageOutput {
+    pub page: Page,
+    pub markdown: String,
+    /// How many `Block`s went into `markdown`
+    pub block_count: usize,
+    /// The Page's property values, if it is a database row and they were requested
+    pub properties: Option<HashMap<String, PropertyValue>>,
+    /// The title of the Page this one is grouped under, when Pages are grouped by parent
+    pub parent_title: Option<String>,
+}
+
+impl PageOutput {
+    #[must_use]
+    pub fn to_markdown(&self) -> String {
+        let frontmatter = self
+            .properties
+            .as_ref()
+            .map(properties_to_frontmatter)
+            .unwrap_or_default();
+        format!(
+            "{}Page Title: {}\n{}",
+            frontmatter, self.page.title, self.markdown
+        )
+    }
+
+    /// The Page's markdown under YAML frontmatter for static site generators like Hugo and
+    /// Jekyll: its title, creation date, Notion URL, a `notion-import` tag, and its properties
+    /// if it has them. `extra` fields come last, written as strings, and replace any of those
+    /// with the same key.
+    #[must_use]
+    pub fn to_frontmatter_markdown(&self, extra: &[(String, String)]) -> String {
+        let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+        let mut fields = vec![
+            ("title".to_string(), quote(&self.page.title)),
+            (
+                "date".to_string(),
+                self.page
+                    .creation_date
+                    .to_rfc3339_opts(SecondsFormat::Secs, true),
+            ),
+            ("notion_url".to_string(), quote(&self.page.url)),
+            ("tags".to_string(), format!("[{}]", FRONTMATTER_TAG)),
+        ];
+        if let Some(properties) = &self.properties {
+            let mut keys = properties.keys().collect::<Vec<&String>>();
+            keys.sort();
+            fields.extend(
+                keys.into_iter()
+                    .map(|key| (key.clone(), properties[key].to_yaml())),
+            );
+        }
+        for (key, value) in extra {
+            match fields.iter_mut().find(|(existing, _)| existing == key) {
+                Some(field) => field.1 = quote(value),
+                None => fields.push((key.clone(), quote(value))),
+            }
+        }
+
+        let mut frontmatter = String::from("---\n");
+        for (key, value) in fields {
+            frontmatter.push_str(&format!("{}: {}\n", yaml_key(&key), value));
+        }
+        frontmatter.push_str("---\n");
+
+        frontmatter + &self.markdown
+    }
+}
+
+/// The tag `PageOutput::to_frontmatter_markdown` gives every Page, so imported Pages can be
+/// told apart from the rest of a site's content
+const FRONTMATTER_TAG: &str = "notion-import";
+
+/// Writes `key` bare if it's a plain identifier, and quoted otherwise so it's still valid YAML
+fn yaml_key(key: &str) -> String {
+    let is_plain = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if is_plain {
+        key.to_string()
+    } else {
+        serde_json::to_string(key).unwrap_or_default()
+    }
+}
+
+/// Everything ingested from Notion in a single run, ready to be turned into a prompt
+#[derive(Debug, Clone)]
+pub struct PromptContext {
+    pub pages: Vec<PageOutput>,
+    pub total_blocks: usize,
+    pub total_words: usize,
+    pub generated_at: DateTime<Utc>,
+    /// How long building the context took, filled in by `ingest_notion`
+    pub performance: PerformanceReport,
+}
+
+impl PromptContext {
+    #[must_use]
+    pub fn new(pages: Vec<PageOutput>) -> Self {
+        PromptContext {
+            total_blocks: pages.iter().map(|page| page.block_count).sum(),
+            total_words: pages
+                .iter()
+                .map(|page| page.markdown.split_whitespace().count())
+                .sum(),
+            pages,
+            generated_at: Utc::now(),
+            performance: PerformanceReport::default(),
+        }
+    }
+
+    /// The indices of `pages`, highest `priority` first. Pages that tie keep their original order.
+    #[must_use]
+    pub fn priority_order(&self, priority: PagePriority) -> Vec<usize> {
+        let mut indices = (0..self.pages.len()).collect::<Vec<_>>();
+        match priority {
+            // the Pages aren't necessarily in edit order, e.g. with --page-order hierarchical
+            // or --created, so sort them rather than trusting the order Notion returned
+            PagePriority::Recency => indices.sort_by(|&a, &b| {
+                self.pages[b]
+                    .page
+                    .update_date
+                    .cmp(&self.pages[a].page.update_date)
+            }),
+            PagePriority::Richness => indices.sort_by(|&a, &b| {
+                self.pages[b]
+                    .page
+                    .richness_score()
+                    .total_cmp(&self.pages[a].page.richness_score())
+            }),
+            PagePriority::Alphabetical => {
+                indices.sort_by(|&a, &b| self.pages[a].page.title.cmp(&self.pages[b].page.title))
+            }
+        }
+        indices
+    }
+
+    /// Drops Pages, lowest `priority` first, until the total word count is at most `max_words`.
+    /// The Pages that are kept stay in their original order. Returns how many were dropped.
+    pub fn truncate_to_words(&mut self, max_words: usize, priority: PagePriority) -> usize {
+        let word_counts = self
+            .pages
+            .iter()
+            .map(|output| output.markdown.split_whitespace().count())
+            .collect::<Vec<_>>();
+        let indices = self.priority_order(priority);
+
+        let mut total_words = word_counts.iter().sum::<usize>();
+        let mut dropped = HashSet::new();
+        for &i in indices.iter().rev() {
+            if total_words <= max_words {
+                break;
+            }
+            total_words -= word_counts[i];
+            dropped.insert(i);
+        }
+
+        let pages = std::mem::take(&mut self.pages);
+        self.pages = pages
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !dropped.contains(i))
+            .map(|(_, output)| output)
+            .collect();
+        self.total_blocks = self.pages.iter().map(|page| page.block_count).sum();
+        self.total_words = total_words;
+        dropped.len()
+    }
+
+    /// Drops Pages from the end until `render`ing the context takes at most `max_bytes`, so the
+    /// output is only ever cut between Pages. Returns the dropped Pages, in their original order.
+    pub fn truncate_to_bytes(
+        &mut self,
+        max_bytes: usize,
+        render: impl Fn(&PromptContext) -> String,
+    ) -> Vec<PageOutput> {
+        self.truncate_while(|context| render(context).len() > max_bytes)
+    }
+
+    /// Same as `truncate_to_bytes`, except the limit is in characters, which is how Slack
+    /// limits the length of a message
+    pub fn truncate_to_chars(
+        &mut self,
+        max_chars: usize,
+        render: impl Fn(&PromptContext) -> String,
+    ) -> Vec<PageOutput> {
+        self.truncate_while(|context| render(context).chars().count() > max_chars)
+    }
+
+    /// Cuts Pages down, lowest `priority` first, until every Page fits in `window` as
+    /// `render_pages` renders it, one string per Page. The lowest priority Page loses its
+    /// sections (see `split_by_heading`) from the end, whole, and is dropped once only its
+    /// first section is left. Afterwards `window` holds the rendered Pages that are kept, in
+    /// their original order, and its `to_prompt` is the output. Pages are cut one section at a
+    /// time, since dropping one can change how the rest are rendered, e.g. which of them starts
+    /// a group. Returns the dropped Pages, lowest priority first.
+    pub fn truncate_to_tokens(
+        &mut self,
+        window: &mut ContextWindow,
+        priority: PagePriority,
+        render_pages: impl Fn(&PromptContext) -> Vec<String>,
+    ) -> Vec<PageOutput> {
+        let mut dropped = Vec::new();
+        loop {
+            window.clear();
+            let rendered = render_pages(self);
+            let fits = self
+                .pages
+                .iter()
+                .zip(rendered)
+                .all(|(output, text)| window.add_page(output.page.clone(), text));
+            if fits {
+                break;
+            }
+            let lowest = self
+                .priority_order(priority)
+                .pop()
+                .expect("a window without any Pages always fits");
+            let markdown = &mut self.pages[lowest].markdown;
+            match last_section_start(markdown) {
+                Some(start) => markdown.truncate(start),
+                None => dropped.push(self.pages.remove(lowest)),
+            }
+        }
+
+        self.recount();
+        dropped
+    }
+
+    fn truncate_while(&mut self, too_long: impl Fn(&PromptContext) -> bool) -> Vec<PageOutput> {
+        let mut dropped = Vec::new();
+        while !self.pages.is_empty() && too_long(self) {
+            dropped.extend(self.pages.pop());
+        }
+        dropped.reverse();
+
+        self.recount();
+        dropped
+    }
+
+    /// Updates the totals after Pages were dropped
+    fn recount(&mut self) {
+        self.total_blocks = self.pages.iter().map(|page| page.block_count).sum();
+        self.total_words = self
+            .pages
+            .iter()
+            .map(|page| page.markdown.split_whitespace().count())
+            .sum();
+    }
+
+    /// Each Page's markdown, separated by blank lines. When Pages are grouped by parent, each
+    /// group starts with a `## Parent: <title>` heading, and the Pages without a parent that
+    /// follow a group start with a `## Pages without a parent` heading.
+    #[must_use]
+    pub fn to_markdown(&self) -> String {
+        self.to_markdown_pages().join("\n\n")
+    }
+
+    /// The Pages of `to_markdown`, one string per Page, with each group's heading in front of
+    /// the group's first Page
+    #[must_use]
+    pub fn to_markdown_pages(&self) -> Vec<String> {
+        let mut previous_parent = None;
+        self.pages
+            .iter()
+            .map(|output| {
+                let parent = output.parent_title.as_deref();
+                let heading = match (parent, previous_parent) {
+                    (Some(title), _) if parent != previous_parent => {
+                        format!("## Parent: {}\n\n", title)
+                    }
+                    // otherwise the Pages without a parent would read as part of the group
+                    // before them
+                    (None, Some(_)) => "## Pages without a parent\n\n".to_string(),
+                    _ => String::new(),
+                };
+                previous_parent = parent;
+                heading + &output.to_markdown()
+            })
+            .collect()
+    }
+
+    /// Every Page rendered with `PageOutput::to_frontmatter_markdown`, one after another
+    #[must_use]
+    pub fn to_frontmatter_markdown(&self, extra: &[(String, String)]) -> String {
+        self.to_frontmatter_markdown_pages(extra).join("\n")
+    }
+
+    /// The Pages of `to_frontmatter_markdown`, one string per Page
+    #[must_use]
+    pub fn to_frontmatter_markdown_pages(&self, extra: &[(String, String)]) -> Vec<String> {
+        self.pages
+            .iter()
+            .map(|output| output.to_frontmatter_markdown(extra))
+            .collect()
+    }
+
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "pages": self.pages.iter().map(|output| serde_json::json!({
+                "id": output.page.id,
+                "title": output.page.title,
+                "url": output.page.url,
+                "creation_date": output.page.creation_date,
+                "update_date": output.page.update_date,
+                "markdown": output.markdown,
+                "block_count": output.block_count,
+                "properties": output.properties,
+            })).collect::<Vec<_>>(),
+            "total_blocks": self.total_blocks,
+            "total_words": self.total_words,
+            "generated_at": self.generated_at,
+        })
+    }
+}
+
+/// The value of one of a database row Page's properties, simplified down to what's useful
+/// to show in a prompt. Property types we don't (yet) care about become `Unsupported`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PropertyValue {
+    Title(String),
+    RichText(String),
+    Number(Option<f64>),
+    Select(Option<String>),
+    MultiSelect(Vec<String>),
+    Status(Option<String>),
+    Date {
+        start: Option<String>,
+        end: Option<String>,
+    },
+    Checkbox(bool),
+    Url(Option<String>),
+    Email(Option<String>),
+    PhoneNumber(Option<String>),
+    /// The IDs of the related Pages
+    Relation(Vec<PageID>),
+    /// The names (or IDs, for users without a name) of the people
+    People(Vec<String>),
+    Unsupported(String),
+}
+
+impl PropertyValue {
+    /// Builds a `PropertyValue` from a page property object in the shape the Notion API
+    /// returns it, i.e. `{"type": "select", "select": {"name": "Done", ...}}`
+    #[must_use]
+    pub fn from_json(property: &serde_json::Value) -> Self {
+        let property_type = property["type"].as_str().unwrap_or_default();
+        let value = &property[property_type];
+        let string = |v: &serde_json::Value| v.as_str().map(str::to_string);
+        let rich_text = |v: &serde_json::Value| {
+            v.as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|segment| segment["plain_text"].as_str())
+                .collect::<String>()
+        };
+        let names = |v: &serde_json::Value| {
+            v.as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|item| item["name"].as_str().map(str::to_string))
+                .collect::<Vec<String>>()
+        };
+
+        match property_type {
+            "title" => PropertyValue::Title(rich_text(value)),
+            "rich_text" => PropertyValue::RichText(rich_text(value)),
+            "number" => PropertyValue::Number(value.as_f64()),
+            "select" => PropertyValue::Select(string(&value["name"])),
+            "multi_select" => PropertyValue::MultiSelect(names(value)),
+            "status" => PropertyValue::Status(string(&value["name"])),
+            "date" => PropertyValue::Date {
+                start: string(&value["start"]),
+                end: string(&value["end"]),
+            },
+            "checkbox" => PropertyValue::Checkbox(value.as_bool().unwrap_or_default()),
+            "url" => PropertyValue::Url(string(value)),
+            "email" => PropertyValue::Email(string(value)),
+            "phone_number" => PropertyValue::PhoneNumber(string(value)),
+            "relation" => PropertyValue::Relation(
+                value
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|relation| string(&relation["id"]))
+                    .collect(),
+            ),
+            "people" => PropertyValue::People(
+                value
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|person| string(&person["name"]).or(string(&person["id"])))
+                    .collect(),
+            ),
+            other => PropertyValue::Unsupported(other.to_string()),
+        }
+    }
+
+    /// Renders the value as a YAML scalar or flow sequence. Strings are emitted as JSON
+    /// strings, which are also valid YAML and save us from hand-rolling any escaping.
+    #[must_use]
+    pub fn to_yaml(&self) -> String {
+        let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+        let optional = |s: &Option<String>| s.as_deref().map_or("null".to_string(), quote);
+        let list = |items: &[String]| {
+            format!(
+                "[{}]",
+                items
+                    .iter()
+                    .map(|item| quote(item))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        };
+
+        match self {
+            PropertyValue::Title(s) | PropertyValue::RichText(s) => quote(s),
+            PropertyValue::Number(n) => n.map_or("null".to_string(), |n| n.to_string()),
+            PropertyValue::Select(s)
+            | PropertyValue::Status(s)
+            | PropertyValue::Url(s)
+            | PropertyValue::Email(s)
+            | PropertyValue::PhoneNumber(s) => optional(s),
+            PropertyValue::MultiSelect(items)
+            | PropertyValue::Relation(items)
+            | PropertyValue::People(items) => list(items),
+            PropertyValue::Date { start, end: None } => optional(start),
+            PropertyValue::Date { start, end } => {
+                format!("{{start: {}, end: {}}}", optional(start), optional(end))
+            }
+            PropertyValue::Checkbox(b) => b.to_string(),
+            PropertyValue::Unsupported(property_type) => {
+                quote(&format!("<unsupported {property_type}>"))
+            }
+        }
+    }
+}
+
+/// The type of one of a database's properties, i.e. the kind of value every row Page has
+/// for it. Types we don't (yet) care about keep their Notion name in `Other`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PropertyType {
+    Title,
+    RichText,
+    Number,
+    Select,
+    MultiSelect,
+    Status,
+    Date,
+    Checkbox,
+    Url,
+    Email,
+    PhoneNumber,
+    Relation,
+    Rollup,
+    Formula,
+    People,
+    Other(String),
+}
+
+impl PropertyType {
+    #[must_use]
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "title" => PropertyType::Title,
+            "rich_text" => PropertyType::RichText,
+            "number" => PropertyType::Number,
+            "select" => PropertyType::Select,
+            "multi_select" => PropertyType::MultiSelect,
+            "status" => PropertyType::Status,
+            "date" => PropertyType::Date,
+            "checkbox" => PropertyType::Checkbox,
+            "url" => PropertyType::Url,
+            "email" => PropertyType::Email,
+            "phone_number" => PropertyType::PhoneNumber,
+            "relation" => PropertyType::Relation,
+            "rollup" => PropertyType::Rollup,
+            "formula" => PropertyType::Formula,
+            "people" => PropertyType::People,
+            other => PropertyType::Other(other.to_string()),
+        }
+    }
+}
+
+/// The title and property types of a Notion database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseSchema {
+    pub id: String,
+    pub title: String,
+    /// Each property's name mapped to its type
+    pub properties: HashMap<String, PropertyType>,
+}
+
+impl DatabaseSchema {
+    /// Builds a `DatabaseSchema` from a database object in the shape the Notion API returns
+    /// it, i.e. `{"id": "...", "title": [...], "properties": {"Status": {"type": "select", ...}}}`
+    #[must_use]
+    pub fn from_json(database: &serde_json::Value) -> Self {
+        DatabaseSchema {
+            id: database["id"].as_str().unwrap_or_default().to_string(),
+            title: database["title"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|segment| segment["plain_text"].as_str())
+                .collect(),
+            properties: database["properties"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .map(|(name, property)| {
+                    let property_type = property["type"].as_str().unwrap_or_default();
+                    (name.clone(), PropertyType::from_name(property_type))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A member (or bot) of the Notion workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceUser {
+    pub id: String,
+    pub name: String,
+    /// Only people have an email, and only when the integration has been granted access to it
+    pub email: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+impl WorkspaceUser {
+    /// Builds a `WorkspaceUser` from a user object in the shape the Notion API returns it,
+    /// i.e. `{"object": "user", "id": "...", "type": "person", "person": {"email": "..."}, ...}`
+    #[must_use]
+    pub fn from_json(user: &serde_json::Value) -> Self {
+        let string = |v: &serde_json::Value| v.as_str().map(str::to_string);
+        WorkspaceUser {
+            id: string(&user["id"]).unwrap_or_default(),
+            name: string(&user["name"]).unwrap_or_default(),
+            email: string(&user["person"]["email"]),
+            avatar_url: string(&user["avatar_url"]),
+        }
+    }
+}
+
+/// Renders a database row's properties as a YAML frontmatter block, with keys sorted so the
+/// output is stable between runs
+#[must_use]
+pub fn properties_to_frontmatter(properties: &HashMap<String, PropertyValue>) -> String {
+    let mut keys = properties.keys().collect::<Vec<&String>>();
+    keys.sort();
+
+    let mut frontmatter = String::from("---\n");
+    for key in keys {
+        frontmatter.push_str(&format!(
+            "{}: {}\n",
+            serde_json::to_string(key).unwrap_or_default(),
+            properties[key].to_yaml()
+        ));
+    }
+    frontmatter.push_str("---\n");
+
+    frontmatter
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::TimeZone;
+    use dendron::tree_node;
     use notion_client::objects::{
-        block::{BulletedListItemValue, TextColor},
+        block::{BookmarkValue, BulletedListItemValue, ParagraphValue, TextColor},
         property::Color,
         rich_text::{RichText, Text},
     };
 
     use super::*;
 
+    #[test]
+    fn test_child_page_and_database_ids() {
+        let child = |id: &str, type_name: &str| {
+            Block::from_notion_json(
+                &serde_json::json!({
+                    "object": "block",
+                    "id": id,
+                    "parent": {"type": "page_id", "page_id": "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb"},
+                    "has_children": false,
+                    "type": type_name,
+                    type_name: {"title": "Nested"}
+                })
+                .to_string(),
+            )
+            .unwrap()
+        };
+        let page = Page {
+            child_blocks: vec![
+                child("0c1d0000-0000-4000-8000-000000000001", "child_page"),
+                Block::from_text("between them"),
+                child("0c1d0000-0000-4000-8000-000000000002", "child_database"),
+            ],
+            ..Page::for_test("7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb", "Projects")
+        };
+
+        assert_eq!(
+            page.child_page_ids(),
+            vec!["0c1d0000-0000-4000-8000-000000000001"]
+        );
+        assert_eq!(
+            page.child_database_ids(),
+            vec!["0c1d0000-0000-4000-8000-000000000002"]
+        );
+    }
+
+    #[test]
+    fn test_page_title_and_url_lookups() {
+        let block = Block::from_text("on a known Page");
+        let page_map = HashMap::from([(block.page_id.clone(), "Journal".to_string())]);
+        let page_urls = HashMap::from([(
+            block.page_id.clone(),
+            "https://www.notion.so/Journal".to_string(),
+        )]);
+
+        assert_eq!(
+            block.page_title(&page_map).map(String::as_str),
+            Some("Journal")
+        );
+        assert_eq!(
+            block.page_url(&page_urls).map(String::as_str),
+            Some("https://www.notion.so/Journal")
+        );
+        assert_eq!(block.page_title(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn test_content_hash_ignores_ids_and_timestamps() {
+        let block = Block::from_text("Remember to follow up with Dana");
+        let touched = Block {
+            id: "8d5f1c2a-0000-4000-8000-000000000001".to_string(),
+            update_date: block.update_date + chrono::Duration::hours(1),
+            ..block.clone()
+        };
+        let edited = Block {
+            text: "Remember to follow up with Dana about the invoice".to_string(),
+            ..block.clone()
+        };
+
+        assert_eq!(block.content_hash(), touched.content_hash());
+        assert_ne!(block.content_hash(), edited.content_hash());
+        assert_eq!(block.content_hash().len(), 64);
+    }
+
+    #[test]
+    fn test_structural_blocks_are_empty_and_add_no_depth() {
+        let block_type = |type_name: &str| -> BlockType {
+            serde_json::from_value(serde_json::json!({"type": type_name, type_name: {}})).unwrap()
+        };
+        let column_list = Block {
+            block_type: block_type("column_list"),
+            ..Block::from_text("")
+        };
+        let column = Block {
+            block_type: block_type("column"),
+            ..Block::from_text("")
+        };
+        let divider = Block {
+            block_type: block_type("divider"),
+            ..Block::from_text("---")
+        };
+        let paragraph = Block::from_text("side by side");
+
+        assert!(divider.is_structural());
+        assert!(divider.is_empty());
+        assert!(!paragraph.is_structural());
+
+        let tree = BlockTree::from(tree_node! {
+            column_list, [
+                /(column, [paragraph]),
+            ]
+        });
+        assert_eq!(tree.depth(), 1);
+    }
+
+    #[test]
+    fn test_block_json_round_trip() {
+        let block = Block::from_text("round trip");
+
+        let parsed = Block::from_json(&block.to_json()).unwrap();
+
+        assert_eq!(parsed.id, block.id);
+        assert_eq!(parsed.text, "round trip");
+        assert_eq!(parsed.update_date, block.update_date);
+    }
+
+    #[test]
+    fn test_block_to_notion_api_block() {
+        let blocks = crate::core::helpers::markdown_to_blocks(
+            "## Plan\n\nShip it\n\n```rust\nfn main() {}\n```\n",
+        );
+
+        assert_eq!(
+            blocks[0].to_notion_api_block(),
+            serde_json::json!({
+                "object": "block",
+                "type": "heading_2",
+                "heading_2": {"rich_text": [{"type": "text", "text": {"content": "Plan"}}]},
+            })
+        );
+        assert_eq!(
+            blocks[1].to_notion_api_block()["paragraph"]["rich_text"][0]["text"]["content"],
+            "Ship it"
+        );
+        assert_eq!(blocks[2].to_notion_api_block()["code"]["language"], "rust");
+
+        let long = Block::from_text(&"a".repeat(MAX_RICH_TEXT_CHARS + 1));
+        let rich_text = &long.to_notion_api_block()["paragraph"]["rich_text"];
+        assert_eq!(rich_text.as_array().unwrap().len(), 2);
+        assert_eq!(rich_text[1]["text"]["content"], "a");
+    }
+
+    #[test]
+    fn test_block_from_notion_json() {
+        let raw = r#"{
+            "object": "block",
+            "id": "b10c0005-0000-4000-8000-000000000005",
+            "parent": {"type": "page_id", "page_id": "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb"},
+            "created_time": "2024-08-18T10:00:00.000Z",
+            "last_edited_time": "2024-08-18T10:00:00.000Z",
+            "has_children": false,
+            "type": "paragraph",
+            "paragraph": {
+                "rich_text": [{
+                    "type": "text",
+                    "text": {"content": "First nested", "link": null},
+                    "plain_text": "First nested",
+                    "href": null
+                }],
+                "color": "default"
+            }
+        }"#;
+
+        let block = Block::from_notion_json(raw).unwrap();
+
+        assert_eq!(block.id, "b10c0005-0000-4000-8000-000000000005");
+        assert_eq!(block.page_id, "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb");
+        assert_eq!(block.text, "First nested");
+        assert!(Block::from_notion_json("{\"object\": \"block\"").is_err());
+    }
+
     #[test]
     fn test_block_to_markdown() {
         let blocks = vec![
             Block {
-                id: "1".to_string(),
                 block_type: BlockType::Heading1 {
                     heading_1: Default::default(),
                 },
-                text: "Heading 1".to_string(),
-                creation_date: Utc::now(),
-                update_date: Utc::now(),
-                parent_block_id: None,
-                has_children: false,
-                page_id: "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb".to_string(),
+                ..Block::from_text("Heading 1")
             },
             Block {
-                id: "2".to_string(),
                 block_type: BlockType::Heading2 {
                     heading_2: Default::default(),
                 },
-                text: "Heading 2".to_string(),
-                creation_date: Utc::now(),
-                update_date: Utc::now(),
-                parent_block_id: None,
-                has_children: false,
-                page_id: "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb".to_string(),
+                ..Block::from_text("Heading 2")
             },
             Block {
-                id: "3".to_string(),
                 block_type: BlockType::BulletedListItem {
                     bulleted_list_item: BulletedListItemValue {
                         rich_text: vec![RichText::Text {
@@ -134,25 +1558,9 @@ mod tests {
                         children: None,
                     },
                 },
-                text: "Bullet point".to_string(),
-                creation_date: Utc::now(),
-                update_date: Utc::now(),
-                parent_block_id: None,
-                has_children: false,
-                page_id: "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb".to_string(),
-            },
-            Block {
-                id: "4".to_string(),
-                block_type: BlockType::Paragraph {
-                    paragraph: Default::default(),
-                },
-                text: "Normal text".to_string(),
-                creation_date: Utc::now(),
-                update_date: Utc::now(),
-                parent_block_id: None,
-                has_children: false,
-                page_id: "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb".to_string(),
+                ..Block::from_text("Bullet point")
             },
+            Block::from_text("Normal text"),
         ];
 
         let expected_markdown = "# Heading 1\n## Heading 2\n- Bullet point\nNormal text";
@@ -164,4 +1572,551 @@ mod tests {
 
         assert_eq!(result_markdown, expected_markdown);
     }
+
+    #[test]
+    fn test_bookmark_to_markdown() {
+        let bookmark = |caption: &str| Block {
+            block_type: BlockType::Bookmark {
+                bookmark: BookmarkValue {
+                    caption: vec![],
+                    url: "https://beepb00p.xyz/exobrain/".to_string(),
+                },
+            },
+            ..Block::from_text(caption)
+        };
+
+        assert_eq!(
+            bookmark("Exobrain").to_markdown(),
+            "[Exobrain](https://beepb00p.xyz/exobrain/)"
+        );
+        assert_eq!(
+            bookmark("").to_markdown(),
+            "[https://beepb00p.xyz/exobrain/](https://beepb00p.xyz/exobrain/)"
+        );
+    }
+
+    #[test]
+    fn test_block_urls() {
+        let link = |text: &str, href: Option<&str>| RichText::Text {
+            plain_text: Some(text.to_string()),
+            href: href.map(str::to_string),
+            annotations: None,
+            text: Text {
+                content: text.to_string(),
+                link: None,
+            },
+        };
+        let block = Block {
+            block_type: BlockType::Paragraph {
+                paragraph: ParagraphValue {
+                    rich_text: vec![
+                        link("see ", None),
+                        link("the docs", Some("https://developers.notion.com")),
+                        link(" and ", None),
+                        link("dross", Some("https://github.com/Melvillian/dross")),
+                    ],
+                    ..Default::default()
+                },
+            },
+            ..Block::from_text("see the docs and dross")
+        };
+
+        assert!(block.contains_url());
+        assert_eq!(
+            block.urls(),
+            vec![
+                "https://developers.notion.com".to_string(),
+                "https://github.com/Melvillian/dross".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_workspace_user_from_json() {
+        let person = WorkspaceUser::from_json(&serde_json::json!({
+            "object": "user",
+            "id": "d40e767c-d7af-4b18-a86d-55c61f1e39a4",
+            "type": "person",
+            "person": { "email": "avo@example.org" },
+            "name": "Avocado Lovelace",
+            "avatar_url": "https://secure.notion-static.com/e6a352a8.jpg"
+        }));
+        assert_eq!(person.id, "d40e767c-d7af-4b18-a86d-55c61f1e39a4");
+        assert_eq!(person.name, "Avocado Lovelace");
+        assert_eq!(person.email.as_deref(), Some("avo@example.org"));
+        assert_eq!(
+            person.avatar_url.as_deref(),
+            Some("https://secure.notion-static.com/e6a352a8.jpg")
+        );
+
+        let bot = WorkspaceUser::from_json(&serde_json::json!({
+            "object": "user",
+            "id": "9a3b5ae0-c6e6-482d-b0e1-ed315ee6dc57",
+            "type": "bot",
+            "bot": {},
+            "name": "Doug Engelbot",
+            "avatar_url": null
+        }));
+        assert_eq!(bot.name, "Doug Engelbot");
+        assert_eq!(bot.email, None);
+        assert_eq!(bot.avatar_url, None);
+    }
+
+    #[test]
+    fn test_property_value_from_json() {
+        let select = serde_json::json!({"id": "a", "type": "select", "select": {"name": "Done", "color": "green"}});
+        let multi_select = serde_json::json!({"type": "multi_select", "multi_select": [{"name": "rust"}, {"name": "notion"}]});
+        let date =
+            serde_json::json!({"type": "date", "date": {"start": "2024-08-19", "end": null}});
+        let title = serde_json::json!({"type": "title", "title": [{"plain_text": "Daily "}, {"plain_text": "Notes"}]});
+        let rollup = serde_json::json!({"type": "rollup", "rollup": {}});
+
+        assert_eq!(
+            PropertyValue::from_json(&select),
+            PropertyValue::Select(Some("Done".to_string()))
+        );
+        assert_eq!(
+            PropertyValue::from_json(&multi_select),
+            PropertyValue::MultiSelect(vec!["rust".to_string(), "notion".to_string()])
+        );
+        assert_eq!(
+            PropertyValue::from_json(&date),
+            PropertyValue::Date {
+                start: Some("2024-08-19".to_string()),
+                end: None
+            }
+        );
+        assert_eq!(
+            PropertyValue::from_json(&title),
+            PropertyValue::Title("Daily Notes".to_string())
+        );
+        assert_eq!(
+            PropertyValue::from_json(&rollup),
+            PropertyValue::Unsupported("rollup".to_string())
+        );
+    }
+
+    #[test]
+    fn test_database_schema_from_json() {
+        let database = serde_json::json!({
+            "object": "database",
+            "id": "5e1f0c3a-8d2b-4c7e-9f1a-2b3c4d5e6f70",
+            "title": [{"plain_text": "Reading "}, {"plain_text": "List"}],
+            "properties": {
+                "Name": {"id": "title", "type": "title", "title": {}},
+                "Tags": {"id": "a", "type": "multi_select", "multi_select": {"options": []}},
+                "Score": {"id": "b", "type": "formula", "formula": {"expression": "1"}},
+                "Verified": {"id": "c", "type": "verification", "verification": {}}
+            }
+        });
+
+        let schema = DatabaseSchema::from_json(&database);
+
+        assert_eq!(schema.id, "5e1f0c3a-8d2b-4c7e-9f1a-2b3c4d5e6f70");
+        assert_eq!(schema.title, "Reading List");
+        assert_eq!(
+            schema.properties,
+            HashMap::from([
+                ("Name".to_string(), PropertyType::Title),
+                ("Tags".to_string(), PropertyType::MultiSelect),
+                ("Score".to_string(), PropertyType::Formula),
+                (
+                    "Verified".to_string(),
+                    PropertyType::Other("verification".to_string())
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_properties_to_frontmatter() {
+        let properties = HashMap::from([
+            (
+                "Tags".to_string(),
+                PropertyValue::MultiSelect(vec!["rust".to_string(), "notion".to_string()]),
+            ),
+            ("Done".to_string(), PropertyValue::Checkbox(true)),
+            (
+                "Status".to_string(),
+                PropertyValue::Status(Some("In \"Progress\"".to_string())),
+            ),
+        ]);
+
+        assert_eq!(
+            properties_to_frontmatter(&properties),
+            "---\n\"Done\": true\n\"Status\": \"In \\\"Progress\\\"\"\n\"Tags\": [\"rust\", \"notion\"]\n---\n"
+        );
+    }
+
+    #[test]
+    fn test_page_output_to_frontmatter_markdown() {
+        let output = PageOutput {
+            page: Page {
+                creation_date: Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap(),
+                ..Page::for_test("7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb", "Trip \"Notes\"")
+            },
+            markdown: "Packed the tent\n".to_string(),
+            block_count: 1,
+            properties: Some(HashMap::from([(
+                "Trip Length".to_string(),
+                PropertyValue::Number(Some(3.0)),
+            )])),
+            parent_title: None,
+        };
+
+        let extra = [
+            ("draft".to_string(), "true".to_string()),
+            ("tags".to_string(), "travel".to_string()),
+        ];
+
+        assert_eq!(
+            output.to_frontmatter_markdown(&extra),
+            "---\ntitle: \"Trip \\\"Notes\\\"\"\ndate: 2024-01-15T10:30:00Z\nnotion_url: \"https://www.notion.so/7b1b3b0c14cb45a6a4b6d2b48faecccb\"\ntags: \"travel\"\n\"Trip Length\": 3\ndraft: \"true\"\n---\nPacked the tent\n"
+        );
+    }
+
+    #[test]
+    fn test_join_separator() {
+        assert_eq!(
+            join_separator(&BlockType::Paragraph {
+                paragraph: Default::default()
+            }),
+            ""
+        );
+        assert_eq!(
+            join_separator(&BlockType::BulletedListItem {
+                bulleted_list_item: Default::default()
+            }),
+            " "
+        );
+    }
+
+    #[test]
+    fn test_from_notion_block_keeps_multiline_code() {
+        let segment = |content: &str| {
+            serde_json::json!({
+                "type": "text",
+                "text": { "content": content, "link": null },
+                "plain_text": content,
+                "href": null
+            })
+        };
+        let notion_block: NotionBlock = serde_json::from_value(serde_json::json!({
+            "object": "block",
+            "id": "c0de",
+            "parent": { "type": "page_id", "page_id": "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb" },
+            "created_time": "2024-08-19T00:00:00.000Z",
+            "last_edited_time": "2024-08-19T00:00:00.000Z",
+            "has_children": false,
+            "archived": false,
+            "type": "code",
+            "code": {
+                "caption": [],
+                "rich_text": [segment("fn main() {"), segment("    println!(\"hi\");"), segment("}")],
+                "language": "rust"
+            }
+        }))
+        .unwrap();
+
+        let block = Block::from_notion_block(
+            notion_block,
+            "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb".to_string(),
+        );
+
+        assert_eq!(block.text, "fn main() {\n    println!(\"hi\");\n}");
+    }
+
+    #[test]
+    fn test_plain_text_code_block_markdown_detects_language() {
+        let notion_block: NotionBlock = serde_json::from_value(serde_json::json!({
+            "object": "block",
+            "id": "c0de",
+            "created_time": "2024-08-19T00:00:00.000Z",
+            "last_edited_time": "2024-08-19T00:00:00.000Z",
+            "has_children": false,
+            "archived": false,
+            "type": "code",
+            "code": {
+                "caption": [],
+                "rich_text": [{
+                    "type": "text",
+                    "text": { "content": "let mut x = 1;", "link": null },
+                    "plain_text": "let mut x = 1;",
+                    "href": null
+                }],
+                "language": "plain text"
+            }
+        }))
+        .unwrap();
+
+        let block = Block::from_notion_block(
+            notion_block,
+            "7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb".to_string(),
+        );
+
+        assert_eq!(block.to_markdown(), "```rust\nlet mut x = 1;\n```");
+    }
+
+    #[test]
+    fn test_normalize_text_trims_whitespace() {
+        assert_eq!(
+            normalize_text("  padded text \t\n".to_string()),
+            "padded text"
+        );
+    }
+
+    #[test]
+    fn test_normalize_text_collapses_whitespace_runs() {
+        assert_eq!(
+            normalize_text("one  two \t three\n\nfour".to_string()),
+            "one two three four"
+        );
+    }
+
+    #[test]
+    fn test_normalize_text_replaces_non_breaking_spaces() {
+        assert_eq!(
+            normalize_text("non\u{a0}breaking\u{a0}\u{a0}space".to_string()),
+            "non breaking space"
+        );
+    }
+
+    #[test]
+    fn test_normalize_text_handles_empty_and_blank_text() {
+        assert_eq!(normalize_text(String::new()), "");
+        assert_eq!(normalize_text(" \u{a0} ".to_string()), "");
+    }
+
+    #[test]
+    fn test_page_word_and_block_counts() {
+        let mut page = Page {
+            child_blocks: vec![Block::from_text(""), Block::from_text("")],
+            ..Page::for_test("7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb", "Journal")
+        };
+
+        assert!(page.is_empty());
+        assert_eq!(page.word_count(), 0);
+        assert_eq!(page.block_count(), 2);
+
+        page.child_blocks
+            .push(Block::from_text("three whole words"));
+
+        assert!(!page.is_empty());
+        assert_eq!(page.word_count(), 3);
+        assert_eq!(page.block_count(), 3);
+    }
+
+    #[test]
+    fn test_page_url_to_id() {
+        let id = Some("651d530e-07a1-4f9c-97b4-084614c5049b".to_string());
+
+        assert_eq!(
+            Page::url_to_id(
+                "https://www.notion.so/August-19-2024-651d530e07a14f9c97b4084614c5049b"
+            ),
+            id
+        );
+        assert_eq!(
+            Page::url_to_id("https://www.notion.so/651D530E07A14F9C97B4084614C5049B"),
+            id
+        );
+        assert_eq!(
+            Page::url_to_id(
+                "https://www.notion.so/acme/Roadmap-651d530e07a14f9c97b4084614c5049b?pvs=4#b10c"
+            ),
+            id
+        );
+        assert_eq!(Page::url_to_id("https://www.notion.so/Roadmap"), None);
+        assert_eq!(
+            Page::url_to_id("https://www.notion.so/Roadmap651d530e07a14f9c97b4084614c5049b"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_page_preview() {
+        let mut page = Page {
+            child_blocks: vec![
+                Block::from_text("Went climbing"),
+                Block::from_text(""),
+                Block::from_text("Finished the\nreport"),
+                Block::from_text("Called mum"),
+            ],
+            ..Page::for_test("7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb", "Journal")
+        };
+
+        assert_eq!(page.preview(2), "Went climbing | Finished the report");
+        assert_eq!(
+            page.preview(10),
+            "Went climbing | Finished the report | Called mum"
+        );
+
+        page.child_blocks = vec![Block::from_text(&"word ".repeat(30))];
+        let preview = page.preview(3);
+        assert_eq!(preview.chars().count(), PAGE_PREVIEW_MAX_CHARS);
+        assert!(preview.ends_with('…'));
+    }
+
+    #[test]
+    fn test_truncate_to_words_drops_least_rich_pages_first() {
+        let output = |title: &str, text: &str| PageOutput {
+            page: Page {
+                child_blocks: vec![Block::from_text(text)],
+                ..Page::for_test(title, title)
+            },
+            markdown: text.to_string(),
+            block_count: 1,
+            properties: None,
+            parent_title: None,
+        };
+        let pages = vec![
+            output("Sparse", "two words"),
+            output("Rich", "a page with quite a few more words in it"),
+            output("Medium", "somewhere in the middle"),
+        ];
+
+        let mut by_richness = PromptContext::new(pages.clone());
+        assert_eq!(by_richness.truncate_to_words(14, PagePriority::Richness), 1);
+        let titles = by_richness
+            .pages
+            .iter()
+            .map(|output| output.page.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Rich", "Medium"]);
+        assert_eq!(by_richness.total_words, 14);
+    }
+
+    #[test]
+    fn test_truncate_to_words_by_recency_sorts_by_edit_time() {
+        let now = Utc::now();
+        let output = |title: &str, days_ago: i64| PageOutput {
+            page: Page {
+                update_date: now - chrono::Duration::days(days_ago),
+                ..Page::for_test(title, title)
+            },
+            markdown: "five words in this page".to_string(),
+            block_count: 1,
+            properties: None,
+            parent_title: None,
+        };
+        // not in edit order, the way --page-order hierarchical can leave them
+        let mut context = PromptContext::new(vec![
+            output("Parent", 3),
+            output("Child", 0),
+            output("Sibling", 1),
+        ]);
+
+        assert_eq!(context.truncate_to_words(10, PagePriority::Recency), 1);
+        let titles = context
+            .pages
+            .iter()
+            .map(|output| output.page.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Child", "Sibling"]);
+    }
+
+    #[test]
+    fn test_truncate_to_tokens_counts_the_rendered_pages() {
+        let now = Utc::now();
+        let output = |title: &str, days_ago: i64| PageOutput {
+            page: Page {
+                update_date: now - chrono::Duration::days(days_ago),
+                ..Page::for_test(title, title)
+            },
+            markdown: "word ".repeat(20),
+            block_count: 1,
+            properties: None,
+            parent_title: Some("Projects".to_string()),
+        };
+        let mut context = PromptContext::new(vec![
+            output("Oldest", 3),
+            output("Newest", 0),
+            output("Middle", 1),
+        ]);
+        let mut window = ContextWindow::new(70, "gpt-4o");
+
+        let dropped = context.truncate_to_tokens(&mut window, PagePriority::Recency, |context| {
+            context.to_markdown_pages()
+        });
+
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].page.title, "Oldest");
+        // the group's heading moved to the new first Page, and it's counted too
+        assert_eq!(window.to_prompt(), context.to_markdown());
+        assert!(window
+            .to_prompt()
+            .starts_with("## Parent: Projects\n\nPage Title: Newest"));
+        assert!(window.count_tokens(&window.to_prompt()) <= 70);
+    }
+
+    #[test]
+    fn test_truncate_to_tokens_keeps_whole_sections() {
+        let first = format!("# Plans\n{}\n", "plan ".repeat(20));
+        let output = PageOutput {
+            page: Page::for_test("Notes", "Notes"),
+            markdown: format!("{}# Log\n{}\n", first, "log ".repeat(40)),
+            block_count: 2,
+            properties: None,
+            parent_title: None,
+        };
+        let mut context = PromptContext::new(vec![output]);
+        let mut window = ContextWindow::new(40, "gpt-4o");
+
+        let dropped = context.truncate_to_tokens(&mut window, PagePriority::Recency, |context| {
+            context.to_markdown_pages()
+        });
+
+        assert!(dropped.is_empty());
+        assert_eq!(context.pages[0].markdown, first);
+        assert_eq!(window.to_prompt(), context.to_markdown());
+    }
+
+    #[test]
+    fn test_truncate_to_bytes_drops_whole_pages_from_the_end() {
+        let output = |title: &str| PageOutput {
+            page: Page::for_test(title, title),
+            markdown: "x".repeat(100),
+            block_count: 1,
+            properties: None,
+            parent_title: None,
+        };
+        let mut context = PromptContext::new(vec![output("One"), output("Two"), output("Three")]);
+        let two_pages = context.pages[..2]
+            .iter()
+            .map(PageOutput::to_markdown)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        // one byte short of the first two Pages, so the second is cut rather than split
+        let dropped = context.truncate_to_bytes(two_pages.len() - 1, PromptContext::to_markdown);
+
+        let titles = dropped
+            .iter()
+            .map(|output| output.page.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Two", "Three"]);
+        assert_eq!(context.pages.len(), 1);
+        assert_eq!(context.total_blocks, 1);
+    }
+
+    #[test]
+    fn test_grouped_pages_get_a_parent_heading() {
+        let output = |title: &str, parent_title: Option<&str>| PageOutput {
+            page: Page::for_test(title, title),
+            markdown: String::new(),
+            block_count: 0,
+            properties: None,
+            parent_title: parent_title.map(str::to_string),
+        };
+        let context = PromptContext::new(vec![
+            output("Monday", Some("Daily Notes")),
+            output("Tuesday", Some("Daily Notes")),
+            output("Unrelated", None),
+        ]);
+
+        assert_eq!(
+            context.to_markdown(),
+            "## Parent: Daily Notes\n\nPage Title: Monday\n\n\nPage Title: Tuesday\n\n\n## Pages without a parent\n\nPage Title: Unrelated\n"
+        );
+    }
 }
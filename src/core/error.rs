@@ -0,0 +1,135 @@
+use notion_client::NotionClientError;
+use std::fmt;
+
+use super::datatypes::PageID;
+
+#[derive(Debug)]
+pub enum DrossError {
+    /// An error returned by the Notion API, or by `notion-client` while talking to it
+    Notion(NotionClientError),
+    /// Notion rejected the integration token (a `401`)
+    InvalidToken,
+    /// The integration can't read this Page, usually because it hasn't been shared with it
+    InsufficientPermissions { page_id: PageID },
+    /// The integration can't see a single Page, so it hasn't been shared with any yet
+    EmptyWorkspace,
+    /// The run has made as many Notion API requests as its budget allows
+    BudgetExhausted { calls_made: u32 },
+    /// A Notion API version that isn't a date like `2022-06-28`
+    InvalidApiVersion { version: String },
+    /// A request to an HTTP endpoint other than Notion's (e.g. a webhook) failed
+    Http(reqwest::Error),
+    /// An HTTP endpoint other than Notion's responded with a non-success status
+    UnexpectedStatus { url: String, status: u16 },
+    /// Reading or writing a local file failed
+    Io(std::io::Error),
+    /// JSON that didn't have the shape we expected, e.g. a response body we parse ourselves
+    InvalidJson(serde_json::Error),
+    /// A Notion API response that still couldn't be read after retrying. The raw body is kept
+    /// so it can be attached to a bug report.
+    Deserialization {
+        source: serde_json::Error,
+        body: String,
+    },
+}
+
+impl fmt::Display for DrossError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DrossError::Notion(e) => write!(f, "Notion API error: {}", e),
+            DrossError::InvalidToken => write!(f, "Notion rejected the integration token"),
+            DrossError::InsufficientPermissions { page_id } => {
+                write!(
+                    f,
+                    "the Notion integration doesn't have access to Page {}",
+                    page_id
+                )
+            }
+            DrossError::EmptyWorkspace => {
+                write!(f, "the Notion integration doesn't have access to any Pages")
+            }
+            DrossError::BudgetExhausted { calls_made } => {
+                write!(f, "used up the budget of {} Notion API calls", calls_made)
+            }
+            DrossError::InvalidApiVersion { version } => write!(
+                f,
+                "expected a Notion API version like 2022-06-28, got {}",
+                version
+            ),
+            DrossError::Http(e) => write!(f, "HTTP error: {}", e),
+            DrossError::UnexpectedStatus { url, status } => {
+                write!(f, "{} responded with status {}", url, status)
+            }
+            DrossError::Io(e) => write!(f, "I/O error: {}", e),
+            DrossError::InvalidJson(e) => write!(f, "invalid JSON: {}", e),
+            DrossError::Deserialization { source, body } => write!(
+                f,
+                "couldn't read a Notion API response: {}. Please include its body in a bug report:\n{}",
+                source, body
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DrossError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DrossError::Notion(e) => Some(e),
+            DrossError::InvalidToken
+            | DrossError::InsufficientPermissions { .. }
+            | DrossError::EmptyWorkspace
+            | DrossError::BudgetExhausted { .. }
+            | DrossError::InvalidApiVersion { .. } => None,
+            DrossError::Http(e) => Some(e),
+            DrossError::UnexpectedStatus { .. } => None,
+            DrossError::Io(e) => Some(e),
+            DrossError::InvalidJson(e) => Some(e),
+            DrossError::Deserialization { source, .. } => Some(source),
+        }
+    }
+}
+
+impl DrossError {
+    /// Picks out the Notion API errors caused by a misconfigured integration, so they can be
+    /// reported with instructions for fixing them. `page_id` is the Page being read, if any.
+    #[must_use]
+    pub fn from_notion(e: NotionClientError, page_id: Option<&str>) -> Self {
+        let status = match &e {
+            NotionClientError::InvalidStatusCode { error } => Some(error.status),
+            _ => None,
+        };
+        match (status, page_id) {
+            (Some(401), _) => DrossError::InvalidToken,
+            // Notion answers with a 404 rather than a 403 for Pages that exist but haven't
+            // been shared with the integration
+            (Some(403 | 404), Some(page_id)) => DrossError::InsufficientPermissions {
+                page_id: page_id.to_string(),
+            },
+            _ => DrossError::Notion(e),
+        }
+    }
+}
+
+impl From<NotionClientError> for DrossError {
+    fn from(e: NotionClientError) -> Self {
+        DrossError::from_notion(e, None)
+    }
+}
+
+impl From<reqwest::Error> for DrossError {
+    fn from(e: reqwest::Error) -> Self {
+        DrossError::Http(e)
+    }
+}
+
+impl From<std::io::Error> for DrossError {
+    fn from(e: std::io::Error) -> Self {
+        DrossError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for DrossError {
+    fn from(e: serde_json::Error) -> Self {
+        DrossError::InvalidJson(e)
+    }
+}
@@ -0,0 +1,212 @@
+use log::{debug, info};
+use notion_client::objects::block::BlockType;
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::Path,
+};
+
+use super::{
+    datatypes::{BlockTree, PageID, PageOutput, PromptContext},
+    error::DrossError,
+    helpers::build_markdown_from_trees_with_registry,
+    markdown::BlockTypeRegistry,
+};
+use crate::notion::Notion;
+
+/// Exports the Page with `page_id` and every Page nested under it as markdown files, mirroring
+/// the Notion hierarchy as directories: a Page titled "Foo" is written to `Foo.md`, and the
+/// Pages nested inside it are written to the `Foo/` directory next to it. Where a nested Page
+/// sits in `Foo.md`, there's a link to its file rather than its content.
+///
+/// # Returns
+/// The number of Pages exported
+pub async fn export_page_tree(
+    notion: &Notion,
+    page_id: &PageID,
+    dir: &Path,
+) -> Result<usize, DrossError> {
+    // walking down from the root only touches the Pages being exported, rather than
+    // searching the whole workspace for the Pages under it
+    let root = notion.get_page_by_id(page_id).await?;
+    let mut queue = VecDeque::from([(root, dir.to_path_buf())]);
+    let mut visited = HashSet::new();
+    let mut exported = 0;
+
+    while let Some((page, page_dir)) = queue.pop_front() {
+        if !visited.insert(page.id.clone()) {
+            continue;
+        }
+        let file_stem = file_stem_for_title(&page.title, &page.id);
+
+        // unlike ingestion, we want the whole Page here, not just its recently edited Blocks
+        let trees = notion.grow_the_roots_within_page(page.child_blocks).await?;
+        let child_page_ids = child_page_ids(&trees);
+        fs::create_dir_all(&page_dir)?;
+        let path = page_dir.join(format!("{}.md", file_stem));
+        let markdown = build_markdown_from_trees_with_registry(
+            trees,
+            &registry_linking_child_pages(&file_stem),
+        );
+        fs::write(&path, markdown)?;
+        debug!(target: "notion", "exported Page {} to {}", page.id, path.display());
+        exported += 1;
+
+        let child_dir = page_dir.join(&file_stem);
+        for child_id in child_page_ids {
+            let child = notion.get_page_by_id(&child_id).await?;
+            queue.push_back((child, child_dir.clone()));
+        }
+    }
+
+    info!(target: "notion", "exported {} Pages to {}", exported, dir.display());
+
+    Ok(exported)
+}
+
+/// The IDs of the Pages nested in `trees`, wherever they are on the Page, including inside
+/// toggles and columns. Pages nested inside those Pages are left out, since they're found when
+/// their own parent is exported.
+fn child_page_ids(trees: &[BlockTree]) -> Vec<PageID> {
+    let mut ids = Vec::new();
+    for tree in trees {
+        // the depth of the child page whose contents we're currently skipping over
+        let mut inside_child_page: Option<usize> = None;
+        for (block, depth) in tree.flatten_with_depth() {
+            if inside_child_page.is_some_and(|page_depth| depth > page_depth) {
+                continue;
+            }
+            inside_child_page = None;
+            if matches!(block.block_type, BlockType::ChildPage { .. }) {
+                ids.push(block.id);
+                inside_child_page = Some(depth);
+            }
+        }
+    }
+    ids
+}
+
+/// The default registry, except nested Pages are rendered as links to the files they're
+/// exported to, in the `dir_stem/` directory next to the Page linking to them
+fn registry_linking_child_pages(dir_stem: &str) -> BlockTypeRegistry {
+    let dir_stem = dir_stem.to_string();
+    let mut registry = BlockTypeRegistry::default();
+    registry.register("child_page", move |block, _| {
+        // the angle brackets let the path have spaces in it
+        format!(
+            "[{}](<{}/{}.md>)",
+            block.text,
+            dir_stem,
+            file_stem_for_title(&block.text, &block.id)
+        )
+    });
+    registry
+}
+
+/// Writes each ingested Page in `context` to its own `<title>.md` file in `dir`, as rendered by
+/// `render`. Pages that share a title get their ID added to the file name so none are lost.
+///
+/// # Returns
+/// The number of Pages written
+pub fn write_pages_to_dir(
+    context: &PromptContext,
+    dir: &Path,
+    render: impl Fn(&PageOutput) -> String,
+) -> Result<usize, DrossError> {
+    fs::create_dir_all(dir)?;
+    let mut taken = HashSet::new();
+    for output in &context.pages {
+        let mut file_stem = file_stem_for_title(&output.page.title, &output.page.id);
+        if !taken.insert(file_stem.clone()) {
+            file_stem = format!("{} {}", file_stem, output.page.id);
+            taken.insert(file_stem.clone());
+        }
+        let path = dir.join(format!("{}.md", file_stem));
+        fs::write(&path, render(output))?;
+        debug!(target: "notion", "wrote Page {} to {}", output.page.id, path.display());
+    }
+
+    Ok(context.pages.len())
+}
+
+/// Turns a Page title into something safe to use as a file name, falling back to the Page's
+/// ID for titles with nothing usable in them
+fn file_stem_for_title(title: &str, page_id: &str) -> String {
+    let stem = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+            c if c.is_control() => '-',
+            c => c,
+        })
+        .collect::<String>()
+        .trim()
+        .trim_start_matches('.')
+        .to_string();
+
+    if stem.is_empty() {
+        page_id.to_string()
+    } else {
+        stem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::datatypes::BlockTreeBuilder;
+
+    #[test]
+    fn test_file_stem_for_title() {
+        assert_eq!(
+            file_stem_for_title("August 19 2024", "abc"),
+            "August 19 2024"
+        );
+        assert_eq!(file_stem_for_title("Q3/Q4: Plans?", "abc"), "Q3-Q4- Plans-");
+        assert_eq!(file_stem_for_title("..", "abc"), "abc");
+    }
+
+    #[test]
+    fn test_child_page_ids_finds_nested_pages() {
+        let child_page = |id: &str| {
+            BlockTreeBuilder::new(id)
+                .id(id)
+                .block_type(BlockType::ChildPage {
+                    child_page: Default::default(),
+                })
+        };
+        let trees = vec![
+            child_page("top").child(child_page("grandchild")).build(),
+            BlockTreeBuilder::new("More")
+                .block_type(BlockType::Toggle {
+                    toggle: Default::default(),
+                })
+                .child(child_page("in-toggle"))
+                .child(BlockTreeBuilder::new("after"))
+                .build(),
+        ];
+
+        assert_eq!(child_page_ids(&trees), vec!["top", "in-toggle"]);
+    }
+
+    #[test]
+    fn test_child_pages_are_exported_as_links() {
+        let trees = vec![
+            BlockTreeBuilder::new("Intro").build(),
+            BlockTreeBuilder::new("Q3: Plans")
+                .id("abc")
+                .block_type(BlockType::ChildPage {
+                    child_page: Default::default(),
+                })
+                .build(),
+        ];
+
+        assert_eq!(
+            build_markdown_from_trees_with_registry(
+                trees,
+                &registry_linking_child_pages("Roadmap")
+            ),
+            "Intro\n[Q3: Plans](<Roadmap/Q3- Plans.md>)\n"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use log::{info, warn};
+use reqwest::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+use super::{datatypes::PromptContext, error::DrossError};
+
+pub const GIST_API_URL: &str = "https://api.github.com/gists";
+
+/// Where the GitHub token is kept in the system keyring, so it doesn't sit in the config
+/// file in plain text
+const KEYRING_SERVICE: &str = "dross";
+const KEYRING_USER: &str = "github_token";
+
+/// The body of a request to create or update a gist
+#[derive(Debug, Clone, Serialize)]
+pub struct Gist {
+    pub description: String,
+    pub public: bool,
+    /// Each file's name mapped to its contents
+    pub files: BTreeMap<String, GistFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GistFile {
+    pub content: String,
+}
+
+impl Gist {
+    /// Builds a gist with one markdown file per Page, named after the Page's title
+    #[must_use]
+    pub fn from_prompt_context(context: &PromptContext, description: &str, public: bool) -> Self {
+        let mut taken = HashSet::new();
+        let files = context
+            .pages
+            .iter()
+            .map(|page| {
+                let filename = gist_filename(&page.page.title, &mut taken);
+                let file = GistFile {
+                    content: page.to_markdown(),
+                };
+                (filename, file)
+            })
+            .collect();
+        Gist {
+            description: description.to_string(),
+            public,
+            files,
+        }
+    }
+}
+
+/// Turns a Page title into a `page_title.md` style filename. Gist filenames must be unique,
+/// so repeats of a title already in `taken` get a number, e.g. `page_title_2.md`.
+fn gist_filename(title: &str, taken: &mut HashSet<String>) -> String {
+    let stem = title
+        .trim()
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join("_");
+    let stem = if stem.is_empty() {
+        "untitled".to_string()
+    } else {
+        stem
+    };
+
+    let mut filename = format!("{}.md", stem);
+    let mut n = 2;
+    while !taken.insert(filename.clone()) {
+        filename = format!("{}_{}.md", stem, n);
+        n += 1;
+    }
+    filename
+}
+
+/// Creates a new gist, or replaces the files of `update_gist_id` if given, and returns the
+/// gist's URL
+pub async fn upload_gist(
+    token: &str,
+    gist: &Gist,
+    update_gist_id: Option<&str>,
+) -> Result<String, DrossError> {
+    let body = serde_json::to_vec(gist).expect("Gist always serializes");
+    let client = reqwest::Client::new();
+    let (request, url) = match update_gist_id {
+        Some(gist_id) => {
+            let url = format!("{}/{}", GIST_API_URL, gist_id);
+            (client.patch(&url), url)
+        }
+        None => (client.post(GIST_API_URL), GIST_API_URL.to_string()),
+    };
+
+    let res = request
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .header(ACCEPT, "application/vnd.github+json")
+        .header(CONTENT_TYPE, "application/json")
+        // GitHub rejects requests without a User-Agent
+        .header(USER_AGENT, "dross")
+        .body(body)
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        return Err(DrossError::UnexpectedStatus {
+            url,
+            status: res.status().as_u16(),
+        });
+    }
+
+    let created: serde_json::Value = serde_json::from_slice(&res.bytes().await?)?;
+    Ok(created["html_url"].as_str().unwrap_or_default().to_string())
+}
+
+/// The GitHub token saved by `save_github_token`, if there is one
+#[must_use]
+pub fn saved_github_token() -> Option<String> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).ok()?;
+    entry.get_password().ok()
+}
+
+/// Saves `token` to the system keyring so later `dross export-gist` runs don't need `--token`.
+/// Saving is best-effort, since the token that was passed in still works for this run.
+pub fn save_github_token(token: &str) {
+    let saved = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+        .and_then(|entry| entry.set_password(token));
+    match saved {
+        Ok(()) => info!("saved the GitHub token to the system keyring"),
+        Err(e) => warn!(
+            "failed to save the GitHub token to the system keyring: {}",
+            e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gist_filename() {
+        let mut taken = HashSet::new();
+        assert_eq!(gist_filename("Daily Notes", &mut taken), "daily_notes.md");
+        assert_eq!(
+            gist_filename("daily notes!", &mut taken),
+            "daily_notes_2.md"
+        );
+        assert_eq!(
+            gist_filename("Q3: Roadmap / Plan", &mut taken),
+            "q3_roadmap_plan.md"
+        );
+        assert_eq!(gist_filename("  ", &mut taken), "untitled.md");
+    }
+}
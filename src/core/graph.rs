@@ -0,0 +1,132 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::datatypes::{Page, PageID};
+
+/// The Page hierarchy of a Notion workspace, where each Page points to the Pages nested
+/// directly inside it. Only Pages nested in other Pages have a parent, so Pages at the top of
+/// the workspace or inside a database are the roots.
+#[derive(Debug, Clone, Default)]
+pub struct WorkspaceGraph {
+    pub pages: HashMap<PageID, Page>,
+    /// Each parent Page's ID mapped to the IDs of its children, in the order the Pages were given
+    pub edges: HashMap<PageID, Vec<PageID>>,
+}
+
+impl WorkspaceGraph {
+    /// Builds the graph from each Page's `parent_page_id`. A parent doesn't have to be one of
+    /// `pages`, in which case its children still share an edge, but it has no `Page` of its own.
+    #[must_use]
+    pub fn from_pages(pages: impl IntoIterator<Item = Page>) -> Self {
+        let mut graph = WorkspaceGraph::default();
+        for page in pages {
+            if let Some(parent_id) = &page.parent_page_id {
+                graph
+                    .edges
+                    .entry(parent_id.clone())
+                    .or_default()
+                    .push(page.id.clone());
+            }
+            graph.pages.insert(page.id.clone(), page);
+        }
+        graph
+    }
+
+    /// The ID of the Page that `page_id` is nested directly inside, if it's nested in a Page
+    #[must_use]
+    pub fn parent(&self, page_id: &str) -> Option<&PageID> {
+        self.pages.get(page_id)?.parent_page_id.as_ref()
+    }
+
+    /// The IDs of every Page that `page_id` is nested inside, nearest first. The last one may
+    /// be a Page that isn't in the graph, since its parent is all we know about it.
+    #[must_use]
+    pub fn ancestors(&self, page_id: &str) -> Vec<PageID> {
+        let mut ancestors = Vec::new();
+        let mut visited = HashSet::from([page_id.to_string()]);
+        let mut current = page_id;
+        while let Some(parent_id) = self.parent(current) {
+            // Notion doesn't allow cycles, but a bad graph shouldn't hang us
+            if !visited.insert(parent_id.clone()) {
+                break;
+            }
+            ancestors.push(parent_id.clone());
+            current = parent_id;
+        }
+        ancestors
+    }
+
+    /// The IDs of every Page nested inside `page_id`, breadth first
+    #[must_use]
+    pub fn descendants(&self, page_id: &str) -> Vec<PageID> {
+        let mut descendants = Vec::new();
+        let mut visited = HashSet::from([page_id.to_string()]);
+        let mut queue = VecDeque::from([page_id.to_string()]);
+        while let Some(id) = queue.pop_front() {
+            for child_id in self.edges.get(&id).into_iter().flatten() {
+                if visited.insert(child_id.clone()) {
+                    descendants.push(child_id.clone());
+                    queue.push_back(child_id.clone());
+                }
+            }
+        }
+        descendants
+    }
+
+    /// The IDs of the other Pages nested directly inside the same Page as `page_id`. Pages that
+    /// aren't nested in a Page have no siblings.
+    #[must_use]
+    pub fn siblings(&self, page_id: &str) -> Vec<PageID> {
+        self.parent(page_id)
+            .and_then(|parent_id| self.edges.get(parent_id))
+            .into_iter()
+            .flatten()
+            .filter(|id| id.as_str() != page_id)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(id: &str, parent_id: Option<&str>) -> Page {
+        Page {
+            parent_page_id: parent_id.map(str::to_string),
+            ..Page::for_test(id, id)
+        }
+    }
+
+    fn graph() -> WorkspaceGraph {
+        WorkspaceGraph::from_pages(vec![
+            page("root", None),
+            page("child-a", Some("root")),
+            page("child-b", Some("root")),
+            page("grandchild", Some("child-a")),
+            page("unrelated", None),
+        ])
+    }
+
+    #[test]
+    fn test_ancestors_are_nearest_first() {
+        assert_eq!(graph().ancestors("grandchild"), vec!["child-a", "root"]);
+        assert!(graph().ancestors("root").is_empty());
+    }
+
+    #[test]
+    fn test_descendants_are_breadth_first() {
+        assert_eq!(
+            graph().descendants("root"),
+            vec!["child-a", "child-b", "grandchild"]
+        );
+        assert!(graph().descendants("unrelated").is_empty());
+    }
+
+    #[test]
+    fn test_siblings_share_a_parent_page() {
+        assert_eq!(graph().siblings("child-a"), vec!["child-b"]);
+        assert!(graph().siblings("grandchild").is_empty());
+        // top-level Pages aren't siblings of each other
+        assert!(graph().siblings("root").is_empty());
+    }
+}
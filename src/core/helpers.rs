@@ -1,25 +1,1802 @@
-use dendron::{Node, Tree};
+use chrono::Utc;
+use clap::ValueEnum;
+use dendron::{HierarchyEditGrant, Node, Tree};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use log::debug;
+use notion_client::objects::block::BlockType;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Parser, Tag, TagEnd};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::OnceLock,
+};
+use tiktoken_rs::{cl100k_base, get_bpe_from_model, CoreBPE};
 
-use super::datatypes::Block;
+use super::{
+    datatypes::{
+        rich_text_runs, Block, BlockById, BlockID, BlockTree, Page, PageID, StructuralDeduplicate,
+    },
+    error::DrossError,
+    graph::WorkspaceGraph,
+    markdown::BlockTypeRegistry,
+};
 
-pub fn build_markdown_from_trees(trees: Vec<Tree<Block>>) -> String {
+pub fn build_markdown_from_trees(trees: Vec<BlockTree>) -> String {
+    build_markdown_from_trees_with_registry(trees, BlockTypeRegistry::global())
+}
+
+/// Same as `build_markdown_from_trees`, except each `Block` is rendered by the formatters in `registry`
+pub fn build_markdown_from_trees_with_registry(
+    trees: Vec<BlockTree>,
+    registry: &BlockTypeRegistry,
+) -> String {
+    build_markdown_from_trees_with_duplicate_check(trees, registry, DuplicateCheck::ById)
+}
+
+/// How `build_markdown_from_trees` decides that a `Block` has already been written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateCheck {
+    /// Any Block with the same ID, see `BlockById`
+    #[default]
+    ById,
+    /// A Block with the same ID, content and parent, see `StructuralDeduplicate`
+    Structural,
+}
+
+/// The Blocks already written to the markdown, compared the way a `DuplicateCheck` says to
+enum DuplicatesChecker {
+    ById(HashSet<BlockById>),
+    Structural(HashSet<StructuralDeduplicate>),
+}
+
+impl DuplicatesChecker {
+    fn new(check: DuplicateCheck) -> Self {
+        match check {
+            DuplicateCheck::ById => DuplicatesChecker::ById(HashSet::new()),
+            DuplicateCheck::Structural => DuplicatesChecker::Structural(HashSet::new()),
+        }
+    }
+
+    /// Records `block` as written, returning false if it already was
+    fn insert(&mut self, block: &Block) -> bool {
+        match self {
+            DuplicatesChecker::ById(seen) => seen.insert(BlockById(block.clone())),
+            DuplicatesChecker::Structural(seen) => {
+                seen.insert(StructuralDeduplicate(block.clone()))
+            }
+        }
+    }
+}
+
+/// Same as `build_markdown_from_trees_with_registry`, except Blocks are deduplicated by `check`
+pub fn build_markdown_from_trees_with_duplicate_check(
+    trees: Vec<BlockTree>,
+    registry: &BlockTypeRegistry,
+    check: DuplicateCheck,
+) -> String {
     let mut markdown = String::new();
+    // an edited Block can be a descendant of another edited Block, in which case it
+    // shows up both as its own tree and inside the other Block's tree. We only want
+    // it in the markdown once, so we keep track of the Blocks we've already written
+    let mut duplicates_checker = DuplicatesChecker::new(check);
 
+    // roots that are numbered list items in the same list are numbered like siblings, as
+    // long as they're next to each other. Items in between that weren't edited aren't
+    // roots, so the numbers can fall short of the ones on the Page
+    let mut root_list_number = 0;
+    let mut previous_root_parent = None;
     for tree in trees {
-        build_markdown_recursive(tree.root(), 0, &mut markdown);
+        let (is_numbered, parent) = {
+            let root = tree.root_block();
+            (root.is_numbered_list_item(), root.parent_block_id.clone())
+        };
+        root_list_number = if !is_numbered {
+            0
+        } else if root_list_number > 0 && previous_root_parent == parent {
+            root_list_number + 1
+        } else {
+            1
+        };
+        previous_root_parent = parent;
+        build_markdown_recursive(
+            tree.root(),
+            root_list_number.max(1),
+            &mut Vec::new(),
+            registry,
+            &mut duplicates_checker,
+            &mut markdown,
+        );
     }
 
     markdown
 }
 
-fn build_markdown_recursive(node: Node<Block>, depth: usize, markdown: &mut String) {
-    let tabs = "\t".repeat(depth);
-    markdown.push_str(&format!("{}{}\n", tabs, node.borrow_data().to_markdown()));
+/// `list_number` is the position of `node` within the current run of sibling
+/// `NumberedListItem`s, and is ignored for every other type of `Block`. `ancestors` are the
+/// written Blocks `node` is nested under, outermost first, which set how far it's indented
+/// (see `BlockTypeRegistry::format_with_context`).
+fn build_markdown_recursive(
+    node: Node<Block>,
+    list_number: usize,
+    ancestors: &mut Vec<Block>,
+    registry: &BlockTypeRegistry,
+    duplicates_checker: &mut DuplicatesChecker,
+    markdown: &mut String,
+) {
+    if !duplicates_checker.insert(&node.borrow_data()) {
+        debug!(target: "notion", "skipping duplicate Block {}, it's already in the markdown", node.borrow_data().id);
+        return;
+    }
 
-    // println!("{}", &format!("{}{}\n", tabs, node.borrow_data().text));
-    // println!("{}", node.)
+    let (is_column_list, is_column) = {
+        let block = node.borrow_data();
+        (
+            matches!(block.block_type, BlockType::ColumnList { .. }),
+            matches!(block.block_type, BlockType::Column { .. }),
+        )
+    };
+    if is_column_list {
+        // a multi-column layout has nothing to show itself, so its columns are written one
+        // after another at its depth, with a separator line between them
+        for (i, column) in node.children().enumerate() {
+            if i > 0 {
+                let tabs = "\t".repeat(ancestors.len());
+                markdown.push_str(&format!("{}{}\n", tabs, registry.column_separator()));
+            }
+            if duplicates_checker.insert(&column.borrow_data()) {
+                build_children_markdown(column, ancestors, registry, duplicates_checker, markdown);
+            }
+        }
+    } else if is_column {
+        build_children_markdown(node, ancestors, registry, duplicates_checker, markdown);
+    } else {
+        let block = node.borrow_data().clone();
+        let formatted = {
+            let ancestors = ancestors.iter().collect::<Vec<_>>();
+            registry.format_with_context(&block, list_number, &ancestors)
+        };
+        markdown.push_str(&formatted);
+        markdown.push('\n');
+        ancestors.push(block);
+        build_children_markdown(node, ancestors, registry, duplicates_checker, markdown);
+        ancestors.pop();
+    }
+}
+
+/// Puts `indent` in front of every non-empty line of `text`, so a `Block` rendered as
+/// several lines, like a code block, stays nested under its parent
+pub(crate) fn indent_lines(text: &str, indent: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
+/// Writes the children of `node` under `ancestors`, numbering each run of `NumberedListItem`s
+fn build_children_markdown(
+    node: Node<Block>,
+    ancestors: &mut Vec<Block>,
+    registry: &BlockTypeRegistry,
+    duplicates_checker: &mut DuplicatesChecker,
+    markdown: &mut String,
+) {
+    // Notion restarts numbering whenever a run of numbered list items is
+    // interrupted by any other type of Block, so we do the same
+    let mut child_list_number = 0;
     for child in node.children() {
-        build_markdown_recursive(child, depth + 1, markdown);
+        if child.borrow_data().is_numbered_list_item() {
+            child_list_number += 1;
+        } else {
+            child_list_number = 0;
+        }
+        build_markdown_recursive(
+            child,
+            child_list_number.max(1),
+            ancestors,
+            registry,
+            duplicates_checker,
+            markdown,
+        );
+    }
+}
+
+/// Parses `markdown` into the `Block`s that would render it in Notion, the reverse of
+/// `build_markdown_from_trees`. Headings, paragraphs, bulleted and numbered list items, quotes,
+/// and fenced code are supported, and everything else is kept as a paragraph of its plain text.
+/// Nested list items are flattened into a single run of top-level `Block`s.
+///
+/// The `Block`s haven't been created in Notion yet, so they have no ID or Page ID.
+#[must_use]
+pub fn markdown_to_blocks(markdown: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    // the type of the Block currently being parsed, and its text so far
+    let mut current: Option<(&'static str, serde_json::Value)> = None;
+    let mut text = String::new();
+    // whether each list we're inside of is numbered, innermost last
+    let mut lists: Vec<bool> = Vec::new();
+
+    let mut finish = |current: &mut Option<(&'static str, serde_json::Value)>,
+                      text: &mut String| {
+        let trimmed = match current {
+            Some(("code", _)) => text.trim_end_matches('\n'),
+            _ => text.trim(),
+        };
+        if let Some((type_name, extra)) = current.take() {
+            if !trimmed.is_empty() {
+                blocks.push(new_block(type_name, trimmed, extra));
+            }
+        }
+        text.clear();
+    };
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                let type_name = match level {
+                    HeadingLevel::H1 => "heading_1",
+                    HeadingLevel::H2 => "heading_2",
+                    // Notion only has three levels of heading
+                    _ => "heading_3",
+                };
+                current = Some((type_name, serde_json::json!({ "is_toggleable": false })));
+            }
+            Event::Start(Tag::Paragraph) if current.is_none() => {
+                current = Some(("paragraph", serde_json::json!({})));
+            }
+            Event::Start(Tag::BlockQuote) => current = Some(("quote", serde_json::json!({}))),
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) if !info.is_empty() => info.to_string(),
+                    _ => "plain text".to_string(),
+                };
+                current = Some(("code", serde_json::json!({ "language": language })));
+            }
+            Event::Start(Tag::List(first_number)) => {
+                // the text of an item's nested list doesn't belong to the item itself
+                finish(&mut current, &mut text);
+                lists.push(first_number.is_some());
+            }
+            Event::Start(Tag::Item) => {
+                let type_name = if lists.last() == Some(&true) {
+                    "numbered_list_item"
+                } else {
+                    "bulleted_list_item"
+                };
+                current = Some((type_name, serde_json::json!({})));
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+            }
+            Event::End(TagEnd::Paragraph) => match current {
+                Some(("paragraph", _)) => finish(&mut current, &mut text),
+                _ => text.push(' '),
+            },
+            Event::End(
+                TagEnd::Heading(_) | TagEnd::BlockQuote | TagEnd::CodeBlock | TagEnd::Item,
+            ) => finish(&mut current, &mut text),
+            Event::Text(t) | Event::Code(t) | Event::Html(t) | Event::InlineHtml(t) => {
+                if current.is_none() {
+                    current = Some(("paragraph", serde_json::json!({})));
+                }
+                text.push_str(&t);
+            }
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak => text.push('\n'),
+            _ => {}
+        }
+    }
+    finish(&mut current, &mut text);
+
+    blocks
+}
+
+/// A `Block` of the type the Notion API calls `type_name`, with `extra` merged into its value
+/// alongside the rich text, which is split into runs short enough for Notion to accept
+fn new_block(type_name: &str, text: &str, mut extra: serde_json::Value) -> Block {
+    extra["rich_text"] = rich_text_runs(text).into();
+    if type_name != "code" {
+        extra["color"] = serde_json::json!("default");
+    }
+    let block_type = serde_json::from_value(serde_json::json!({
+        "type": type_name,
+        type_name: extra,
+    }))
+    .or_else(|_| {
+        // most likely a code block in a language Notion doesn't know, which is better
+        // than nothing as plain text
+        extra["language"] = serde_json::json!("plain text");
+        serde_json::from_value(serde_json::json!({ "type": type_name, type_name: extra }))
+    })
+    .expect("every supported Block type deserializes from its rich text");
+
+    let now = Utc::now();
+    Block {
+        id: String::new(),
+        page_id: String::new(),
+        block_type,
+        text: text.to_string(),
+        creation_date: now,
+        update_date: now,
+        parent_block_id: None,
+        has_children: false,
+    }
+}
+
+/// The order to put a `Page`'s changed `Block`s in before they are grown into trees
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockSort {
+    Creation,
+    Update,
+    Alpha,
+    /// Keep the order the Blocks were retrieved from Notion in
+    #[default]
+    None,
+}
+
+impl BlockSort {
+    pub fn apply(self, blocks: &mut [Block]) {
+        match self {
+            BlockSort::Creation => sort_blocks_by_creation(blocks),
+            BlockSort::Update => sort_blocks_by_update(blocks),
+            BlockSort::Alpha => sort_blocks_by_text_alpha(blocks),
+            BlockSort::None => {}
+        }
+    }
+}
+
+/// The order the block root search visits a Page's old Blocks in when looking below them for
+/// recently edited ones. Depth first follows each nested Block to the bottom before moving on
+/// to its siblings, so it only keeps one path's worth of siblings around at a time, while
+/// breadth first finishes each level before going deeper.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraversalStrategy {
+    Bfs,
+    #[default]
+    Dfs,
+}
+
+/// Sorts `Block`s from oldest to newest creation time. Blocks created at the same time keep
+/// their order.
+pub fn sort_blocks_by_creation(blocks: &mut [Block]) {
+    blocks.sort_by_key(|block| block.creation_date);
+}
+
+/// Sorts `Block`s from least to most recently updated. Blocks updated at the same time keep
+/// their order.
+pub fn sort_blocks_by_update(blocks: &mut [Block]) {
+    blocks.sort_by_key(|block| block.update_date);
+}
+
+/// Sorts `Block`s alphabetically (case-insensitive) by their text
+pub fn sort_blocks_by_text_alpha(blocks: &mut [Block]) {
+    blocks.sort_by_cached_key(|block| block.text.to_lowercase());
+}
+
+/// The order to output `Page`s in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PageOrder {
+    /// Most recently edited first, the order Notion returns them in
+    #[default]
+    Edited,
+    /// Parent Pages before the Pages nested inside them
+    Hierarchical,
+    /// Pages nested in the same Page together, under a heading with their parent's title
+    Grouped,
+}
+
+/// Which `Page`s to keep when the output has to be cut down to size, highest priority first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PagePriority {
+    /// Pages with the most words, Blocks, headings, and links (see `Page::richness_score`)
+    Richness,
+    /// Most recently edited first
+    #[default]
+    Recency,
+    /// By title, A to Z
+    Alphabetical,
+}
+
+/// Orders `pages` so that every Page comes after its parent in `graph`. Pages whose parent isn't
+/// in `pages` come first, then their children, then their grandchildren, and so on. Within each
+/// level Pages keep their original order.
+#[must_use]
+pub fn sort_pages_hierarchically(pages: Vec<Page>, graph: &WorkspaceGraph) -> Vec<Page> {
+    let ids = pages
+        .iter()
+        .map(|page| page.id.clone())
+        .collect::<HashSet<_>>();
+    let mut children: HashMap<&PageID, Vec<usize>> = HashMap::new();
+    let mut level = Vec::new();
+    for (i, page) in pages.iter().enumerate() {
+        match graph
+            .parent(&page.id)
+            .filter(|parent| ids.contains(*parent))
+        {
+            Some(parent) => children.entry(parent).or_default().push(i),
+            None => level.push(i),
+        }
+    }
+
+    let mut order = Vec::with_capacity(pages.len());
+    while !level.is_empty() {
+        order.extend(&level);
+        level = level
+            .iter()
+            .flat_map(|&i| children.remove(&pages[i].id).unwrap_or_default())
+            .collect();
+        level.sort_unstable();
+    }
+    // only a cycle of parents could leave Pages unvisited, which Notion doesn't allow, but if
+    // it ever happens we'd rather output those Pages at the end than drop them
+    let visited = order.iter().copied().collect::<HashSet<_>>();
+    order.extend((0..pages.len()).filter(|i| !visited.contains(i)));
+
+    let mut pages = pages.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .into_iter()
+        .map(|i| pages[i].take().expect("each Page is only ordered once"))
+        .collect()
+}
+
+/// Groups `pages` by the Page they're nested directly inside, so Pages that share a parent
+/// (e.g. several entries under a "Daily Notes" Page) can be output together. The parent is
+/// `None` for Pages that aren't nested in a Page, or whose parent isn't in `graph`, and those
+/// Pages all share a single group. Groups are in the order their first Page appears in `pages`,
+/// and Pages keep their original order within each group.
+#[must_use]
+pub fn group_pages_by_parent(
+    pages: Vec<Page>,
+    graph: &WorkspaceGraph,
+) -> Vec<(Option<Page>, Vec<Page>)> {
+    let mut groups: Vec<(Option<Page>, Vec<Page>)> = Vec::new();
+    let mut group_index: HashMap<Option<PageID>, usize> = HashMap::new();
+    for page in pages {
+        let parent = graph
+            .parent(&page.id)
+            .and_then(|parent_id| graph.pages.get(parent_id));
+        let i = *group_index
+            .entry(parent.map(|parent| parent.id.clone()))
+            .or_insert_with(|| {
+                groups.push((parent.cloned(), Vec::new()));
+                groups.len() - 1
+            });
+        groups[i].1.push(page);
+    }
+    groups
+}
+
+/// Collects every URL linked to from the `Block`s of one or more trees
+#[derive(Debug, Default)]
+pub struct UrlExtractor {
+    urls: Vec<(Block, String)>,
+}
+
+impl UrlExtractor {
+    pub fn extract(&mut self, tree: &BlockTree) {
+        for block in tree.flatten() {
+            for url in block.urls() {
+                self.urls.push((block.clone(), url));
+            }
+        }
+    }
+
+    /// The `(block, url)` pairs found so far, in the order they appear in the trees
+    #[must_use]
+    pub fn into_urls(self) -> Vec<(Block, String)> {
+        self.urls
+    }
+}
+
+/// Matches the IDs Notion gives Pages and Blocks, both as dashed UUIDs and as the bare 32
+/// character hex strings that appear in Notion URLs
+const NOTION_ID_PATTERN: &str =
+    r"\b(?:[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}|[0-9a-f]{32})\b";
+
+/// Strips Notion-internal Page and Block IDs out of `Block` text, where they only pollute the prompt
+#[derive(Debug, Clone)]
+pub struct TextSanitizer {
+    id_regex: Regex,
+    /// What each ID is replaced with, use an empty string to remove them entirely
+    replacement: String,
+}
+
+impl Default for TextSanitizer {
+    fn default() -> Self {
+        TextSanitizer::new("[ref]")
+    }
+}
+
+impl TextSanitizer {
+    #[must_use]
+    pub fn new(replacement: &str) -> Self {
+        TextSanitizer {
+            id_regex: RegexBuilder::new(NOTION_ID_PATTERN)
+                .case_insensitive(true)
+                .build()
+                .expect("NOTION_ID_PATTERN is a valid regex"),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn sanitize(&self, text: &str) -> String {
+        self.id_regex
+            .replace_all(text, regex::NoExpand(&self.replacement))
+            .into_owned()
+    }
+
+    /// Sanitizes the text of every `Block` in the tree. Only the text is touched, so links
+    /// rendered from a `Block`'s other fields (like a bookmark's URL) keep working.
+    pub fn sanitize_tree(&self, tree: &BlockTree) {
+        let mut nodes = vec![tree.root()];
+        while let Some(node) = nodes.pop() {
+            nodes.extend(node.children());
+            let sanitized = self.sanitize(&node.borrow_data().text);
+            node.borrow_data_mut().text = sanitized;
+        }
+    }
+}
+
+/// Splits `markdown` into `(heading_text, section_body)` pairs, starting a new section at every
+/// heading of `level` or above (so splitting at level 2 also splits at `#` headings). Text before
+/// the first heading becomes a section with an empty heading. Only unindented headings outside
+/// of fenced code count, since those are the only ones `build_markdown_from_trees` writes.
+#[must_use]
+pub fn split_by_heading(markdown: &str, level: u8) -> Vec<(String, String)> {
+    sections(markdown, level)
+        .into_iter()
+        .map(|section| {
+            let (first_line, rest) = section.split_once('\n').unwrap_or((section, ""));
+            match heading(first_line) {
+                Some((_, text)) => (text.to_string(), rest.to_string()),
+                None => (String::new(), section.to_string()),
+            }
+        })
+        .collect()
+}
+
+/// Splits `markdown` into chunks of at most `max_chars` characters, only ever splitting between
+/// sections (see `split_by_heading`). A single section longer than `max_chars` is kept whole as
+/// a chunk of its own.
+#[must_use]
+pub fn split_into_chunks(markdown: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_chars = 0;
+    // Notion only has three levels of heading, so this splits at every heading dross writes
+    for section in sections(markdown, 3) {
+        let section_chars = section.chars().count();
+        if chunk_chars > 0 && chunk_chars + section_chars > max_chars {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_chars = 0;
+        }
+        chunk.push_str(section);
+        chunk_chars += section_chars;
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Where the last section of `markdown` starts (see `split_into_chunks`), unless it is the only
+/// one, so that `markdown` can be cut down to its leading whole sections
+#[must_use]
+pub(crate) fn last_section_start(markdown: &str) -> Option<usize> {
+    let sections = sections(markdown, 3);
+    let last = sections.last()?;
+    (sections.len() > 1).then(|| markdown.len() - last.len())
+}
+
+/// Markdown spans that wrapping mustn't split: bold text, inline code, and links
+const UNBREAKABLE_SPAN_PATTERN: &str = r"\*\*[^*\n]+\*\*|`[^`\n]+`|\[[^\]\n]*\]\([^)\n]*\)";
+
+/// Stands in for the spaces inside an unbreakable span while wrapping. Block text has its
+/// non-breaking spaces normalized away (see `normalize_text`), so it can't clash with real text.
+const UNBREAKABLE_SPACE: char = '\u{a0}';
+
+/// Wraps each line of `markdown` at `width` columns for reading in a terminal. Continuation
+/// lines keep the line's leading tabs, so nested Blocks stay nested, and fenced code is left
+/// alone. Lines are only broken at spaces, never inside bold text, inline code, or links.
+#[must_use]
+pub fn wrap_markdown(markdown: &str, width: usize) -> String {
+    static UNBREAKABLE_SPAN: OnceLock<Regex> = OnceLock::new();
+    let unbreakable_span = UNBREAKABLE_SPAN.get_or_init(|| {
+        Regex::new(UNBREAKABLE_SPAN_PATTERN).expect("UNBREAKABLE_SPAN_PATTERN is a valid regex")
+    });
+
+    let mut wrapped = String::with_capacity(markdown.len());
+    let mut in_code = false;
+    for line in markdown.split_inclusive('\n') {
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        if content.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        if in_code || content.trim_start().starts_with("```") {
+            wrapped.push_str(line);
+            continue;
+        }
+
+        let text = content.trim_start_matches('\t');
+        let indent = &content[..content.len() - text.len()];
+        let protected = unbreakable_span.replace_all(text, |captures: &regex::Captures| {
+            captures[0].replace(' ', &UNBREAKABLE_SPACE.to_string())
+        });
+        let options = textwrap::Options::new(width)
+            .initial_indent(indent)
+            .subsequent_indent(indent)
+            .word_separator(textwrap::WordSeparator::AsciiSpace)
+            .break_words(false);
+        let lines = textwrap::wrap(&protected, options);
+        if lines.is_empty() {
+            wrapped.push_str(content);
+        } else {
+            wrapped.push_str(&lines.join("\n").replace(UNBREAKABLE_SPACE, " "));
+        }
+        wrapped.push_str(newline);
+    }
+    wrapped
+}
+
+/// Markdown markers that can start a line: headings, quotes, to-dos, and bulleted list items
+const LINE_MARKER_PATTERN: &str = r"^(?:#{1,6} |> ?|[-*+] \[[ xX]\] |[-*+] )";
+
+/// A numbered list item's marker, with its number in the `number` group. Unlike the other
+/// line markers it can also be the start of an ordinary paragraph, like `2024. was busy`
+const NUMBERED_MARKER_PATTERN: &str = r"^(?P<number>\d+)\. ";
+
+/// Markdown markers around inline text, with the text itself in the `text` group: bold,
+/// strikethrough, inline code, and `*` and `_` italics. `_` only counts at word boundaries so
+/// `snake_case` is left alone.
+const INLINE_MARKER_PATTERN: &str = r"\*\*(?P<bold>[^*]+)\*\*|__(?P<bold2>[^_]+)__|~~(?P<strike>[^~]+)~~|`(?P<code>[^`]+)`|\*(?P<italic>[^*\s][^*]*)\*|(?P<before>^|[^\w])_(?P<italic2>[^_\s][^_]*)_(?P<after>[^\w]|$)";
+
+/// The compiled `LINE_MARKER_PATTERN`, `INLINE_MARKER_PATTERN`, and a regex for `[text](url)`
+/// links, with the link's parts in the `text` and `url` groups
+fn markdown_marker_regexes() -> (&'static Regex, &'static Regex, &'static Regex) {
+    static LINE_MARKER: OnceLock<Regex> = OnceLock::new();
+    static INLINE_MARKER: OnceLock<Regex> = OnceLock::new();
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    let line_marker = LINE_MARKER.get_or_init(|| {
+        Regex::new(LINE_MARKER_PATTERN).expect("LINE_MARKER_PATTERN is a valid regex")
+    });
+    let inline_marker = INLINE_MARKER.get_or_init(|| {
+        Regex::new(INLINE_MARKER_PATTERN).expect("INLINE_MARKER_PATTERN is a valid regex")
+    });
+    let link = LINK.get_or_init(|| {
+        Regex::new(r"\[(?P<text>[^\]]*)\]\((?P<url>[^)\s]*)\)").expect("the link regex is valid")
+    });
+    (line_marker, inline_marker, link)
+}
+
+/// Removes the markdown formatting from `markdown`, for places that can't render it, like SMS,
+/// voice assistants, or plain email. Line markers (`#`, `- `, `1. `, `> `, `- [ ] `), code
+/// fences, and inline markers (`**`, `_`, `~~`, `` ` ``) are removed, and links become
+/// `text (url)`. Leading tabs are kept so nested Blocks stay nested, and code is left as is.
+///
+/// A number is only removed as a list marker when it's 1, or one more than the number of the
+/// list item before it at the same depth, the way dross numbers lists. Any other number, like
+/// the year in `2024. was busy`, is part of the line's text.
+#[must_use]
+pub fn strip_markdown(markdown: &str) -> String {
+    static NUMBERED_MARKER: OnceLock<Regex> = OnceLock::new();
+    let numbered_marker = NUMBERED_MARKER.get_or_init(|| {
+        Regex::new(NUMBERED_MARKER_PATTERN).expect("NUMBERED_MARKER_PATTERN is a valid regex")
+    });
+    let (line_marker, inline_marker, link) = markdown_marker_regexes();
+    let mut stripped = String::with_capacity(markdown.len());
+    let mut in_code = false;
+    // the number of the last list item at each depth, while its list is still going
+    let mut list_numbers: HashMap<usize, u64> = HashMap::new();
+    for line in markdown.split_inclusive('\n') {
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        if content.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            stripped.push_str(line);
+            continue;
+        }
+
+        let mut text = content.trim_start_matches('\t');
+        let indent = &content[..content.len() - text.len()];
+        // a list item inside a quote has both markers
+        while let Some(marker) = line_marker.find(text) {
+            text = &text[marker.end()..];
+        }
+        let depth = indent.len();
+        let number = numbered_marker
+            .captures(text)
+            .and_then(|captures| Some((captures["number"].parse::<u64>().ok()?, captures)))
+            .filter(|(number, _)| {
+                *number == 1
+                    || list_numbers
+                        .get(&depth)
+                        .is_some_and(|last| *number == last + 1)
+            });
+        if let Some((number, captures)) = number {
+            text = &text[captures[0].len()..];
+            // a list nested under an earlier item has ended
+            list_numbers.retain(|list_depth, _| *list_depth < depth);
+            list_numbers.insert(depth, number);
+        } else if !content.is_empty() {
+            list_numbers.remove(&depth);
+        }
+        let text = link.replace_all(text, |captures: &regex::Captures| {
+            let (text, url) = (&captures["text"], &captures["url"]);
+            if text.is_empty() || text == url {
+                url.to_string()
+            } else {
+                format!("{} ({})", text, url)
+            }
+        });
+        let text = inline_marker.replace_all(&text, |captures: &regex::Captures| {
+            ["bold", "bold2", "strike", "code", "italic"]
+                .iter()
+                .find_map(|name| captures.name(name))
+                .map(|text| text.as_str().to_string())
+                .unwrap_or_else(|| {
+                    format!(
+                        "{}{}{}",
+                        &captures["before"], &captures["italic2"], &captures["after"]
+                    )
+                })
+        });
+
+        stripped.push_str(indent);
+        stripped.push_str(&text);
+        stripped.push_str(newline);
+    }
+    stripped
+}
+
+/// How long a Slack message can be before Slack cuts it off
+pub const SLACK_MAX_CHARS: usize = 3000;
+
+/// Converts `markdown` to Slack's "mrkdwn", which has its own markers for inline formatting
+/// (`*bold*`, `_italic_`, `~strike~`) and no headings or list markers. Headings become bold
+/// lines, bullets become `•`, to-dos become `☐` or `☑`, and links become `<url|text>`. Slack
+/// ignores a code block's language, so it's kept as a comment on the block's first line.
+///
+/// `&`, `<`, and `>` are escaped as Slack requires, except for `>` quote markers.
+#[must_use]
+pub fn markdown_to_slack_mrkdwn(markdown: &str) -> String {
+    let (line_marker, inline_marker, link) = markdown_marker_regexes();
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+
+    let mut mrkdwn = String::with_capacity(markdown.len());
+    let mut in_code = false;
+    for line in markdown.split_inclusive('\n') {
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        if let Some(language) = content.trim_start().strip_prefix("```") {
+            mrkdwn.push_str("```");
+            mrkdwn.push_str(newline);
+            if !in_code && !language.is_empty() {
+                mrkdwn.push_str(&format!("{} {}\n", comment_prefix(language), language));
+            }
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            mrkdwn.push_str(&escape(line));
+            continue;
+        }
+
+        let mut text = content.trim_start_matches('\t');
+        let indent = &content[..content.len() - text.len()];
+        let mut markers = String::new();
+        let mut is_heading = false;
+        while let Some(marker) = line_marker.find(text) {
+            match marker.as_str().trim_end() {
+                heading if heading.starts_with('#') => is_heading = true,
+                ">" => markers.push_str("> "),
+                "- [ ]" | "* [ ]" | "+ [ ]" => markers.push_str("☐ "),
+                checked if checked.ends_with(']') => markers.push_str("☑ "),
+                _ => markers.push_str("• "),
+            }
+            text = &text[marker.end()..];
+        }
+        let text = escape(text);
+        let text = link.replace_all(&text, |captures: &regex::Captures| {
+            let (text, url) = (&captures["text"], &captures["url"]);
+            if text.is_empty() || text == url {
+                format!("<{}>", url)
+            } else {
+                format!("<{}|{}>", url, text)
+            }
+        });
+        let text = inline_marker.replace_all(&text, |captures: &regex::Captures| {
+            if let Some(bold) = captures.name("bold").or_else(|| captures.name("bold2")) {
+                // a heading is bold as a whole, and Slack can't nest bold in bold
+                if is_heading {
+                    bold.as_str().to_string()
+                } else {
+                    format!("*{}*", bold.as_str())
+                }
+            } else if let Some(strike) = captures.name("strike") {
+                format!("~{}~", strike.as_str())
+            } else if let Some(italic) = captures.name("italic") {
+                format!("_{}_", italic.as_str())
+            } else {
+                // inline code and `_` italics are the same in mrkdwn
+                captures[0].to_string()
+            }
+        });
+
+        mrkdwn.push_str(indent);
+        mrkdwn.push_str(&markers);
+        if is_heading && !text.is_empty() {
+            mrkdwn.push_str(&format!("*{}*", text));
+        } else {
+            mrkdwn.push_str(&text);
+        }
+        mrkdwn.push_str(newline);
+    }
+    mrkdwn
+}
+
+/// What starts a line comment in `language`, for `markdown_to_slack_mrkdwn`'s language hints
+fn comment_prefix(language: &str) -> &'static str {
+    match language {
+        "python" | "ruby" | "shell" | "bash" | "sh" | "yaml" | "toml" | "r" | "perl"
+        | "powershell" | "makefile" | "dockerfile" | "elixir" => "#",
+        "sql" | "haskell" | "lua" => "--",
+        _ => "//",
+    }
+}
+
+/// Slices `markdown` at the start of every heading of `level` or above, so that concatenating
+/// the sections gives back `markdown`
+fn sections(markdown: &str, level: u8) -> Vec<&str> {
+    let mut starts = vec![0];
+    let mut offset = 0;
+    let mut in_code = false;
+    for line in markdown.split_inclusive('\n') {
+        // fences of nested code Blocks are indented along with the Block
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        } else if !in_code && offset > 0 && heading(line).is_some_and(|(l, _)| l <= level) {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    starts.push(markdown.len());
+
+    starts
+        .windows(2)
+        .map(|bounds| &markdown[bounds[0]..bounds[1]])
+        .filter(|section| !section.is_empty())
+        .collect()
+}
+
+/// The level and text of a markdown ATX heading line, like `## Plans`
+fn heading(line: &str) -> Option<(u8, &str)> {
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    let text = line[hashes..].strip_prefix(' ')?;
+    if (1..=6).contains(&hashes) {
+        Some((hashes as u8, text.trim_end()))
+    } else {
+        None
+    }
+}
+
+/// Serializes a whole tree of `Block`s as nested `{"block": {...}, "children": [...]}` JSON,
+/// so a grown tree can be stored and read back with `deserialize_tree` without refetching it
+#[must_use]
+pub fn serialize_tree(tree: &Tree<Block>) -> serde_json::Value {
+    fn serialize_node(node: &Node<Block>) -> serde_json::Value {
+        serde_json::json!({
+            "block": node.borrow_data().to_json(),
+            "children": node
+                .children()
+                .map(|child| serialize_node(&child))
+                .collect::<Vec<_>>(),
+        })
+    }
+    serialize_node(&tree.root())
+}
+
+/// Reads back a tree written by `serialize_tree`. A node without `children` is a leaf.
+pub fn deserialize_tree(value: serde_json::Value) -> Result<Tree<Block>, DrossError> {
+    fn add_children(
+        node: &Node<Block>,
+        value: &serde_json::Value,
+        grant: &HierarchyEditGrant<Block>,
+    ) -> Result<(), DrossError> {
+        for child_value in value["children"].as_array().into_iter().flatten() {
+            let child = node.create_as_last_child(grant, Block::from_json(&child_value["block"])?);
+            add_children(&child, child_value, grant)?;
+        }
+        Ok(())
+    }
+
+    let root = Node::new_tree(Block::from_json(&value["block"])?);
+    let grant = root
+        .tree()
+        .grant_hierarchy_edit()
+        .expect("nothing else can be editing a tree we just created");
+    add_children(&root, &value, &grant)?;
+    Ok(root.tree())
+}
+
+/// Finds every node in `tree` whose `Block`'s text contains `query`, in depth-first order. The
+/// nodes are returned rather than `Ref`s to their `Block`s, since a `Ref` can't outlive the
+/// traversal, and a node can still be borrowed, or used to look at its parent and children.
+#[must_use]
+pub fn find_blocks_by_text(
+    tree: &BlockTree,
+    query: &str,
+    case_sensitive: bool,
+) -> Vec<Node<Block>> {
+    let query = if case_sensitive {
+        query.to_string()
+    } else {
+        query.to_lowercase()
+    };
+
+    let mut matches = Vec::new();
+    let mut stack = vec![tree.root()];
+    while let Some(node) = stack.pop() {
+        let is_match = {
+            let text = &node.borrow_data().text;
+            if case_sensitive {
+                text.contains(&query)
+            } else {
+                text.to_lowercase().contains(&query)
+            }
+        };
+        let children = node.children().collect::<Vec<_>>();
+        stack.extend(children.into_iter().rev());
+        if is_match {
+            matches.push(node);
+        }
+    }
+    matches
+}
+
+/// Fuzzy matches `query` against the title of each item, returning the items that matched
+/// along with their match scores, best match first
+pub fn fuzzy_rank<T>(items: Vec<T>, query: &str, title: impl Fn(&T) -> &str) -> Vec<(i64, T)> {
+    let matcher = SkimMatcherV2::default();
+    let mut ranked = items
+        .into_iter()
+        .filter_map(|item| {
+            matcher
+                .fuzzy_match(title(&item), query)
+                .map(|score| (score, item))
+        })
+        .collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.0.cmp(&a.0));
+    ranked
+}
+
+/// How many characters of `Block` text to log when `DROSS_LOG_TEXT_MAX` isn't set
+const DEFAULT_LOG_TEXT_MAX: usize = 80;
+
+/// How many characters of `Block` text to log, read from `DROSS_LOG_TEXT_MAX` the first time
+/// it's needed. Code Blocks and callouts can hold kilobytes of text that would flood the log.
+pub fn log_text_max() -> usize {
+    static LOG_TEXT_MAX: OnceLock<usize> = OnceLock::new();
+    *LOG_TEXT_MAX.get_or_init(|| {
+        env::var("DROSS_LOG_TEXT_MAX")
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .unwrap_or(DEFAULT_LOG_TEXT_MAX)
+    })
+}
+
+/// The first `max_chars` characters of `s`, followed by "…" if anything was cut off
+#[must_use]
+pub fn truncate_for_log(s: &str, max_chars: usize) -> String {
+    match s.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &s[..end]),
+        None => s.to_string(),
+    }
+}
+
+/// Guesses the language of a code snippet from a few telltale keywords, for code `Block`s
+/// the user didn't pick a language for
+#[must_use]
+pub fn detect_language(code: &str) -> Option<&'static str> {
+    if code.starts_with("fn ") || code.contains("let mut") {
+        Some("rust")
+    } else if code.contains("def ") && code.contains("import ") {
+        Some("python")
+    } else if code.contains("function ") && code.contains("const ") {
+        Some("javascript")
+    } else {
+        None
+    }
+}
+
+/// The `blocks` of the Page with ID `page_id` whose `parent_block_id` isn't the ID of any other
+/// of them. Blocks directly on the Page have no `parent_block_id`, so they're never orphans.
+#[must_use]
+pub fn find_orphans(blocks: &[Block], page_id: &str) -> Vec<Block> {
+    let ids = blocks
+        .iter()
+        .map(|block| block.id.as_str())
+        .chain([page_id])
+        .collect::<HashSet<&str>>();
+    blocks
+        .iter()
+        .filter(|block| {
+            block
+                .parent_block_id
+                .as_deref()
+                .is_some_and(|parent_id| !ids.contains(parent_id))
+        })
+        .cloned()
+        .collect()
+}
+
+/// How much of each Block's text `block_tree_lines` shows
+const TREE_LINE_TEXT_CHARS: usize = 40;
+
+/// One Block's line in the drawing made by `block_tree_lines`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeLine {
+    /// The `├── ` / `└── ` connector, preceded by the `│` rails of the Block's ancestors
+    pub prefix: String,
+    pub block_id: BlockID,
+    pub type_name: String,
+    /// The start of the Block's text, on one line
+    pub text: String,
+}
+
+impl std::fmt::Display for TreeLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{} {} {}",
+            self.prefix, self.type_name, self.block_id, self.text
+        )
+    }
+}
+
+/// Draws `trees` the way the Unix `tree` command draws directories, one line per Block with
+/// each tree's root as a top-level entry, for seeing how a Page's Blocks were grown
+#[must_use]
+pub fn block_tree_lines(trees: &[BlockTree]) -> Vec<TreeLine> {
+    fn add_lines(node: &Node<Block>, rails: &str, is_last: bool, lines: &mut Vec<TreeLine>) {
+        let block = node.borrow_data();
+        lines.push(TreeLine {
+            prefix: format!("{}{}", rails, if is_last { "└── " } else { "├── " }),
+            block_id: block.id.clone(),
+            type_name: block.type_name(),
+            text: truncate_for_log(
+                &block.text.split_whitespace().collect::<Vec<_>>().join(" "),
+                TREE_LINE_TEXT_CHARS,
+            ),
+        });
+        let child_rails = format!("{}{}", rails, if is_last { "    " } else { "│   " });
+        let children = node.children().collect::<Vec<_>>();
+        for (i, child) in children.iter().enumerate() {
+            add_lines(child, &child_rails, i + 1 == children.len(), lines);
+        }
+    }
+
+    let mut lines = Vec::new();
+    for (i, tree) in trees.iter().enumerate() {
+        add_lines(&tree.root(), "", i + 1 == trees.len(), &mut lines);
+    }
+    lines
+}
+
+/// The model whose tokenizer counts tokens when no other is given
+pub const DEFAULT_TOKEN_MODEL: &str = "gpt-4o";
+
+/// What separates the Pages in `ContextWindow::to_prompt`, unless told otherwise
+const CONTEXT_WINDOW_PAGE_SEPARATOR: &str = "\n\n";
+
+/// Assembles a prompt out of as many Pages as fit within an LLM's token limit, counting each
+/// Page's tokens with the tokenizer `model` uses as it's added. Each Page is added as it's
+/// rendered in the output, so the prompt is exactly the rendered Pages joined together.
+pub struct ContextWindow {
+    pub max_tokens: usize,
+    pub model: String,
+    pub pages: Vec<(Page, String)>,
+    separator: String,
+    bpe: CoreBPE,
+    used_tokens: usize,
+}
+
+impl ContextWindow {
+    /// An empty window of `max_tokens` tokens. Models `tiktoken` doesn't know are counted with
+    /// `cl100k_base`, which is close enough for every recent OpenAI-style tokenizer.
+    #[must_use]
+    pub fn new(max_tokens: usize, model: impl Into<String>) -> Self {
+        let model = model.into();
+        let bpe = get_bpe_from_model(&model).unwrap_or_else(|_| {
+            debug!(target: "notion", "no tokenizer for model {}, counting tokens with cl100k_base", model);
+            cl100k_base().expect("the bundled cl100k_base ranks are valid")
+        });
+        ContextWindow {
+            max_tokens,
+            model,
+            pages: Vec::new(),
+            separator: CONTEXT_WINDOW_PAGE_SEPARATOR.to_string(),
+            bpe,
+            used_tokens: 0,
+        }
+    }
+
+    /// Joins the Pages with `separator` rather than a blank line
+    #[must_use]
+    pub fn with_separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Adds `page`, rendered as `text`, to the end of the prompt, unless that would take it
+    /// over `max_tokens`, in which case the window is left as it was and false is returned
+    pub fn add_page(&mut self, page: Page, text: String) -> bool {
+        let mut tokens = self.count_tokens(&text);
+        if !self.pages.is_empty() {
+            tokens += self.count_tokens(&self.separator);
+        }
+        if self.used_tokens + tokens > self.max_tokens {
+            return false;
+        }
+
+        self.used_tokens += tokens;
+        self.pages.push((page, text));
+        true
+    }
+
+    /// Empties the window, keeping its budget and tokenizer
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.used_tokens = 0;
+    }
+
+    /// How many tokens `text` is to `model`
+    #[must_use]
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+
+    #[must_use]
+    pub fn remaining_tokens(&self) -> usize {
+        self.max_tokens - self.used_tokens
+    }
+
+    /// Every Page's text, in the order they were added
+    #[must_use]
+    pub fn to_prompt(&self) -> String {
+        self.pages
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect::<Vec<&str>>()
+            .join(&self.separator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::datatypes::BlockTreeBuilder;
+
+    #[test]
+    fn test_markdown_to_blocks_maps_each_element_to_a_block_type() {
+        let markdown = "# Title\n\nSome *emphasized*\ntext\n\n- one\n- two\n  1. nested\n\n> quoted\n\n```rust\nfn main() {}\n```\n";
+
+        let blocks = markdown_to_blocks(markdown);
+
+        let types_and_text = blocks
+            .iter()
+            .map(|block| (block.type_name(), block.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types_and_text,
+            vec![
+                ("heading_1".to_string(), "Title"),
+                ("paragraph".to_string(), "Some emphasized text"),
+                ("bulleted_list_item".to_string(), "one"),
+                ("bulleted_list_item".to_string(), "two"),
+                ("numbered_list_item".to_string(), "nested"),
+                ("quote".to_string(), "quoted"),
+                ("code".to_string(), "fn main() {}"),
+            ]
+        );
+        assert_eq!(blocks[6].to_markdown(), "```rust\nfn main() {}\n```");
+    }
+
+    #[test]
+    fn test_markdown_to_blocks_splits_long_text_into_rich_text_runs() {
+        let text = "a".repeat(2001);
+
+        let blocks = markdown_to_blocks(&text);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].text, text);
+        let block_type = serde_json::to_value(&blocks[0].block_type).unwrap();
+        let runs = block_type["paragraph"]["rich_text"].as_array().unwrap();
+        let contents = runs
+            .iter()
+            .map(|run| run["text"]["content"].as_str().unwrap().len())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, vec![2000, 1]);
+    }
+
+    #[test]
+    fn test_numbered_list_numbering_restarts_after_other_blocks() {
+        let tree = BlockTreeBuilder::new("Root")
+            .child(BlockTreeBuilder::numbered("first"))
+            .child(BlockTreeBuilder::numbered("second"))
+            .child(BlockTreeBuilder::new("interruption"))
+            .child(BlockTreeBuilder::numbered("first again"))
+            .build();
+
+        let markdown = build_markdown_from_trees(vec![tree]);
+
+        assert_eq!(
+            markdown,
+            "Root\n\t1. first\n\t2. second\n\tinterruption\n\t1. first again\n"
+        );
+    }
+
+    #[test]
+    fn test_numbered_list_roots_are_numbered_in_order() {
+        let trees = vec![
+            BlockTreeBuilder::numbered("first").build(),
+            BlockTreeBuilder::numbered("second")
+                .child(BlockTreeBuilder::numbered("nested"))
+                .build(),
+            BlockTreeBuilder::numbered("third").build(),
+            BlockTreeBuilder::new("interruption").build(),
+            BlockTreeBuilder::numbered("first again").build(),
+        ];
+
+        let markdown = build_markdown_from_trees(trees);
+
+        assert_eq!(
+            markdown,
+            "1. first\n2. second\n\t1. nested\n3. third\ninterruption\n1. first again\n"
+        );
+    }
+
+    #[test]
+    fn test_multi_line_blocks_are_indented_on_every_line() {
+        let code = markdown_to_blocks("```rust\nfn main() {\n    run();\n\n}\n```").remove(0);
+        let tree = BlockTreeBuilder::new("Root")
+            .child(BlockTreeBuilder::new(&code.text).block_type(code.block_type))
+            .build();
+
+        let markdown = build_markdown_from_trees(vec![tree]);
+
+        assert_eq!(
+            markdown,
+            "Root\n\t```rust\n\tfn main() {\n\t    run();\n\n\t}\n\t```\n"
+        );
+    }
+
+    #[test]
+    fn test_block_in_multiple_trees_is_only_written_once() {
+        let parent = BlockTreeBuilder::new("parent")
+            .child(BlockTreeBuilder::new("child").id("b"))
+            .build();
+        let child_edited_later = BlockTreeBuilder::new("child")
+            .id("b")
+            .updated_at(Utc::now() + chrono::Duration::seconds(1))
+            .build();
+
+        let markdown = build_markdown_from_trees(vec![parent, child_edited_later]);
+
+        assert_eq!(markdown, "parent\n\tchild\n");
+    }
+
+    #[test]
+    fn test_two_column_layout_is_written_one_column_after_another() {
+        let block_type = |type_name: &str| -> BlockType {
+            serde_json::from_value(serde_json::json!({"type": type_name, type_name: {}})).unwrap()
+        };
+        let layout = || {
+            BlockTreeBuilder::new("Root").child(
+                BlockTreeBuilder::new("")
+                    .block_type(block_type("column_list"))
+                    .child(
+                        BlockTreeBuilder::new("")
+                            .block_type(block_type("column"))
+                            .child(BlockTreeBuilder::new("left")),
+                    )
+                    .child(
+                        BlockTreeBuilder::new("")
+                            .block_type(block_type("column"))
+                            .child(
+                                BlockTreeBuilder::new("right")
+                                    .child(BlockTreeBuilder::new("nested")),
+                            ),
+                    ),
+            )
+        };
+
+        assert_eq!(
+            build_markdown_from_trees(vec![layout().build()]),
+            "Root\n\tleft\n\t---column---\n\tright\n\t\tnested\n"
+        );
+
+        let mut registry = BlockTypeRegistry::default();
+        registry.set_column_separator("|||");
+        assert_eq!(
+            build_markdown_from_trees_with_registry(vec![layout().build()], &registry),
+            "Root\n\tleft\n\t|||\n\tright\n\t\tnested\n"
+        );
+    }
+
+    #[test]
+    fn test_structural_duplicate_check_ignores_timestamps_but_not_text() {
+        let original = BlockTreeBuilder::new("draft").id("a").build();
+        let touched = BlockTreeBuilder::new("draft")
+            .id("a")
+            .updated_at(Utc::now() + chrono::Duration::seconds(1))
+            .build();
+        let rewritten = BlockTreeBuilder::new("final").id("a").build();
+        assert!(original
+            .root_block()
+            .structurally_equal(&touched.root_block()));
+
+        let markdown = build_markdown_from_trees_with_duplicate_check(
+            vec![original, touched, rewritten],
+            BlockTypeRegistry::global(),
+            DuplicateCheck::Structural,
+        );
+
+        assert_eq!(markdown, "draft\nfinal\n");
+    }
+
+    #[test]
+    fn test_fuzzy_rank() {
+        let titles = vec![
+            "Q3 Planning Meeting",
+            "Grocery List",
+            "Weekly meeting notes",
+        ];
+
+        let ranked = fuzzy_rank(titles, "meeting", |title| title);
+
+        assert_eq!(ranked.len(), 2);
+        assert!(ranked
+            .iter()
+            .all(|(_, title)| title.to_lowercase().contains("meeting")));
+        assert!(ranked[0].0 >= ranked[1].0);
+    }
+
+    #[test]
+    fn test_detect_language_rust() {
+        assert_eq!(detect_language("fn main() {}"), Some("rust"));
+        assert_eq!(detect_language("let mut count = 0;"), Some("rust"));
+    }
+
+    #[test]
+    fn test_detect_language_python() {
+        assert_eq!(
+            detect_language("import os\n\ndef main():\n    pass"),
+            Some("python")
+        );
+        // a def on its own isn't enough to go on
+        assert_eq!(detect_language("def main():\n    pass"), None);
+    }
+
+    #[test]
+    fn test_detect_language_javascript() {
+        assert_eq!(
+            detect_language("const x = 1;\nfunction f() { return x; }"),
+            Some("javascript")
+        );
+    }
+
+    #[test]
+    fn test_detect_language_unknown() {
+        assert_eq!(detect_language("SELECT * FROM pages;"), None);
+    }
+
+    #[test]
+    fn test_text_sanitizer_replaces_uuids() {
+        let sanitizer = TextSanitizer::default();
+
+        assert_eq!(
+            sanitizer.sanitize("see 7b1b3b0c-14cb-45a6-a4b6-D2B48FAECCCB for details"),
+            "see [ref] for details"
+        );
+        assert_eq!(
+            sanitizer.sanitize("copied from 7b1b3b0c14cb45a6a4b6d2b48faecccb"),
+            "copied from [ref]"
+        );
+    }
+
+    #[test]
+    fn test_text_sanitizer_removes_ids_and_leaves_other_hex_alone() {
+        let sanitizer = TextSanitizer::new("");
+
+        assert_eq!(
+            sanitizer.sanitize("commit deadbeef, id 7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb"),
+            "commit deadbeef, id "
+        );
+    }
+
+    #[test]
+    fn test_text_sanitizer_sanitizes_whole_tree() {
+        let tree = BlockTreeBuilder::new("parent 7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb")
+            .child(BlockTreeBuilder::new(
+                "child 7b1b3b0c14cb45a6a4b6d2b48faecccb",
+            ))
+            .build();
+
+        TextSanitizer::default().sanitize_tree(&tree);
+
+        assert_eq!(
+            build_markdown_from_trees(vec![tree]),
+            "parent [ref]\n\tchild [ref]\n"
+        );
+    }
+
+    fn page(id: &str) -> Page {
+        Page::for_test(id, id)
+    }
+
+    fn child_page(id: &str, parent_id: &str) -> Page {
+        Page {
+            parent_page_id: Some(parent_id.to_string()),
+            ..page(id)
+        }
+    }
+
+    #[test]
+    fn test_sort_blocks_by_time_keeps_ties_in_order() {
+        use chrono::TimeZone;
+
+        let at = |hour| Utc.with_ymd_and_hms(2024, 8, 1, hour, 0, 0).unwrap();
+        let block = |text: &str, created, updated| Block {
+            creation_date: at(created),
+            update_date: at(updated),
+            ..Block::from_text(text)
+        };
+        let blocks = vec![
+            block("b", 2, 3),
+            block("a", 1, 5),
+            block("c", 2, 1),
+            block("d", 1, 3),
+        ];
+        let texts = |blocks: &[Block]| {
+            blocks
+                .iter()
+                .map(|block| block.text.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut by_creation = blocks.clone();
+        sort_blocks_by_creation(&mut by_creation);
+        let mut by_update = blocks.clone();
+        sort_blocks_by_update(&mut by_update);
+
+        // Blocks created or updated at the same time stay in the order they were in
+        assert_eq!(texts(&by_creation), vec!["a", "d", "b", "c"]);
+        assert_eq!(texts(&by_update), vec!["c", "b", "d", "a"]);
+    }
+
+    #[test]
+    fn test_sort_pages_hierarchically() {
+        let pages = vec![
+            child_page("grandchild", "child-a"),
+            child_page("child-b", "root"),
+            page("unrelated"),
+            // parents outside of the Pages being sorted are ignored
+            child_page("root", "not-edited"),
+            child_page("child-a", "root"),
+        ];
+        let graph = WorkspaceGraph::from_pages(pages.clone());
+
+        let sorted = sort_pages_hierarchically(pages, &graph);
+        let ids = sorted
+            .iter()
+            .map(|page| page.id.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            ids,
+            vec!["unrelated", "root", "child-b", "child-a", "grandchild"]
+        );
+    }
+
+    #[test]
+    fn test_sort_pages_hierarchically_keeps_pages_in_a_cycle() {
+        let pages = vec![child_page("a", "b"), child_page("b", "a")];
+        let graph = WorkspaceGraph::from_pages(pages.clone());
+
+        let sorted = sort_pages_hierarchically(pages, &graph);
+
+        assert_eq!(sorted.len(), 2);
+    }
+
+    #[test]
+    fn test_group_pages_by_parent() {
+        let daily_notes = page("daily-notes");
+        let pages = vec![
+            child_page("monday", "daily-notes"),
+            page("unrelated"),
+            child_page("tuesday", "daily-notes"),
+            // the parent wasn't fetched, so there's nothing to group under
+            child_page("orphan", "not-fetched"),
+        ];
+        let graph = WorkspaceGraph::from_pages(pages.iter().cloned().chain([daily_notes]));
+
+        let groups = group_pages_by_parent(pages, &graph)
+            .into_iter()
+            .map(|(parent, pages)| {
+                (
+                    parent.map(|parent| parent.id),
+                    pages.into_iter().map(|page| page.id).collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            groups,
+            vec![
+                (
+                    Some("daily-notes".to_string()),
+                    vec!["monday".to_string(), "tuesday".to_string()]
+                ),
+                (None, vec!["unrelated".to_string(), "orphan".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tree_json_round_trip() {
+        let tree = BlockTreeBuilder::new("root")
+            .child(BlockTreeBuilder::new("child").child(BlockTreeBuilder::new("grandchild")))
+            .child(BlockTreeBuilder::new("second child"))
+            .build()
+            .root()
+            .tree();
+
+        let json = serialize_tree(&tree);
+        assert_eq!(json["block"]["text"], "root");
+        assert_eq!(
+            json["children"][0]["children"][0]["block"]["text"],
+            "grandchild"
+        );
+
+        let round_tripped = BlockTree::from(deserialize_tree(json).unwrap());
+        let texts = round_tripped
+            .flatten_with_depth()
+            .into_iter()
+            .map(|(block, depth)| (block.text, depth))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                ("root".to_string(), 0),
+                ("child".to_string(), 1),
+                ("grandchild".to_string(), 2),
+                ("second child".to_string(), 1),
+            ]
+        );
+        assert!(deserialize_tree(serde_json::json!({"children": []})).is_err());
+    }
+
+    #[test]
+    fn test_split_by_heading() {
+        let markdown = "intro\n# One\nfirst\n## One A\n```\n# not a heading\n```\n# Two\nsecond\n";
+
+        assert_eq!(
+            split_by_heading(markdown, 1),
+            vec![
+                (String::new(), "intro\n".to_string()),
+                (
+                    "One".to_string(),
+                    "first\n## One A\n```\n# not a heading\n```\n".to_string()
+                ),
+                ("Two".to_string(), "second\n".to_string()),
+            ]
+        );
+        assert_eq!(split_by_heading(markdown, 2).len(), 4);
+    }
+
+    #[test]
+    fn test_split_into_chunks_keeps_sections_whole() {
+        let markdown = "# A\naaaa\n# B\nbb\n# C\nc very long section\n";
+
+        let chunks = split_into_chunks(markdown, 16);
+
+        assert_eq!(
+            chunks,
+            vec!["# A\naaaa\n# B\nbb\n", "# C\nc very long section\n"]
+        );
+        assert_eq!(chunks.concat(), markdown);
+    }
+
+    #[test]
+    fn test_sections_skip_headings_in_indented_code() {
+        let markdown = "# A\n- item\n\t```python\n# not a heading\n\t```\n# B\nb\n";
+
+        assert_eq!(
+            split_into_chunks(markdown, 1),
+            vec![
+                "# A\n- item\n\t```python\n# not a heading\n\t```\n",
+                "# B\nb\n"
+            ]
+        );
+        assert_eq!(
+            last_section_start(markdown),
+            Some(markdown.find("# B").unwrap())
+        );
+        assert_eq!(last_section_start("# B\nb\n"), None);
+    }
+
+    #[test]
+    fn test_block_tree_lines() {
+        let first = BlockTreeBuilder::new("Groceries")
+            .id("a")
+            .child(
+                BlockTreeBuilder::new("fruit")
+                    .id("a1")
+                    .child(BlockTreeBuilder::new("apples").id("a1x")),
+            )
+            .child(
+                BlockTreeBuilder::new("a very long line about bread that goes on and on").id("a2"),
+            )
+            .build();
+        let second = BlockTreeBuilder::new("Chores").id("b").build();
+
+        let lines = block_tree_lines(&[first, second])
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            lines,
+            vec![
+                "├── paragraph a Groceries",
+                "│   ├── paragraph a1 fruit",
+                "│   │   └── paragraph a1x apples",
+                "│   └── paragraph a2 a very long line about bread that goes o…",
+                "└── paragraph b Chores",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrap_markdown() {
+        let markdown = "- short\n\t- one two three **four five** six\n```\nlet code = \"is never wrapped at all\";\n```\n";
+
+        assert_eq!(
+            wrap_markdown(markdown, 20),
+            "- short\n\t- one two three\n\t**four five** six\n```\nlet code = \"is never wrapped at all\";\n```\n"
+        );
+        assert_eq!(wrap_markdown("", 20), "");
+    }
+
+    #[test]
+    fn test_strip_markdown() {
+        let markdown = "# Plans\n- **ship** the _new_ release\n\t1. fix ~~all~~ the `snake_case` bugs\n> - [ ] read [the docs](https://docs.rs)\n```rust\n# not a heading\n```\n";
+
+        assert_eq!(
+            strip_markdown(markdown),
+            "Plans\nship the new release\n\tfix all the snake_case bugs\nread the docs (https://docs.rs)\n# not a heading\n"
+        );
+        assert_eq!(strip_markdown("see https://docs.rs"), "see https://docs.rs");
+    }
+
+    #[test]
+    fn test_strip_markdown_only_removes_list_numbering() {
+        assert_eq!(
+            strip_markdown("1. first\n\t1. nested\n2. second\n2024. was busy\n"),
+            "first\n\tnested\nsecond\n2024. was busy\n"
+        );
+    }
+
+    #[test]
+    fn test_markdown_to_slack_mrkdwn() {
+        let markdown = "# Plans\n- **ship** the _new_ release\n\t1. fix ~~all~~ the `snake_case` bugs\n> - [ ] read [the docs](https://docs.rs) & <more>\n```rust\nlet x = a < b;\n```\n";
+
+        assert_eq!(
+            markdown_to_slack_mrkdwn(markdown),
+            "*Plans*\n• *ship* the _new_ release\n\t1. fix ~all~ the `snake_case` bugs\n> ☐ read <https://docs.rs|the docs> &amp; &lt;more&gt;\n```\n// rust\nlet x = a &lt; b;\n```\n"
+        );
+        assert_eq!(
+            markdown_to_slack_mrkdwn("## The **big** launch\n"),
+            "*The big launch*\n"
+        );
+    }
+
+    #[test]
+    fn test_find_blocks_by_text() {
+        let tree = BlockTreeBuilder::new("Meeting notes")
+            .id("root")
+            .child(
+                BlockTreeBuilder::new("follow up with the NOTES team")
+                    .id("a")
+                    .child(BlockTreeBuilder::new("nothing to see").id("a1")),
+            )
+            .child(BlockTreeBuilder::new("more notes").id("b"))
+            .build();
+        let ids = |nodes: Vec<Node<Block>>| {
+            nodes
+                .iter()
+                .map(|node| node.borrow_data().id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ids(find_blocks_by_text(&tree, "notes", false)),
+            vec!["root", "a", "b"]
+        );
+        assert_eq!(
+            ids(find_blocks_by_text(&tree, "notes", true)),
+            vec!["root", "b"]
+        );
+        assert!(find_blocks_by_text(&tree, "missing", false).is_empty());
+    }
+
+    #[test]
+    fn test_find_orphans() {
+        let tree = BlockTreeBuilder::new("parent")
+            .child(BlockTreeBuilder::new("child"))
+            .build();
+        let mut blocks = tree.flatten();
+        let orphan = Block {
+            parent_block_id: Some("deleted-block".to_string()),
+            ..Block::from_text("orphan")
+        };
+        blocks.push(orphan.clone());
+
+        let orphans = find_orphans(&blocks, &orphan.page_id);
+
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].id, orphan.id);
+    }
+
+    #[test]
+    fn test_truncate_for_log() {
+        assert_eq!(truncate_for_log("short", 80), "short");
+        assert_eq!(truncate_for_log("exactly", 7), "exactly");
+        assert_eq!(truncate_for_log("truncated text", 9), "truncated…");
+        // multi-byte characters are counted as one character each
+        assert_eq!(truncate_for_log("漢字とかな", 2), "漢字…");
+    }
+
+    #[test]
+    fn test_context_window_skips_pages_over_budget() {
+        let mut window = ContextWindow::new(30, "gpt-4o");
+
+        assert!(window.add_page(page("Journal"), "Slept well".to_string()));
+        let after_first = window.remaining_tokens();
+        assert!(after_first < 30);
+        assert!(!window.add_page(page("Essay"), "word ".repeat(100)));
+        assert_eq!(window.remaining_tokens(), after_first);
+        assert!(window.add_page(page("Inbox"), "Call the bank".to_string()));
+
+        assert_eq!(window.to_prompt(), "Slept well\n\nCall the bank");
+
+        window.clear();
+        assert_eq!(window.remaining_tokens(), 30);
+        assert_eq!(window.to_prompt(), "");
     }
 }
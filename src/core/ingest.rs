@@ -0,0 +1,483 @@
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, info, warn};
+use notion_client::objects::block::{BlockType, LinkToPageValue};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+use tokio::time::Instant;
+
+use super::{
+    datatypes::{BlockTree, Page, PageID, PageOutput, PromptContext, PropertyValue},
+    error::DrossError,
+    graph::WorkspaceGraph,
+    helpers::{
+        build_markdown_from_trees_with_duplicate_check, group_pages_by_parent, log_text_max,
+        sort_pages_hierarchically, truncate_for_log, BlockSort, DuplicateCheck, PageOrder,
+        TextSanitizer,
+    },
+    markdown::{BlockTypeRegistry, DEFAULT_COLUMN_SEPARATOR},
+    page_map::LazyPageMap,
+    performance::PerformanceReport,
+    search_cache::SearchCache,
+    selector::{can_select_interactively, select_pages_interactively},
+    state::{page_map_path, search_cache_path},
+};
+use crate::notion::Notion;
+
+/// Knobs for a single run of `ingest_notion`
+#[derive(Debug, Clone)]
+pub struct IngestOptions {
+    /// How far back to look for edited Pages and Blocks
+    pub dur: Duration,
+    pub sort_blocks: BlockSort,
+    pub page_order: PageOrder,
+    /// How old the cached page map can get before it is refetched
+    pub page_map_ttl: Duration,
+    /// Refetch the page map even if the cached one is still fresh
+    pub refresh_page_map: bool,
+    /// Where the page map is cached between runs
+    pub page_map_path: PathBuf,
+    /// How long the result of searching for recently edited Pages is reused by later runs
+    /// with the same `dur`. Zero turns the cache off.
+    pub search_cache_ttl: Duration,
+    /// Where recent Page searches are cached between runs
+    pub search_cache_path: PathBuf,
+    /// Only use the N most recently edited Pages within `dur`
+    pub last_n_pages: Option<usize>,
+    /// Leave out the Pages last edited after this, see `Notion::search_by_date_range`. The
+    /// search cache isn't used when it's set.
+    pub until: Option<DateTime<Utc>>,
+    /// Select Pages created within `dur`, rather than edited within it
+    pub select_by_creation: bool,
+    /// Only include Pages created by the Notion user with this ID
+    pub created_by: Option<String>,
+    /// Only include Pages whose `tag_property` multi-select has every one of these values
+    pub page_tags: Vec<String>,
+    /// The multi-select property `page_tags` are looked for in
+    pub tag_property: Option<String>,
+    /// Prepend each database row Page's markdown with its properties as YAML frontmatter
+    pub include_properties: bool,
+    /// Replace Notion Page and Block IDs in Block text with `[ref]`
+    pub sanitize_ids: bool,
+    /// Skip growing the recently edited `Block`s into trees, so only their own text is output
+    pub blocks_only: bool,
+    /// Let the user pick which of the selected Pages to process from a checklist, if there's
+    /// a terminal to show it in
+    pub interactive: bool,
+    /// How many levels deeper to put Notion's headings, see `BlockTypeRegistry::set_heading_offset`
+    pub heading_offset: i8,
+    /// The line written between the columns of a multi-column layout, see
+    /// `BlockTypeRegistry::set_column_separator`
+    pub column_separator: String,
+    /// How a Block that shows up in more than one grown tree is recognized as already written
+    pub duplicate_check: DuplicateCheck,
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        IngestOptions {
+            dur: Duration::days(7),
+            sort_blocks: BlockSort::None,
+            page_order: PageOrder::Edited,
+            page_map_ttl: Duration::hours(24),
+            refresh_page_map: false,
+            page_map_path: page_map_path(),
+            search_cache_ttl: Duration::zero(),
+            search_cache_path: search_cache_path(),
+            last_n_pages: None,
+            until: None,
+            select_by_creation: false,
+            created_by: None,
+            page_tags: Vec::new(),
+            tag_property: None,
+            include_properties: false,
+            sanitize_ids: false,
+            blocks_only: false,
+            interactive: false,
+            heading_offset: 0,
+            column_separator: DEFAULT_COLUMN_SEPARATOR.to_string(),
+            duplicate_check: DuplicateCheck::ById,
+        }
+    }
+}
+
+/// Fetches every Notion Page edited within `options.dur`, grows the recently edited `Block`s
+/// of each into trees, and returns the markdown for each of them, ready to be put in a prompt.
+pub async fn ingest_notion(
+    notion: &Notion,
+    options: &IngestOptions,
+) -> Result<PromptContext, DrossError> {
+    let dur = options.dur;
+    let mut performance = PerformanceReport::default();
+    let api_calls_before = notion.api_calls_made();
+    let (cache_hits_before, cache_misses_before) = (notion.cache_hits(), notion.cache_misses());
+    let mut stage_start = Instant::now();
+    let mut page_map = LazyPageMap::new(
+        options.page_map_path.clone(),
+        options.page_map_ttl,
+        options.refresh_page_map,
+    );
+
+    let mut pages_edited_within_dur = if options.select_by_creation {
+        let mut pages = notion.get_pages_created_after(Utc::now() - dur).await?;
+        if let Some(n) = options.last_n_pages {
+            pages.sort_by(|a, b| b.creation_date.cmp(&a.creation_date));
+            pages.truncate(n);
+        }
+        pages
+    } else if let Some(until) = options.until {
+        let mut pages = notion.search_by_date_range(Utc::now() - dur, until).await?;
+        if let Some(n) = options.last_n_pages {
+            pages.truncate(n);
+        }
+        pages
+    } else if options.search_cache_ttl > Duration::zero() {
+        SearchCache::load_or_search(
+            notion,
+            &options.search_cache_path,
+            dur,
+            options.last_n_pages,
+            options.search_cache_ttl,
+        )
+        .await?
+    } else if let Some(n) = options.last_n_pages {
+        notion.get_last_n_edited_pages(dur, n).await?
+    } else {
+        notion.get_last_edited_pages(dur).await?
+    };
+    if let Some(user_id) = &options.created_by {
+        pages_edited_within_dur.retain(|page| &page.created_by_id == user_id);
+    }
+    if let Some(property) = &options.tag_property {
+        if !options.page_tags.is_empty() {
+            pages_edited_within_dur = filter_pages_by_tags(
+                notion,
+                pages_edited_within_dur,
+                property,
+                &options.page_tags,
+            )
+            .await?;
+        }
+    }
+    info!(target: "notion", "retrieved {} Pages {} in the last {} days", pages_edited_within_dur.len(), if options.select_by_creation { "created" } else { "edited" }, dur.num_days());
+    performance.record_stage("List Pages", stage_start.elapsed());
+    if options.interactive {
+        if can_select_interactively() {
+            pages_edited_within_dur = select_pages_interactively(pages_edited_within_dur)?;
+            info!(target: "notion", "{} Pages were selected", pages_edited_within_dur.len());
+        } else {
+            info!(target: "notion", "not running in a terminal, processing every Page without asking");
+        }
+    }
+    // waiting on the user to pick Pages isn't part of any stage
+    stage_start = Instant::now();
+    let mut parent_titles: HashMap<PageID, String> = HashMap::new();
+    match options.page_order {
+        PageOrder::Edited => {}
+        PageOrder::Hierarchical => {
+            let graph = WorkspaceGraph::from_pages(pages_edited_within_dur.clone());
+            pages_edited_within_dur = sort_pages_hierarchically(pages_edited_within_dur, &graph);
+        }
+        PageOrder::Grouped if !notion.feature_set().supports_parent_filtering => {
+            warn!(target: "notion", "Notion API version {} doesn't report Page parents, so Pages won't be grouped", notion.api_version());
+        }
+        PageOrder::Grouped => {
+            let graph = graph_with_parents(notion, pages_edited_within_dur.clone()).await?;
+            pages_edited_within_dur = group_pages_by_parent(pages_edited_within_dur, &graph)
+                .into_iter()
+                .flat_map(|(parent, pages)| {
+                    if let Some(parent) = parent {
+                        for page in &pages {
+                            parent_titles.insert(page.id.clone(), parent.title.clone());
+                        }
+                    }
+                    pages
+                })
+                .collect();
+        }
+    }
+
+    performance.record_stage("Order Pages", stage_start.elapsed());
+
+    // pagination should never hand us the same Page twice, but if it ever does we'd
+    // fetch and output all of its Blocks twice, so guard against it here
+    let mut processed_page_ids: HashSet<PageID> = HashSet::new();
+    let mut pages_and_block_roots = Vec::new();
+    for page in pages_edited_within_dur {
+        if !processed_page_ids.insert(page.id.clone()) {
+            warn!(target: "notion", "Page {} was returned more than once, skipping it", page.id);
+            continue;
+        }
+        debug!(target: "notion", "Page URL: {}", page.url);
+
+        let page_start = Instant::now();
+        let mut new_block_roots = notion.get_page_block_roots(&page, dur).await?;
+        performance
+            .page_block_root_timings
+            .push((page.title.clone(), page_start.elapsed()));
+        options.sort_blocks.apply(&mut new_block_roots);
+        pages_and_block_roots.push((page, new_block_roots));
+    }
+    let block_roots_duration = performance
+        .page_block_root_timings
+        .iter()
+        .map(|(_, duration)| *duration)
+        .sum();
+    performance.record_stage("Find block roots", block_roots_duration);
+
+    debug!(target: "notion", "retrieved {} pages and their block roots, now we will grow them!", pages_and_block_roots.len());
+
+    let sanitizer = options.sanitize_ids.then(TextSanitizer::default);
+    let offset_registry;
+    let registry =
+        if options.heading_offset == 0 && options.column_separator == DEFAULT_COLUMN_SEPARATOR {
+            BlockTypeRegistry::global()
+        } else {
+            let mut registry = BlockTypeRegistry::default();
+            registry.set_heading_offset(options.heading_offset);
+            registry.set_column_separator(options.column_separator.as_str());
+            offset_registry = registry;
+            &offset_registry
+        };
+    let mut page_outputs = Vec::new();
+    let mut grow_trees_duration = std::time::Duration::ZERO;
+    let mut build_markdown_duration = std::time::Duration::ZERO;
+    for (page, block_roots) in pages_and_block_roots {
+        let grow_start = Instant::now();
+        let trees = if options.blocks_only {
+            block_roots.into_iter().map(BlockTree::new).collect()
+        } else {
+            notion.grow_the_roots(block_roots).await?
+        };
+        grow_trees_duration += grow_start.elapsed();
+        resolve_linked_page_titles(notion, &mut page_map, &trees).await?;
+        if let Some(sanitizer) = &sanitizer {
+            trees.iter().for_each(|tree| sanitizer.sanitize_tree(tree));
+        }
+        debug!(target: "notion", "grown {} trees, and they look like:", trees.len());
+        for (block, depth) in trees.iter().flat_map(BlockTree::flatten_with_depth) {
+            debug!(target: "notion", "{}{} ({}): {}", "  ".repeat(depth + 1), block.id, block.type_name(), truncate_for_log(&block.text, log_text_max()));
+        }
+
+        let block_count = trees.iter().map(|tree| tree.flatten().len()).sum();
+        let markdown_start = Instant::now();
+        let markdown = build_markdown_from_trees_with_duplicate_check(
+            trees,
+            registry,
+            options.duplicate_check,
+        );
+        build_markdown_duration += markdown_start.elapsed();
+        let properties = if options.include_properties && page.parent_database_id.is_some() {
+            Some(notion.retrieve_page_properties(&page.id).await?)
+        } else {
+            None
+        };
+        let parent_title = parent_titles.get(&page.id).cloned();
+        page_outputs.push(PageOutput {
+            page,
+            markdown,
+            block_count,
+            properties,
+            parent_title,
+        });
+    }
+
+    performance.record_stage("Grow trees", grow_trees_duration);
+    performance.record_stage("Build markdown", build_markdown_duration);
+
+    page_map.save_if_changed();
+
+    let mut context = PromptContext::new(page_outputs);
+    performance.run_at = Utc::now();
+    performance.api_call_count = notion.api_calls_made() - api_calls_before;
+    performance.cache_hits = notion.cache_hits() - cache_hits_before;
+    performance.cache_misses = notion.cache_misses() - cache_misses_before;
+    performance.pages_processed = context.pages.len();
+    performance.blocks_processed = context.total_blocks;
+    context.performance = performance;
+    Ok(context)
+}
+
+/// The Pages whose `property` is a multi-select holding every one of `tags`. Each Page's
+/// properties have to be fetched to find out, so this makes a request per Page.
+async fn filter_pages_by_tags(
+    notion: &Notion,
+    pages: Vec<Page>,
+    property: &str,
+    tags: &[String],
+) -> Result<Vec<Page>, DrossError> {
+    let page_count = pages.len();
+    let mut tagged = Vec::new();
+    for page in pages {
+        let properties = notion.retrieve_page_properties(&page.id).await?;
+        let has_tags = match properties.get(property) {
+            Some(PropertyValue::MultiSelect(values)) => tags.iter().all(|tag| values.contains(tag)),
+            _ => false,
+        };
+        if has_tags {
+            tagged.push(page);
+        }
+    }
+    info!(target: "notion", "{} of {} Pages are tagged {} in {}", tagged.len(), page_count, tags.join(", "), property);
+    Ok(tagged)
+}
+
+/// Builds the graph of `pages`, along with the Pages they're nested directly inside. Parents
+/// usually weren't edited themselves, so they're fetched on their own, and a parent the
+/// integration can't read is left out rather than failing the run.
+async fn graph_with_parents(
+    notion: &Notion,
+    pages: Vec<Page>,
+) -> Result<WorkspaceGraph, DrossError> {
+    let mut graph = WorkspaceGraph::from_pages(pages);
+    let missing_parent_ids = graph
+        .edges
+        .keys()
+        .filter(|parent_id| !graph.pages.contains_key(*parent_id))
+        .cloned()
+        .collect::<Vec<PageID>>();
+    for parent_id in missing_parent_ids {
+        match notion.get_page_by_id(&parent_id).await {
+            Ok(parent) => {
+                graph.pages.insert(parent_id, parent);
+            }
+            Err(DrossError::InsufficientPermissions { .. }) => {
+                debug!(target: "notion", "can't read parent Page {}, leaving its children ungrouped", parent_id);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(graph)
+}
+
+/// Link to page `Block`s only contain the ID of the Page they link to, so fill in
+/// their text with the linked Page's title to give the prompt something to go on. A link
+/// whose Page can't be looked up, e.g. one that's been deleted, keeps the text it has.
+async fn resolve_linked_page_titles(
+    notion: &Notion,
+    page_map: &mut LazyPageMap,
+    trees: &[BlockTree],
+) -> Result<(), DrossError> {
+    for tree in trees {
+        let mut nodes = vec![tree.root()];
+        while let Some(node) = nodes.pop() {
+            nodes.extend(node.children());
+
+            let linked_page_id = match &node.borrow_data().block_type {
+                BlockType::LinkToPage {
+                    link_to_page: LinkToPageValue::PageId { page_id },
+                } => Some(page_id.clone()),
+                _ => None,
+            };
+            if let Some(page_id) = linked_page_id {
+                match page_map.title(notion, &page_id).await {
+                    Ok(title) => node.borrow_data_mut().text = title,
+                    Err(e @ DrossError::BudgetExhausted { .. }) => return Err(e),
+                    Err(e) => {
+                        warn!(target: "notion", "failed to look up the title of linked Page {}, leaving the link as it is: {}", page_id, e);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::core::datatypes::BlockTreeBuilder;
+    use crate::notion::{
+        api::FixtureRequest,
+        fake::{page_json, FakeNotionApi, FakeResponse},
+        TokenBucket,
+    };
+
+    #[tokio::test]
+    async fn test_linked_pages_that_cant_be_found_keep_their_text() {
+        let api = Arc::new(FakeNotionApi::new());
+        let notion = Notion::with_api(api.clone(), TokenBucket::new(u32::MAX, 1e9));
+        let path =
+            std::env::temp_dir().join(format!("dross-lazy-page-map-{}.json", std::process::id()));
+        let mut page_map = LazyPageMap::new(path.clone(), Duration::hours(24), false);
+        let unlinked = vec![BlockTreeBuilder::new("no links here").build()];
+        let linked = vec![BlockTreeBuilder::new("a link")
+            .block_type(BlockType::LinkToPage {
+                link_to_page: LinkToPageValue::PageId {
+                    page_id: "deleted".to_string(),
+                },
+            })
+            .build()];
+
+        resolve_linked_page_titles(&notion, &mut page_map, &unlinked)
+            .await
+            .unwrap();
+        // nothing links to a Page, so the map is never fetched
+        assert!(api.requested().is_empty());
+
+        resolve_linked_page_titles(&notion, &mut page_map, &linked)
+            .await
+            .unwrap();
+        page_map.save_if_changed();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(linked[0].root_block().text, "a link");
+        assert_eq!(
+            api.request_count(FixtureRequest::Page { page_id: "deleted" }),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_filter_pages_by_tags_needs_every_tag_in_a_multi_select() {
+        let api = Arc::new(FakeNotionApi::new());
+        let tagged_page = |id: &str, tags: serde_json::Value| {
+            let mut page = page_json(id, &format!("https://www.notion.so/{}", id), Utc::now());
+            page["properties"] = serde_json::json!({ "Tags": tags });
+            api.respond(
+                FixtureRequest::Page { page_id: id },
+                FakeResponse::Json(page),
+            );
+            Page::for_test(id, id)
+        };
+        let option = |name: &str| serde_json::json!({"id": name, "name": name, "color": "default"});
+        let multi_select = |names: &[&str]| {
+            serde_json::json!({
+                "id": "tags",
+                "type": "multi_select",
+                "multi_select": names.iter().copied().map(option).collect::<Vec<_>>(),
+            })
+        };
+        let pages = vec![
+            tagged_page("both", multi_select(&["Project", "Active", "Rust"])),
+            tagged_page("one", multi_select(&["Project"])),
+            tagged_page("none", multi_select(&[])),
+            // a select holding one of the tags still isn't a multi-select of them
+            tagged_page(
+                "select",
+                serde_json::json!({"id": "tags", "type": "select", "select": option("Project")}),
+            ),
+        ];
+        let notion = Notion::with_api(api.clone(), TokenBucket::new(u32::MAX, 1e9));
+
+        let all_tags = ["Project".to_string(), "Active".to_string()];
+        let tagged = filter_pages_by_tags(&notion, pages.clone(), "Tags", &all_tags)
+            .await
+            .unwrap();
+        let one_tag = filter_pages_by_tags(&notion, pages, "Tags", &["Project".to_string()])
+            .await
+            .unwrap();
+
+        let ids = |pages: Vec<Page>| pages.into_iter().map(|page| page.id).collect::<Vec<_>>();
+        assert_eq!(ids(tagged), vec!["both"]);
+        assert_eq!(ids(one_tag), vec!["both", "one"]);
+        // the properties fetched for the first filter are reused by the second
+        assert_eq!(
+            api.request_count(FixtureRequest::Page { page_id: "select" }),
+            1
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use log::debug;
+use notion_client::objects::block::BlockType;
+use std::{collections::HashMap, sync::OnceLock};
+
+use super::{
+    datatypes::Block,
+    helpers::{detect_language, indent_lines},
+};
+
+/// Renders a single `Block` as markdown. The second argument is the `Block`'s position within
+/// the current run of sibling `NumberedListItem`s, which every other formatter can ignore.
+pub type BlockFormatter = Box<dyn Fn(&Block, usize) -> String + Send + Sync>;
+
+/// Maps the Notion API's block type names (see `Block::type_name`) to the function that renders
+/// that type of `Block` as markdown. Types without a formatter are rendered as their plain text.
+///
+/// `BlockTypeRegistry::default()` knows every type dross supports, and any of them can be
+/// overridden with `register`:
+///
+/// ```
+/// use dross::core::markdown::BlockTypeRegistry;
+///
+/// let mut registry = BlockTypeRegistry::default();
+/// registry.register("to_do", |block, _| format!("* TODO {}", block.text));
+/// ```
+pub struct BlockTypeRegistry {
+    formatters: HashMap<&'static str, BlockFormatter>,
+    /// See `set_column_separator`
+    column_separator: String,
+}
+
+/// The line written between the columns of a multi-column layout unless told otherwise
+pub const DEFAULT_COLUMN_SEPARATOR: &str = "---column---";
+
+impl Default for BlockTypeRegistry {
+    fn default() -> Self {
+        let mut registry = BlockTypeRegistry::empty();
+        registry.set_heading_offset(0);
+        registry.register("bulleted_list_item", |block, _| format!("- {}", block.text));
+        registry.register("numbered_list_item", |block, number| {
+            format!("{}. {}", number, block.text)
+        });
+        registry.register("to_do", |block, _| format!("- [ ] {}", block.text));
+        registry.register("toggle", |block, _| format!("> {}", block.text));
+        registry.register("bookmark", format_bookmark);
+        registry.register("code", format_code);
+        registry
+    }
+}
+
+impl BlockTypeRegistry {
+    /// A registry with no formatters, which renders every `Block` as its plain text
+    #[must_use]
+    pub fn empty() -> Self {
+        BlockTypeRegistry {
+            formatters: HashMap::new(),
+            column_separator: DEFAULT_COLUMN_SEPARATOR.to_string(),
+        }
+    }
+
+    /// The default registry, built the first time it's needed and shared from then on
+    pub fn global() -> &'static BlockTypeRegistry {
+        static GLOBAL: OnceLock<BlockTypeRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(BlockTypeRegistry::default)
+    }
+
+    /// Sets the formatter for `type_name`, replacing the existing one if there is one
+    pub fn register(
+        &mut self,
+        type_name: &'static str,
+        formatter: impl Fn(&Block, usize) -> String + Send + Sync + 'static,
+    ) {
+        self.formatters.insert(type_name, Box::new(formatter));
+    }
+
+    /// Shifts every Notion heading `offset` levels deeper (or shallower, if it's negative), for
+    /// when the markdown is embedded in a document with headings of its own. With an offset of
+    /// 1, `heading_1` becomes `##`. Headings are never shallower than `#`, and ones that would
+    /// be deeper than `######` become bold paragraphs instead.
+    pub fn set_heading_offset(&mut self, offset: i8) {
+        for (type_name, level) in [("heading_1", 1), ("heading_2", 2), ("heading_3", 3)] {
+            let level = (level + i32::from(offset)).max(1) as usize;
+            self.register(type_name, move |block, _| {
+                format_heading(level, &block.text)
+            });
+        }
+    }
+
+    /// Sets the line written between the columns of a multi-column layout. Markdown has no
+    /// columns, so they're written one after another with this line between them.
+    pub fn set_column_separator(&mut self, separator: impl Into<String>) {
+        self.column_separator = separator.into();
+    }
+
+    #[must_use]
+    pub fn column_separator(&self) -> &str {
+        &self.column_separator
+    }
+
+    /// Renders `block` with the formatter registered for its type, where `number` is its
+    /// position in the current run of sibling `NumberedListItem`s
+    #[must_use]
+    pub fn format(&self, block: &Block, number: usize) -> String {
+        match self.formatters.get(block.type_name().as_str()) {
+            Some(formatter) => formatter(block, number),
+            None => block.text.clone(),
+        }
+    }
+
+    /// Same as `format`, except nested under `ancestors`, the Blocks `block` is nested under,
+    /// outermost first. Every line is indented by a tab for each of them, so a list item inside
+    /// a toggle sits under the toggle, and a paragraph under a list item continues the item.
+    #[must_use]
+    pub fn format_with_context(
+        &self,
+        block: &Block,
+        number: usize,
+        ancestors: &[&Block],
+    ) -> String {
+        indent_lines(&self.format(block, number), &"\t".repeat(ancestors.len()))
+    }
+}
+
+/// Markdown only has six levels of heading
+const MAX_HEADING_LEVEL: usize = 6;
+
+fn format_heading(level: usize, text: &str) -> String {
+    if level > MAX_HEADING_LEVEL {
+        format!("**{}**", text)
+    } else {
+        format!("{} {}", "#".repeat(level), text)
+    }
+}
+
+/// Notion's API doesn't return a bookmark's OpenGraph title or description, so the
+/// closest thing we have to a title is the caption the user gave it
+fn format_bookmark(block: &Block, _: usize) -> String {
+    let BlockType::Bookmark { bookmark } = &block.block_type else {
+        return block.text.clone();
+    };
+    if block.text.is_empty() {
+        debug!(target: "notion", "bookmark Block {} has no title, using its URL", block.id);
+        format!("[{}]({})", bookmark.url, bookmark.url)
+    } else {
+        format!("[{}]({})", block.text, bookmark.url)
+    }
+}
+
+fn format_code(block: &Block, _: usize) -> String {
+    let BlockType::Code { code } = &block.block_type else {
+        return block.text.clone();
+    };
+    let language = match serde_json::to_value(&code.language) {
+        Ok(serde_json::Value::String(language)) if language != "plain text" => Some(language),
+        // users often paste code without picking a language, so take a guess
+        _ => detect_language(&block.text).map(str::to_string),
+    };
+    format!("```{}\n{}\n```", language.unwrap_or_default(), block.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(block_type: BlockType, text: &str) -> Block {
+        Block {
+            block_type,
+            ..Block::from_text(text)
+        }
+    }
+
+    #[test]
+    fn test_default_registry_formats_numbered_list_items() {
+        let item = block(
+            BlockType::NumberedListItem {
+                numbered_list_item: Default::default(),
+            },
+            "third",
+        );
+
+        assert_eq!(BlockTypeRegistry::default().format(&item, 3), "3. third");
+    }
+
+    #[test]
+    fn test_register_overrides_built_in_formatter() {
+        let paragraph = block(
+            BlockType::Paragraph {
+                paragraph: Default::default(),
+            },
+            "hello",
+        );
+        let mut registry = BlockTypeRegistry::default();
+        assert_eq!(registry.format(&paragraph, 1), "hello");
+
+        registry.register("paragraph", |block, _| format!("<p>{}</p>", block.text));
+
+        assert_eq!(registry.format(&paragraph, 1), "<p>hello</p>");
+    }
+
+    #[test]
+    fn test_heading_offset() {
+        let heading_1 = block(
+            BlockType::Heading1 {
+                heading_1: Default::default(),
+            },
+            "Title",
+        );
+        let heading_3 = block(
+            BlockType::Heading3 {
+                heading_3: Default::default(),
+            },
+            "Detail",
+        );
+        let mut registry = BlockTypeRegistry::default();
+
+        registry.set_heading_offset(1);
+        assert_eq!(registry.format(&heading_1, 1), "## Title");
+        assert_eq!(registry.format(&heading_3, 1), "#### Detail");
+
+        registry.set_heading_offset(3);
+        assert_eq!(registry.format(&heading_3, 1), "**Detail**");
+
+        registry.set_heading_offset(-2);
+        assert_eq!(registry.format(&heading_1, 1), "# Title");
+        assert_eq!(registry.format(&heading_3, 1), "# Detail");
+    }
+
+    #[test]
+    fn test_format_with_context_indents_by_ancestors() {
+        let toggle = block(
+            BlockType::Toggle {
+                toggle: Default::default(),
+            },
+            "Details",
+        );
+        let item = block(
+            BlockType::BulletedListItem {
+                bulleted_list_item: Default::default(),
+            },
+            "point",
+        );
+        let paragraph = Block::from_text("more about the point");
+        let registry = BlockTypeRegistry::default();
+
+        assert_eq!(registry.format_with_context(&item, 1, &[]), "- point");
+        assert_eq!(
+            registry.format_with_context(&item, 1, &[&toggle]),
+            "\t- point"
+        );
+        assert_eq!(
+            registry.format_with_context(&paragraph, 1, &[&toggle, &item]),
+            "\t\tmore about the point"
+        );
+    }
+
+    #[test]
+    fn test_empty_registry_uses_plain_text() {
+        let heading = block(
+            BlockType::Heading1 {
+                heading_1: Default::default(),
+            },
+            "Title",
+        );
+
+        assert_eq!(BlockTypeRegistry::empty().format(&heading, 1), "Title");
+    }
+}
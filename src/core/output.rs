@@ -0,0 +1,197 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use encoding_rs::UTF_8;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const UTF16LE_BOM: [u8; 2] = [0xFF, 0xFE];
+
+/// The text encoding to write output files in. UTF-16 exists for Windows tools
+/// (legacy Office macros, some CMSes) that can't read UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputEncoding {
+    /// UTF-8 without a BOM
+    #[default]
+    #[value(alias = "utf-8")]
+    #[serde(alias = "utf-8")]
+    Utf8,
+    /// UTF-16 little-endian, prefixed with a BOM
+    #[value(alias = "utf-16")]
+    #[serde(alias = "utf-16")]
+    Utf16le,
+}
+
+/// How the generated markdown is laid out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Each Page's title followed by its markdown, ready to paste into a prompt
+    #[default]
+    Markdown,
+    /// Each Page's markdown under YAML frontmatter with its title, date, and Notion URL, for
+    /// static site generators like Hugo and Jekyll
+    FrontmatterYaml,
+    /// Each Page's title followed by its text with the markdown formatting stripped, for places
+    /// that can't render markdown, like SMS, voice assistants, or plain email
+    PlainText,
+    /// Each Page's title followed by its text in Slack's "mrkdwn" formatting, ready to post
+    /// as a Slack message
+    SlackMrkdwn,
+}
+
+/// Converts `text` into the bytes that should be written out for the given `encoding`
+#[must_use]
+pub fn encode_output(text: &str, encoding: OutputEncoding) -> Vec<u8> {
+    match encoding {
+        OutputEncoding::Utf8 => text.as_bytes().to_vec(),
+        OutputEncoding::Utf16le => {
+            let mut bytes = Vec::with_capacity(UTF16LE_BOM.len() + text.len() * 2);
+            bytes.extend_from_slice(&UTF16LE_BOM);
+            for unit in text.encode_utf16() {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            }
+            bytes
+        }
+    }
+}
+
+/// Decodes the contents of a previously written output file. The encoding is sniffed from
+/// the BOM, and files without one are assumed to be UTF-8.
+#[must_use]
+pub fn decode_output(bytes: &[u8]) -> String {
+    let (text, _, _) = UTF_8.decode(bytes);
+    text.into_owned()
+}
+
+pub fn write_output_file(path: &Path, text: &str, encoding: OutputEncoding) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&encode_output(text, encoding))?;
+    file.flush()
+}
+
+/// Prepends `text` to the output file at `path` under a section header for `run_time`, so the
+/// most recent run is at the top. The file is written to a temporary file and renamed into
+/// place, so a crash mid-write can't lose what was already there.
+///
+/// If `max_size_bytes` is set and the existing file is larger than it, the existing file is
+/// archived to `<path>.1` (replacing any previous archive) and a fresh file is started.
+pub fn append_to_output_file(
+    path: &Path,
+    text: &str,
+    encoding: OutputEncoding,
+    max_size_bytes: Option<u64>,
+    run_time: DateTime<Utc>,
+) -> std::io::Result<()> {
+    let existing = match fs::read(path) {
+        Ok(bytes) if max_size_bytes.is_some_and(|max| bytes.len() as u64 > max) => {
+            fs::rename(path, archive_path(path))?;
+            String::new()
+        }
+        Ok(bytes) => decode_output(&bytes),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let mut contents = format!("## {}\n\n{}\n", run_time.format("%Y-%m-%d %H:%M UTC"), text);
+    if !existing.is_empty() {
+        contents.push('\n');
+        contents.push_str(&existing);
+    }
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    write_output_file(&tmp_path, &contents, encoding)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Where `append_to_output_file` moves an output file once it grows too large
+#[must_use]
+pub fn archive_path(path: &Path) -> PathBuf {
+    let mut archive = path.as_os_str().to_owned();
+    archive.push(".1");
+    PathBuf::from(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use encoding_rs::UTF_16LE;
+
+    use super::*;
+
+    const NON_ASCII_TEXT: &str = "# Notes 🦀\n- 漢字とかな\n- café";
+
+    fn round_trip(encoding: OutputEncoding) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!(
+            "dross-output-{:?}-{}.md",
+            encoding,
+            std::process::id()
+        ));
+        write_output_file(&path, NON_ASCII_TEXT, encoding).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dross-{}-{}.md", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_append_puts_newest_run_first() {
+        let path = temp_path("append");
+        let first = Utc.with_ymd_and_hms(2024, 8, 1, 9, 0, 0).unwrap();
+        let second = Utc.with_ymd_and_hms(2024, 8, 2, 9, 0, 0).unwrap();
+
+        append_to_output_file(&path, "old notes", OutputEncoding::Utf8, None, first).unwrap();
+        append_to_output_file(&path, "new notes", OutputEncoding::Utf8, None, second).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "## 2024-08-02 09:00 UTC\n\nnew notes\n\n## 2024-08-01 09:00 UTC\n\nold notes\n"
+        );
+    }
+
+    #[test]
+    fn test_append_rotates_oversized_file() {
+        let path = temp_path("rotate");
+        let archive = archive_path(&path);
+        std::fs::write(&path, "x".repeat(2048)).unwrap();
+
+        append_to_output_file(&path, "fresh", OutputEncoding::Utf8, Some(1024), Utc::now())
+            .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let archived = std::fs::read_to_string(&archive).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&archive).unwrap();
+        assert!(contents.ends_with("\n\nfresh\n"));
+        assert_eq!(archived.len(), 2048);
+    }
+
+    #[test]
+    fn test_utf8_output_round_trips() {
+        let bytes = round_trip(OutputEncoding::Utf8);
+
+        assert_eq!(bytes, NON_ASCII_TEXT.as_bytes());
+        assert_eq!(decode_output(&bytes), NON_ASCII_TEXT);
+    }
+
+    #[test]
+    fn test_utf16le_output_round_trips() {
+        let bytes = round_trip(OutputEncoding::Utf16le);
+
+        assert_eq!(&bytes[..2], &UTF16LE_BOM);
+        let (decoded, had_errors) = UTF_16LE.decode_with_bom_removal(&bytes);
+        assert!(!had_errors);
+        assert_eq!(decoded, NON_ASCII_TEXT);
+        assert_eq!(decode_output(&bytes), NON_ASCII_TEXT);
+    }
+}
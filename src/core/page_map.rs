@@ -0,0 +1,167 @@
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{datatypes::PageID, error::DrossError};
+use crate::notion::Notion;
+
+/// A map from every Page ID in the workspace to its title.
+///
+/// Fetching this requires paginating through the whole workspace and it rarely changes,
+/// so it's cached on disk between runs and only refetched once it's older than a TTL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageMap {
+    pub fetched_at: DateTime<Utc>,
+    pub titles: HashMap<PageID, String>,
+}
+
+impl PageMap {
+    pub async fn fetch(notion: &Notion) -> Result<Self, DrossError> {
+        Ok(PageMap {
+            fetched_at: Utc::now(),
+            titles: notion.get_all_pages_map().await?,
+        })
+    }
+
+    /// Loads the cached `PageMap` from `path`, or `None` if there isn't a readable one
+    #[must_use]
+    pub fn load(path: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(page_map) => Some(page_map),
+            Err(e) => {
+                debug!(target: "notion", "ignoring unreadable page map at {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    #[must_use]
+    pub fn is_stale(&self, ttl: Duration) -> bool {
+        Utc::now() - self.fetched_at > ttl
+    }
+
+    /// Uses the cached `PageMap` at `path` unless it is missing, older than `ttl`, or
+    /// `force_refresh` is set, in which case a fresh one is fetched and written to `path`
+    pub async fn load_or_fetch(
+        notion: &Notion,
+        path: &Path,
+        ttl: Duration,
+        force_refresh: bool,
+    ) -> Result<Self, DrossError> {
+        if !force_refresh {
+            if let Some(page_map) = PageMap::load(path) {
+                if !page_map.is_stale(ttl) {
+                    debug!(target: "notion", "using cached page map with {} Pages", page_map.titles.len());
+                    return Ok(page_map);
+                }
+            }
+        }
+
+        info!(target: "notion", "refreshing the page map, this may take a while for large workspaces");
+        let page_map = PageMap::fetch(notion).await?;
+        if let Err(e) = page_map.save(path) {
+            debug!(target: "notion", "failed to cache page map at {}: {}", path.display(), e);
+        }
+
+        Ok(page_map)
+    }
+
+    /// Looks up the title of `page_id`, fetching just that Page if it was created after
+    /// the map was cached
+    pub async fn title(&mut self, notion: &Notion, page_id: &str) -> Result<String, DrossError> {
+        if let Some(title) = self.titles.get(page_id) {
+            return Ok(title.clone());
+        }
+
+        debug!(target: "notion", "Page {} is missing from the page map, fetching it", page_id);
+        let title = notion.get_page_title(page_id).await?;
+        self.titles.insert(page_id.to_string(), title.clone());
+
+        Ok(title)
+    }
+}
+
+/// A `PageMap` that isn't loaded until the first title is looked up, since fetching one means
+/// paginating through the whole workspace and most runs don't come across any linked Pages
+pub struct LazyPageMap {
+    path: PathBuf,
+    ttl: Duration,
+    force_refresh: bool,
+    page_map: Option<PageMap>,
+    /// How many titles the map had when it was loaded, to tell whether it needs saving
+    loaded_len: usize,
+}
+
+impl LazyPageMap {
+    /// Will load the map with `PageMap::load_or_fetch` on first use
+    #[must_use]
+    pub fn new(path: PathBuf, ttl: Duration, force_refresh: bool) -> Self {
+        LazyPageMap {
+            path,
+            ttl,
+            force_refresh,
+            page_map: None,
+            loaded_len: 0,
+        }
+    }
+
+    /// Looks up the title of `page_id`, loading the map first if this is the first lookup. If
+    /// the map can't be loaded, Pages are looked up one at a time instead.
+    pub async fn title(&mut self, notion: &Notion, page_id: &str) -> Result<String, DrossError> {
+        if self.page_map.is_none() {
+            let page_map = match PageMap::load_or_fetch(
+                notion,
+                &self.path,
+                self.ttl,
+                self.force_refresh,
+            )
+            .await
+            {
+                Ok(page_map) => page_map,
+                Err(e @ DrossError::BudgetExhausted { .. }) => return Err(e),
+                Err(e) => {
+                    warn!(target: "notion", "failed to load the page map, looking up linked Pages one at a time: {}", e);
+                    // stale, so the next run fetches a whole map rather than using this one
+                    PageMap {
+                        fetched_at: DateTime::<Utc>::MIN_UTC,
+                        titles: HashMap::new(),
+                    }
+                }
+            };
+            self.loaded_len = page_map.titles.len();
+            self.page_map = Some(page_map);
+        }
+
+        self.page_map
+            .as_mut()
+            .expect("the page map was just loaded")
+            .title(notion, page_id)
+            .await
+    }
+
+    /// Writes the map back to disk if any titles were added to it since it was loaded
+    pub fn save_if_changed(&self) {
+        let Some(page_map) = &self.page_map else {
+            return;
+        };
+        if page_map.titles.len() == self.loaded_len {
+            return;
+        }
+        if let Err(e) = page_map.save(&self.path) {
+            debug!(target: "notion", "failed to cache page map at {}: {}", self.path.display(), e);
+        }
+    }
+}
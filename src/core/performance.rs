@@ -0,0 +1,135 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+    time::Duration,
+};
+
+use super::{benchmark::format_ms, stats::format_table};
+
+/// How long each stage of a run took and how much work it did, for `--perf`. Unlike
+/// `BenchmarkReport`, this is collected on every real run rather than a limited one.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PerformanceReport {
+    pub run_at: DateTime<Utc>,
+    /// Each stage of the pipeline, in the order they ran
+    pub stage_timings: Vec<(String, Duration)>,
+    /// How long searching each Page for its recently edited Blocks took, by Page title. Their
+    /// total is the `Find block roots` stage.
+    pub page_block_root_timings: Vec<(String, Duration)>,
+    pub api_call_count: u32,
+    /// Lookups answered by `Notion`'s in-run caches, see `Notion::cache_hits`
+    pub cache_hits: u32,
+    pub cache_misses: u32,
+    pub pages_processed: usize,
+    pub blocks_processed: usize,
+}
+
+impl PerformanceReport {
+    pub fn record_stage(&mut self, name: &str, duration: Duration) {
+        self.stage_timings.push((name.to_string(), duration));
+    }
+
+    #[must_use]
+    pub fn total(&self) -> Duration {
+        self.stage_timings
+            .iter()
+            .map(|(_, duration)| *duration)
+            .sum()
+    }
+
+    /// Appends the report to the newline-delimited JSON history at `path`, for spotting
+    /// trends across runs
+    pub fn append_to(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(self)?)
+    }
+}
+
+/// Each stage's time and share of the total, then each Page's block root search, then how
+/// much work the run did
+#[must_use]
+pub fn performance_report_to_table(report: &PerformanceReport) -> String {
+    let total = report.total().as_secs_f64();
+    let ms = |duration: &Duration| duration.as_secs_f64() * 1000.0;
+    let mut rows = report
+        .stage_timings
+        .iter()
+        .map(|(name, duration)| {
+            let share = if total > 0.0 {
+                duration.as_secs_f64() / total * 100.0
+            } else {
+                0.0
+            };
+            [
+                name.clone(),
+                format_ms(ms(duration)),
+                format!("{:.1}%", share),
+            ]
+        })
+        .collect::<Vec<_>>();
+    rows.push([
+        "Total".to_string(),
+        format_ms(total * 1000.0),
+        "100.0%".to_string(),
+    ]);
+    rows.extend(
+        report
+            .page_block_root_timings
+            .iter()
+            .map(|(title, duration)| {
+                [
+                    format!("Block roots of {}", title),
+                    format_ms(ms(duration)),
+                    "-".to_string(),
+                ]
+            }),
+    );
+
+    format!(
+        "{}\n{} Pages, {} Blocks, {} Notion API calls, {} cache hits, {} cache misses\n",
+        format_table(["Stage", "Time", "Share"], &rows),
+        report.pages_processed,
+        report.blocks_processed,
+        report.api_call_count,
+        report.cache_hits,
+        report.cache_misses
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_performance_report_to_table() {
+        let mut report = PerformanceReport {
+            pages_processed: 2,
+            blocks_processed: 30,
+            api_call_count: 7,
+            cache_hits: 1,
+            ..PerformanceReport::default()
+        };
+        report.record_stage("List Pages", Duration::from_millis(300));
+        report.record_stage("Find block roots", Duration::from_millis(100));
+        report
+            .page_block_root_timings
+            .push(("Journal".to_string(), Duration::from_millis(100)));
+
+        assert_eq!(
+            performance_report_to_table(&report),
+            "Stage                   Time      Share\n\
+             List Pages              300.0 ms  75.0%\n\
+             Find block roots        100.0 ms  25.0%\n\
+             Total                   400.0 ms  100.0%\n\
+             Block roots of Journal  100.0 ms  -\n\
+             \n\
+             2 Pages, 30 Blocks, 7 Notion API calls, 1 cache hits, 0 cache misses\n"
+        );
+    }
+}
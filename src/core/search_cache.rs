@@ -0,0 +1,180 @@
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use super::{datatypes::Page, error::DrossError};
+use crate::notion::Notion;
+
+/// Recent results of searching for the Pages edited within a lookback window.
+///
+/// Two runs in quick succession with the same `--days` (e.g. one before and one after a small
+/// edit) would otherwise repeat the whole search, so each result is cached on disk and reused
+/// until it's older than a TTL.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchCache {
+    pub searches: Vec<CachedSearch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedSearch {
+    /// `Notion::token_hash` of the workspace searched, so that switching tokens doesn't reuse
+    /// another workspace's Pages
+    #[serde(default)]
+    pub token_hash: String,
+    /// How far back the search looked, in seconds. The cutoff itself moves with the clock, so
+    /// this is what makes two searches the same.
+    pub lookback_secs: i64,
+    /// The most Pages the search returned, if it was limited
+    pub limit: Option<usize>,
+    pub fetched_at: DateTime<Utc>,
+    pub pages: Vec<Page>,
+}
+
+impl SearchCache {
+    /// Loads the cached searches from `path`, or an empty cache if there isn't a readable one
+    #[must_use]
+    pub fn load(path: &Path) -> Self {
+        let Ok(contents) = fs::read_to_string(path) else {
+            return SearchCache::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            debug!(target: "notion", "ignoring unreadable search cache at {}: {}", path.display(), e);
+            SearchCache::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    /// The Pages found by a search of the same workspace over the same lookback and limit
+    /// within the last `ttl`
+    #[must_use]
+    pub fn get(
+        &self,
+        token_hash: &str,
+        dur: Duration,
+        limit: Option<usize>,
+        ttl: Duration,
+    ) -> Option<&[Page]> {
+        self.searches
+            .iter()
+            .find(|search| {
+                search.token_hash == token_hash
+                    && search.lookback_secs == dur.num_seconds()
+                    && search.limit == limit
+                    && Utc::now() - search.fetched_at <= ttl
+            })
+            .map(|search| search.pages.as_slice())
+    }
+
+    /// Caches `pages` as the result of the search of the workspace with `token_hash` over `dur`
+    /// and `limit`, replacing any older result for the same search and dropping results older
+    /// than `ttl`
+    pub fn insert(
+        &mut self,
+        token_hash: &str,
+        dur: Duration,
+        limit: Option<usize>,
+        pages: Vec<Page>,
+        ttl: Duration,
+    ) {
+        let now = Utc::now();
+        self.searches.retain(|search| {
+            now - search.fetched_at <= ttl
+                && !(search.token_hash == token_hash
+                    && search.lookback_secs == dur.num_seconds()
+                    && search.limit == limit)
+        });
+        self.searches.push(CachedSearch {
+            token_hash: token_hash.to_string(),
+            lookback_secs: dur.num_seconds(),
+            limit,
+            fetched_at: now,
+            pages,
+        });
+    }
+
+    /// Uses the cached result of searching for the (at most `limit`) Pages edited within `dur`
+    /// unless it is missing or older than `ttl`, in which case the search is made and its
+    /// result is cached at `path`
+    pub async fn load_or_search(
+        notion: &Notion,
+        path: &Path,
+        dur: Duration,
+        limit: Option<usize>,
+        ttl: Duration,
+    ) -> Result<Vec<Page>, DrossError> {
+        let mut cache = SearchCache::load(path);
+        if let Some(pages) = cache.get(notion.token_hash(), dur, limit, ttl) {
+            debug!(target: "notion", "using {} cached recently edited Pages", pages.len());
+            return Ok(pages.to_vec());
+        }
+
+        let pages = match limit {
+            Some(n) => notion.get_last_n_edited_pages(dur, n).await?,
+            None => notion.get_last_edited_pages(dur).await?,
+        };
+        cache.insert(notion.token_hash(), dur, limit, pages.clone(), ttl);
+        if let Err(e) = cache.save(path) {
+            debug!(target: "notion", "failed to cache the Page search at {}: {}", path.display(), e);
+        }
+
+        Ok(pages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(title: &str) -> Page {
+        Page::for_test("7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb", title)
+    }
+
+    #[test]
+    fn test_search_cache_matches_lookback_and_limit() {
+        let ttl = Duration::minutes(10);
+        let mut cache = SearchCache::default();
+        cache.insert("abc", Duration::days(1), None, vec![page("Journal")], ttl);
+
+        assert_eq!(
+            cache.get("abc", Duration::days(1), None, ttl).unwrap()[0].title,
+            "Journal"
+        );
+        assert!(cache.get("abc", Duration::days(2), None, ttl).is_none());
+        assert!(cache.get("abc", Duration::days(1), Some(5), ttl).is_none());
+        // another workspace's search
+        assert!(cache.get("def", Duration::days(1), None, ttl).is_none());
+
+        cache.insert("abc", Duration::days(1), None, vec![page("Inbox")], ttl);
+        assert_eq!(cache.searches.len(), 1);
+        assert_eq!(
+            cache.get("abc", Duration::days(1), None, ttl).unwrap()[0].title,
+            "Inbox"
+        );
+    }
+
+    #[test]
+    fn test_search_cache_expires() {
+        let mut cache = SearchCache::default();
+        cache.searches.push(CachedSearch {
+            token_hash: "abc".to_string(),
+            lookback_secs: Duration::days(1).num_seconds(),
+            limit: None,
+            fetched_at: Utc::now() - Duration::minutes(11),
+            pages: vec![page("Journal")],
+        });
+
+        assert!(cache
+            .get("abc", Duration::days(1), None, Duration::minutes(10))
+            .is_none());
+        assert!(cache
+            .get("abc", Duration::days(1), None, Duration::minutes(15))
+            .is_some());
+    }
+}
@@ -0,0 +1,76 @@
+use dialoguer::MultiSelect;
+use std::io::{self, IsTerminal};
+
+use super::datatypes::Page;
+
+/// Whether there's a terminal to show the page selector in. When dross is run from a script
+/// or has its input piped in, the selector is skipped and every Page is processed.
+#[must_use]
+pub fn can_select_interactively() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Shows a checklist of `pages`, all checked to start with, and returns only the ones
+/// the user left checked
+pub fn select_pages_interactively(pages: Vec<Page>) -> io::Result<Vec<Page>> {
+    if pages.is_empty() {
+        return Ok(pages);
+    }
+
+    let labels = pages.iter().map(page_selector_label).collect::<Vec<_>>();
+    let selected = MultiSelect::new()
+        .with_prompt("Select the Pages to process (space to toggle, enter to confirm)")
+        .items(&labels)
+        .defaults(&vec![true; labels.len()])
+        .interact()
+        .map_err(|e| match e {
+            dialoguer::Error::IO(e) => e,
+        })?;
+
+    let mut pages = pages.into_iter().map(Some).collect::<Vec<_>>();
+    Ok(selected
+        .into_iter()
+        .filter_map(|i| pages[i].take())
+        .collect())
+}
+
+/// How many of a Page's top-level Blocks its selector label previews
+const PREVIEW_BLOCKS: usize = 3;
+
+/// How a `Page` is shown in the selector, followed by a preview of its text if it has any.
+/// The block count only covers the Page's top-level Blocks, so it's an estimate of how much
+/// there is to process.
+#[must_use]
+pub fn page_selector_label(page: &Page) -> String {
+    let label = format!(
+        "{} (edited {}, ~{} blocks)",
+        page.title,
+        page.update_date.format("%Y-%m-%d %H:%M"),
+        page.block_count()
+    );
+    match page.preview(PREVIEW_BLOCKS) {
+        preview if preview.is_empty() => label,
+        preview => format!("{} — {}", label, preview),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    #[test]
+    fn test_page_selector_label() {
+        let page = Page {
+            creation_date: Utc.with_ymd_and_hms(2024, 8, 1, 0, 0, 0).unwrap(),
+            update_date: Utc.with_ymd_and_hms(2024, 8, 19, 17, 30, 0).unwrap(),
+            ..Page::for_test("7b1b3b0c-14cb-45a6-a4b6-d2b48faecccb", "Weekly Review")
+        };
+
+        assert_eq!(
+            page_selector_label(&page),
+            "Weekly Review (edited 2024-08-19 17:30, ~0 blocks)"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::future::join_all;
+use log::info;
+use std::{
+    io::{self, ErrorKind, Write},
+    path::PathBuf,
+    process::Stdio,
+};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use super::{
+    datatypes::PromptContext,
+    error::DrossError,
+    gist::{upload_gist, Gist},
+    output::{append_to_output_file, write_output_file, OutputEncoding},
+    webhook::{post_to_webhook, WebhookPayload},
+};
+
+/// A destination for a run's output. A run can write to any number of them, see
+/// `write_to_sinks`.
+#[async_trait]
+pub trait OutputSink: Send + Sync {
+    /// Writes `output`, the run's markdown already rendered in the chosen `--format`. `context`
+    /// is what it was rendered from, for sinks that need more than the text, like a gist with
+    /// a file per Page.
+    async fn write(&self, context: &PromptContext, output: &str) -> Result<(), DrossError>;
+}
+
+/// Writes `output` to every sink at once, carrying on past the ones that fail
+///
+/// # Returns
+/// The errors of the sinks that failed, in the order the sinks were given
+pub async fn write_to_sinks(
+    sinks: &[Box<dyn OutputSink>],
+    context: &PromptContext,
+    output: &str,
+) -> Vec<DrossError> {
+    join_all(sinks.iter().map(|sink| sink.write(context, output)))
+        .await
+        .into_iter()
+        .filter_map(Result::err)
+        .collect()
+}
+
+pub struct StdoutSink;
+
+#[async_trait]
+impl OutputSink for StdoutSink {
+    async fn write(&self, _: &PromptContext, output: &str) -> Result<(), DrossError> {
+        writeln!(io::stdout(), "{}", output)?;
+        Ok(())
+    }
+}
+
+pub struct FileSink {
+    pub path: PathBuf,
+    pub encoding: OutputEncoding,
+    /// Add the output to the top of the file under a timestamped header, rather than
+    /// overwriting it. See `append_to_output_file`
+    pub append: bool,
+    /// With `append`, archive the file once it grows past this many bytes
+    pub max_size_bytes: Option<u64>,
+}
+
+impl FileSink {
+    /// A sink that overwrites the file at `path` with UTF-8
+    #[must_use]
+    pub fn new(path: PathBuf) -> Self {
+        FileSink {
+            path,
+            encoding: OutputEncoding::default(),
+            append: false,
+            max_size_bytes: None,
+        }
+    }
+}
+
+#[async_trait]
+impl OutputSink for FileSink {
+    async fn write(&self, _: &PromptContext, output: &str) -> Result<(), DrossError> {
+        if self.append {
+            append_to_output_file(
+                &self.path,
+                output,
+                self.encoding,
+                self.max_size_bytes,
+                Utc::now(),
+            )?;
+            info!("appended output to {}", self.path.display());
+        } else {
+            write_output_file(&self.path, output, self.encoding)?;
+            info!("wrote output to {}", self.path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Copies the output to the system clipboard with whichever of the platform's clipboard
+/// commands is installed
+pub struct ClipboardSink;
+
+/// Tried in order until one of them runs
+const CLIPBOARD_COMMANDS: [(&str, &[&str]); 4] = [
+    ("pbcopy", &[]),
+    ("clip", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+];
+
+#[async_trait]
+impl OutputSink for ClipboardSink {
+    async fn write(&self, _: &PromptContext, output: &str) -> Result<(), DrossError> {
+        for (program, args) in CLIPBOARD_COMMANDS {
+            let mut child = match Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .spawn()
+            {
+                Ok(child) => child,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(output.as_bytes()).await?;
+            }
+            let status = child.wait().await?;
+            if !status.success() {
+                return Err(io::Error::other(format!("{} exited with {}", program, status)).into());
+            }
+            info!("copied output to the clipboard with {}", program);
+            return Ok(());
+        }
+        Err(io::Error::new(
+            ErrorKind::NotFound,
+            "no clipboard command found, install one of pbcopy, clip, wl-copy, or xclip",
+        )
+        .into())
+    }
+}
+
+pub struct WebhookSink {
+    pub url: String,
+    /// See `webhook::sign`
+    pub secret: Option<String>,
+}
+
+#[async_trait]
+impl OutputSink for WebhookSink {
+    async fn write(&self, context: &PromptContext, output: &str) -> Result<(), DrossError> {
+        let payload = WebhookPayload::new(output.to_string(), context.pages.len());
+        post_to_webhook(&self.url, self.secret.as_deref(), &payload).await
+    }
+}
+
+/// Uploads each Page's markdown as a file in a GitHub gist, and logs the gist's URL
+pub struct GistSink {
+    pub token: String,
+    pub description: String,
+    pub public: bool,
+    /// Replace the files of this gist rather than creating a new one
+    pub update_gist_id: Option<String>,
+}
+
+impl GistSink {
+    /// A sink that creates a new secret gist with no description
+    #[must_use]
+    pub fn new(token: String) -> Self {
+        GistSink {
+            token,
+            description: String::new(),
+            public: false,
+            update_gist_id: None,
+        }
+    }
+
+    /// Uploads `context`'s Pages
+    ///
+    /// # Returns
+    /// The URL of the gist
+    pub async fn upload(&self, context: &PromptContext) -> Result<String, DrossError> {
+        let gist = Gist::from_prompt_context(context, &self.description, self.public);
+        upload_gist(&self.token, &gist, self.update_gist_id.as_deref()).await
+    }
+}
+
+#[async_trait]
+impl OutputSink for GistSink {
+    async fn write(&self, context: &PromptContext, _: &str) -> Result<(), DrossError> {
+        let url = self.upload(context).await?;
+        info!("uploaded output to {}", url);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FailingSink;
+
+    #[async_trait]
+    impl OutputSink for FailingSink {
+        async fn write(&self, _: &PromptContext, _: &str) -> Result<(), DrossError> {
+            Err(io::Error::other("always fails").into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_to_sinks_continues_past_failures() {
+        let first = std::env::temp_dir().join(format!("dross-sink-1-{}.md", std::process::id()));
+        let second = std::env::temp_dir().join(format!("dross-sink-2-{}.md", std::process::id()));
+        let sinks: Vec<Box<dyn OutputSink>> = vec![
+            Box::new(FileSink::new(first.clone())),
+            Box::new(FailingSink),
+            Box::new(FileSink::new(second.clone())),
+        ];
+
+        let errors = write_to_sinks(&sinks, &PromptContext::new(Vec::new()), "notes").await;
+
+        let first_contents = std::fs::read_to_string(&first).unwrap();
+        let second_contents = std::fs::read_to_string(&second).unwrap();
+        std::fs::remove_file(&first).unwrap();
+        std::fs::remove_file(&second).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], DrossError::Io(_)));
+        assert_eq!(first_contents, "notes");
+        assert_eq!(second_contents, "notes");
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// Where the page map lives inside the `data_dir`
+pub const PAGE_MAP_FILE_NAME: &str = "pages_map.json";
+/// Where state carried between runs (e.g. the last run's timestamp) lives inside the `data_dir`
+pub const RUN_STATE_FILE_NAME: &str = "state.json";
+/// Where recent searches for edited Pages are cached inside the `data_dir`
+pub const SEARCH_CACHE_FILE_NAME: &str = "search_cache.json";
+/// Where `--log-http` writes each Notion API request and response inside the `data_dir`
+pub const HTTP_LOG_FILE_NAME: &str = "http_log.ndjson";
+/// Where the history of `dross benchmark` runs lives inside the `data_dir`
+pub const BENCHMARK_FILE_NAME: &str = "benchmark.json";
+/// Where the Pages included in each run are recorded inside the `data_dir`
+pub const ACCESS_HISTORY_FILE_NAME: &str = "access_history.json";
+/// Where `--perf` appends each run's `PerformanceReport` inside the `data_dir`
+pub const PERF_HISTORY_FILE_NAME: &str = "perf_history.ndjson";
+
+/// The directory dross keeps its local state in between runs, `~/.local/share/dross/`
+#[must_use]
+pub fn data_dir() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_default()
+        .join(".local")
+        .join("share")
+        .join("dross")
+}
+
+/// The user's config file, `~/.config/dross/config.toml`
+#[must_use]
+pub fn config_path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_default()
+        .join(".config")
+        .join("dross")
+        .join("config.toml")
+}
+
+#[must_use]
+pub fn page_map_path() -> PathBuf {
+    data_dir().join(PAGE_MAP_FILE_NAME)
+}
+
+#[must_use]
+pub fn run_state_path() -> PathBuf {
+    data_dir().join(RUN_STATE_FILE_NAME)
+}
+
+#[must_use]
+pub fn search_cache_path() -> PathBuf {
+    data_dir().join(SEARCH_CACHE_FILE_NAME)
+}
+
+#[must_use]
+pub fn http_log_path() -> PathBuf {
+    data_dir().join(HTTP_LOG_FILE_NAME)
+}
+
+#[must_use]
+pub fn benchmark_path() -> PathBuf {
+    data_dir().join(BENCHMARK_FILE_NAME)
+}
+
+#[must_use]
+pub fn access_history_path() -> PathBuf {
+    data_dir().join(ACCESS_HISTORY_FILE_NAME)
+}
+
+#[must_use]
+pub fn perf_history_path() -> PathBuf {
+    data_dir().join(PERF_HISTORY_FILE_NAME)
+}
+
+/// Deletes the local state file at `path` (or only pretends to, if `dry_run` is set).
+///
+/// # Returns
+/// The size in bytes of the deleted file, or `None` if there was no file to delete.
+pub fn remove_state_file(path: &Path, dry_run: bool) -> std::io::Result<Option<u64>> {
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if !dry_run {
+        fs::remove_file(path)?;
+    }
+
+    Ok(Some(size))
+}
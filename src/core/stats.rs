@@ -0,0 +1,545 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::datatypes::{BlockTree, Page};
+
+/// Average adult silent reading speed, used to estimate how long a `Page` takes to read
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Summary statistics for a single Notion `Page`
+#[derive(Debug, Clone, Serialize)]
+pub struct PageStats {
+    pub title: String,
+    pub url: String,
+    pub last_edited: DateTime<Utc>,
+    pub block_count: usize,
+    pub word_count: usize,
+    pub reading_time_minutes: usize,
+}
+
+impl PageStats {
+    /// The stats of the `Page`'s top-level `Block`s, for when its trees haven't been grown
+    #[must_use]
+    pub fn from_page(page: &Page) -> Self {
+        PageStats::new(page, page.block_count(), page.word_count())
+    }
+
+    /// The stats of every `Block` in the `Page`'s `trees`, nested ones included
+    #[must_use]
+    pub fn from_trees(page: &Page, trees: &[BlockTree]) -> Self {
+        PageStats::new(
+            page,
+            trees.iter().map(BlockTree::block_count).sum(),
+            trees.iter().map(BlockTree::word_count).sum(),
+        )
+    }
+
+    fn new(page: &Page, block_count: usize, word_count: usize) -> Self {
+        PageStats {
+            title: page.title.clone(),
+            url: page.url.clone(),
+            last_edited: page.update_date,
+            block_count,
+            word_count,
+            reading_time_minutes: word_count.div_ceil(WORDS_PER_MINUTE),
+        }
+    }
+}
+
+/// Computes the `PageStats` for every `Page` from its trees, sorted by word count (most
+/// content-rich first)
+#[must_use]
+pub fn collect_page_stats(pages_and_trees: &[(Page, Vec<BlockTree>)]) -> Vec<PageStats> {
+    let mut stats = pages_and_trees
+        .iter()
+        .map(|(page, trees)| PageStats::from_trees(page, trees))
+        .collect::<Vec<_>>();
+    stats.sort_by(|a, b| b.word_count.cmp(&a.word_count));
+    stats
+}
+
+/// Renders the `PageStats` as a plain-text table with aligned columns, suitable for the terminal
+#[must_use]
+pub fn stats_to_table(stats: &[PageStats]) -> String {
+    let headers = ["Title", "Last Edited", "Blocks", "Words", "Reading Time"];
+    let rows = stats
+        .iter()
+        .map(|s| {
+            [
+                s.title.clone(),
+                s.last_edited.format("%Y-%m-%d %H:%M").to_string(),
+                s.block_count.to_string(),
+                s.word_count.to_string(),
+                format!("{} min", s.reading_time_minutes),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    format_table(headers, &rows)
+}
+
+/// What `dross pages --sort` orders the listed Pages by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PageListSort {
+    /// Alphabetically by title
+    Title,
+    /// By when the Page was last edited, oldest first
+    Date,
+    /// By word count, fewest first
+    Words,
+}
+
+/// Sorts `stats` by `sort`, reversed if `descending` is set
+pub fn sort_page_stats(stats: &mut [PageStats], sort: PageListSort, descending: bool) {
+    stats.sort_by(|a, b| {
+        let ordering = match sort {
+            PageListSort::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+            PageListSort::Date => a.last_edited.cmp(&b.last_edited),
+            PageListSort::Words => a.word_count.cmp(&b.word_count),
+        };
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+/// The longest a title can be in the `dross pages` table before it's cut short
+const PAGE_LIST_MAX_TITLE_CHARS: usize = 40;
+
+/// Titles are never cut shorter than this to fit a narrow terminal, it's better to wrap
+const PAGE_LIST_MIN_TITLE_CHARS: usize = 10;
+
+/// Renders the `PageStats` as the numbered table `dross pages` prints, with each title cut
+/// short so rows fit within `max_width` columns, and edit times relative to `now`
+#[must_use]
+pub fn page_list_to_table(stats: &[PageStats], now: DateTime<Utc>, max_width: usize) -> String {
+    let headers = ["#", "Title", "Last Edited", "Blocks", "Words"];
+    let mut rows = stats
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            [
+                (i + 1).to_string(),
+                s.title.clone(),
+                format_time_ago(s.last_edited, now),
+                s.block_count.to_string(),
+                s.word_count.to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    // everything but the title, including the two spaces between each column
+    let other_columns_width = [0, 2, 3, 4]
+        .iter()
+        .map(|&column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .chain([headers[column].chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .sum::<usize>()
+        + 2 * (headers.len() - 1);
+    let title_width = max_width
+        .saturating_sub(other_columns_width)
+        .clamp(PAGE_LIST_MIN_TITLE_CHARS, PAGE_LIST_MAX_TITLE_CHARS);
+    for row in &mut rows {
+        row[1] = truncate_title(&row[1], title_width);
+    }
+
+    format_table(headers, &rows)
+}
+
+/// Renders the `PageStats` as tab-separated values under a header row, for when `dross pages`
+/// is piped into another program. Titles are never cut short, and edit times are RFC 3339.
+#[must_use]
+pub fn page_list_to_tsv(stats: &[PageStats]) -> String {
+    let mut tsv = String::from("#\tTitle\tLast Edited\tBlocks\tWords\n");
+    for (i, s) in stats.iter().enumerate() {
+        tsv.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            i + 1,
+            // a tab in a title would shift every column after it
+            s.title.replace('\t', " "),
+            s.last_edited.to_rfc3339(),
+            s.block_count,
+            s.word_count
+        ));
+    }
+    tsv
+}
+
+/// Cuts `title` down to at most `max_chars` characters, ending it with `…` if it was cut
+fn truncate_title(title: &str, max_chars: usize) -> String {
+    if title.chars().count() <= max_chars {
+        title.to_string()
+    } else {
+        let truncated = title
+            .chars()
+            .take(max_chars.saturating_sub(1))
+            .collect::<String>();
+        format!("{}…", truncated.trim_end())
+    }
+}
+
+/// How long before `now` `then` was, in the largest whole unit, like `2 hours ago`
+#[must_use]
+pub fn format_time_ago(then: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let elapsed = now - then;
+    let (count, unit) = if elapsed.num_days() > 0 {
+        (elapsed.num_days(), "day")
+    } else if elapsed.num_hours() > 0 {
+        (elapsed.num_hours(), "hour")
+    } else if elapsed.num_minutes() > 0 {
+        (elapsed.num_minutes(), "minute")
+    } else {
+        return "just now".to_string();
+    };
+    format!(
+        "{} {}{} ago",
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    )
+}
+
+/// Lays out `rows` under `headers` in left-aligned columns, two spaces apart
+pub(crate) fn format_table<const N: usize>(headers: [&str; N], rows: &[[String; N]]) -> String {
+    let mut widths = headers.map(|h| h.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut table = format_row(&headers.map(str::to_string));
+    table.push('\n');
+    for row in rows {
+        table.push_str(&format_row(row));
+        table.push('\n');
+    }
+
+    table
+}
+
+/// Text statistics for every `Block` of one type, for spotting types whose text dross fails
+/// to extract
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockTypeStats {
+    pub block_type: String,
+    pub count: usize,
+    /// In characters
+    pub mean_text_length: f64,
+    /// In characters
+    pub max_text_length: usize,
+    pub word_count: usize,
+    /// The share of the `Block`s with no text, from 0 to 100
+    pub empty_percentage: f64,
+}
+
+/// Computes the `BlockTypeStats` of every `Block` in the trees, most common type first
+#[must_use]
+pub fn collect_block_type_stats(trees: &[BlockTree]) -> Vec<BlockTypeStats> {
+    let mut text_lengths_by_type: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+    for block in trees.iter().flat_map(BlockTree::flatten) {
+        text_lengths_by_type
+            .entry(block.type_name())
+            .or_default()
+            .push((block.text.chars().count(), block.word_count()));
+    }
+
+    let mut stats = text_lengths_by_type
+        .into_iter()
+        .map(|(block_type, lengths)| {
+            let count = lengths.len();
+            let total_length = lengths.iter().map(|(length, _)| length).sum::<usize>();
+            let empty = lengths.iter().filter(|(length, _)| *length == 0).count();
+            BlockTypeStats {
+                block_type,
+                count,
+                mean_text_length: total_length as f64 / count as f64,
+                max_text_length: lengths.iter().map(|(length, _)| *length).max().unwrap_or(0),
+                word_count: lengths.iter().map(|(_, words)| words).sum(),
+                empty_percentage: 100.0 * empty as f64 / count as f64,
+            }
+        })
+        .collect::<Vec<_>>();
+    stats.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.block_type.cmp(&b.block_type))
+    });
+    stats
+}
+
+/// Renders the `BlockTypeStats` as a plain-text table with aligned columns
+#[must_use]
+pub fn block_type_stats_to_table(stats: &[BlockTypeStats]) -> String {
+    let headers = [
+        "Type",
+        "Count",
+        "Mean Length",
+        "Max Length",
+        "Words",
+        "Empty",
+    ];
+    let rows = stats
+        .iter()
+        .map(|s| {
+            [
+                s.block_type.clone(),
+                s.count.to_string(),
+                format!("{:.1}", s.mean_text_length),
+                s.max_text_length.to_string(),
+                s.word_count.to_string(),
+                format!("{:.0}%", s.empty_percentage),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    format_table(headers, &rows)
+}
+
+/// One row of the per-`Block` metadata export, flattened so it can be written as CSV
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockStatsRow {
+    pub page_title: String,
+    pub page_url: String,
+    pub page_last_edited: DateTime<Utc>,
+    pub block_id: String,
+    pub block_type: String,
+    pub word_count: usize,
+    /// How far below its tree's root `Block` this `Block` is nested, with the root at 0
+    pub depth: usize,
+    pub update_date: DateTime<Utc>,
+    #[serde(skip)]
+    creation_date: DateTime<Utc>,
+}
+
+/// Flattens each `Page`'s trees into one `BlockStatsRow` per `Block`, sorted by page title,
+/// then depth, then the `Block`'s creation date so the output is the same from run to run
+#[must_use]
+pub fn collect_block_stats_rows(pages_and_trees: &[(Page, Vec<BlockTree>)]) -> Vec<BlockStatsRow> {
+    let mut rows = pages_and_trees
+        .iter()
+        .flat_map(|(page, trees)| {
+            trees
+                .iter()
+                .flat_map(BlockTree::flatten_with_depth)
+                .map(move |(block, depth)| BlockStatsRow {
+                    page_title: page.title.clone(),
+                    page_url: page.url.clone(),
+                    page_last_edited: page.update_date,
+                    block_type: block.type_name(),
+                    word_count: block.word_count(),
+                    depth,
+                    update_date: block.update_date,
+                    creation_date: block.creation_date,
+                    block_id: block.id,
+                })
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| {
+        (&a.page_title, a.depth, a.creation_date, &a.block_id).cmp(&(
+            &b.page_title,
+            b.depth,
+            b.creation_date,
+            &b.block_id,
+        ))
+    });
+    rows
+}
+
+/// Renders the rows as CSV, optionally without the header row for use in shell pipelines
+pub fn block_stats_to_csv(rows: &[BlockStatsRow], include_header: bool) -> csv::Result<String> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(include_header)
+        .from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| csv::Error::from(e.into_error()))?;
+    Ok(
+        String::from_utf8(bytes)
+            .expect("the csv writer only writes the UTF-8 strings it was given"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use notion_client::objects::block::BlockType;
+
+    use super::*;
+    use crate::core::datatypes::BlockTreeBuilder;
+
+    fn block(id: &str, created_minute: u32, text: &str) -> BlockTreeBuilder {
+        BlockTreeBuilder::new(text).id(id).created_at(
+            Utc.with_ymd_and_hms(2024, 8, 1, 12, created_minute, 0)
+                .unwrap(),
+        )
+    }
+
+    fn page(title: &str) -> Page {
+        Page {
+            url: "https://www.notion.so/page".to_string(),
+            creation_date: Utc.with_ymd_and_hms(2024, 8, 1, 0, 0, 0).unwrap(),
+            update_date: Utc.with_ymd_and_hms(2024, 8, 2, 0, 0, 0).unwrap(),
+            ..Page::for_test(title, title)
+        }
+    }
+
+    #[test]
+    fn test_page_list_to_table() {
+        let now = Utc.with_ymd_and_hms(2024, 8, 2, 3, 0, 0).unwrap();
+        let mut stats = [
+            page("Groceries"),
+            page("A very long title that goes on for far longer than forty characters"),
+        ]
+        .iter()
+        .map(PageStats::from_page)
+        .collect::<Vec<_>>();
+        stats[0].word_count = 12;
+
+        sort_page_stats(&mut stats, PageListSort::Words, true);
+        let table = page_list_to_table(&stats, now, 200);
+
+        assert_eq!(
+            table,
+            "#  Title                                    Last Edited  Blocks  Words\n\
+             1  Groceries                                3 hours ago  0       12\n\
+             2  A very long title that goes on for far…  3 hours ago  0       0\n"
+        );
+        assert!(page_list_to_table(&stats, now, 50).contains("A very long title…"));
+        assert_eq!(format_time_ago(now, now), "just now");
+        assert_eq!(
+            format_time_ago(now - chrono::Duration::days(1), now),
+            "1 day ago"
+        );
+    }
+
+    #[test]
+    fn test_page_stats_count_nested_blocks() {
+        let pages_and_trees = vec![
+            (
+                page("Short"),
+                vec![block("s1", 0, "one two")
+                    .child(block("s2", 1, "three"))
+                    .build()],
+            ),
+            (
+                page("Long"),
+                vec![
+                    block("l1", 0, "four five six")
+                        .child(block("l2", 1, "seven").child(block("l3", 2, "eight nine")))
+                        .build(),
+                    block("l4", 3, "ten").build(),
+                ],
+            ),
+        ];
+
+        let stats = collect_page_stats(&pages_and_trees);
+
+        assert_eq!(
+            stats_to_table(&stats),
+            "Title  Last Edited       Blocks  Words  Reading Time\n\
+             Long   2024-08-02 00:00  4       7      1 min\n\
+             Short  2024-08-02 00:00  2       3      1 min\n"
+        );
+    }
+
+    #[test]
+    fn test_block_stats_rows_are_sorted_deterministically() {
+        let pages_and_trees = vec![
+            (page("Zebra"), vec![block("z1", 5, "one two").build()]),
+            (
+                page("Apple"),
+                vec![
+                    block("a2", 3, "root")
+                        .child(block("a3", 1, "child"))
+                        .build(),
+                    block("a1", 2, "earlier root").build(),
+                ],
+            ),
+        ];
+
+        let rows = collect_block_stats_rows(&pages_and_trees);
+        let ids = rows
+            .iter()
+            .map(|row| row.block_id.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec!["a1", "a2", "a3", "z1"]);
+        assert_eq!(rows[2].depth, 1);
+        assert_eq!(rows[3].word_count, 2);
+    }
+
+    #[test]
+    fn test_block_type_stats() {
+        let trees = vec![
+            block("p1", 0, "one two three")
+                .child(block("p2", 1, ""))
+                .child(
+                    BlockTreeBuilder::new("Heading").block_type(BlockType::Heading1 {
+                        heading_1: Default::default(),
+                    }),
+                )
+                .build(),
+            block("p3", 2, "four").build(),
+        ];
+
+        let stats = collect_block_type_stats(&trees);
+
+        assert_eq!(stats.len(), 2);
+        let paragraphs = &stats[0];
+        assert_eq!(paragraphs.block_type, "paragraph");
+        assert_eq!(paragraphs.count, 3);
+        assert_eq!(paragraphs.max_text_length, 13);
+        assert_eq!(paragraphs.mean_text_length, 17.0 / 3.0);
+        assert_eq!(paragraphs.word_count, 4);
+        assert_eq!(paragraphs.empty_percentage, 100.0 / 3.0);
+        assert_eq!(stats[1].block_type, "heading_1");
+        assert!(block_type_stats_to_table(&stats).contains("paragraph  3      5.7"));
+    }
+
+    #[test]
+    fn test_block_stats_to_csv_escapes_and_skips_header() {
+        let pages_and_trees = vec![(
+            page("Notes, \"quoted\""),
+            vec![block("b1", 0, "hello").build()],
+        )];
+        let rows = collect_block_stats_rows(&pages_and_trees);
+
+        let with_header = block_stats_to_csv(&rows, true).unwrap();
+        let mut lines = with_header.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "page_title,page_url,page_last_edited,block_id,block_type,word_count,depth,update_date"
+        );
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("\"Notes, \"\"quoted\"\"\",https://www.notion.so/page,"));
+
+        let without_header = block_stats_to_csv(&rows, false).unwrap();
+        assert_eq!(without_header.lines().count(), 1);
+        assert!(without_header.contains(",b1,paragraph,1,0,"));
+    }
+}
@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use sha2::Sha256;
+
+use super::error::DrossError;
+
+/// The header the HMAC-SHA256 signature of the request body is sent in, when a secret is configured
+pub const SIGNATURE_HEADER: &str = "X-Dross-Signature";
+
+/// How many times to try POSTing before giving up
+const MAX_ATTEMPTS: usize = 2;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookPayload {
+    pub content: String,
+    pub page_count: usize,
+    pub word_count: usize,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl WebhookPayload {
+    #[must_use]
+    pub fn new(content: String, page_count: usize) -> Self {
+        WebhookPayload {
+            word_count: content.split_whitespace().count(),
+            content,
+            page_count,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// Signs `body` with `secret` using HMAC-SHA256, in the `sha256=<hex digest>` format
+/// receivers like GitHub's webhooks use
+#[must_use]
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs the payload as JSON to `url`, retrying once if the request fails or the endpoint
+/// responds with a non-success status
+pub async fn post_to_webhook(
+    url: &str,
+    secret: Option<&str>,
+    payload: &WebhookPayload,
+) -> Result<(), DrossError> {
+    let body = serde_json::to_vec(payload).expect("WebhookPayload always serializes");
+    let client = reqwest::Client::new();
+
+    let mut last_error = None;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let mut request = client
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(secret) = secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &body));
+        }
+
+        match request.send().await {
+            Ok(res) if res.status().is_success() => {
+                info!("webhook responded with {}", res.status());
+                return Ok(());
+            }
+            Ok(res) => {
+                warn!(
+                    "webhook responded with {} on attempt {}",
+                    res.status(),
+                    attempt
+                );
+                last_error = Some(DrossError::UnexpectedStatus {
+                    url: url.to_string(),
+                    status: res.status().as_u16(),
+                });
+            }
+            Err(e) => {
+                warn!("webhook request failed on attempt {}: {}", attempt, e);
+                last_error = Some(DrossError::Http(e));
+            }
+        }
+    }
+
+    Err(last_error.expect("at least one attempt is always made"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
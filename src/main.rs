@@ -1,53 +1,1530 @@
-use chrono::Duration;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
 use dotenv::dotenv;
-use dross::{core::helpers::build_markdown_from_trees, notion::Notion};
-use log::{debug, info};
-use std::env;
+use dross::{
+    core::{
+        access_history::{access_counts_to_table, AccessHistory},
+        benchmark::{benchmark_to_table, run_benchmark, save_benchmark},
+        config::{Config, ConfigSource},
+        datatypes::{Block, Page, PageOutput, PromptContext},
+        error::DrossError,
+        export::{export_page_tree, write_pages_to_dir},
+        gist::{save_github_token, saved_github_token},
+        helpers::{
+            block_tree_lines, fuzzy_rank, log_text_max, markdown_to_blocks,
+            markdown_to_slack_mrkdwn, strip_markdown, truncate_for_log, wrap_markdown, BlockSort,
+            ContextWindow, DuplicateCheck, PageOrder, PagePriority, TraversalStrategy,
+            UrlExtractor, DEFAULT_TOKEN_MODEL, SLACK_MAX_CHARS,
+        },
+        ingest::{ingest_notion, IngestOptions},
+        markdown::DEFAULT_COLUMN_SEPARATOR,
+        output::{OutputEncoding, OutputFormat},
+        page_map::PageMap,
+        performance::performance_report_to_table,
+        sink::{
+            write_to_sinks, ClipboardSink, FileSink, GistSink, OutputSink, StdoutSink, WebhookSink,
+        },
+        state::{
+            access_history_path, benchmark_path, config_path, http_log_path, page_map_path,
+            perf_history_path, remove_state_file, run_state_path, search_cache_path,
+        },
+        stats::{
+            block_stats_to_csv, block_type_stats_to_table, collect_block_stats_rows,
+            collect_block_type_stats, collect_page_stats, page_list_to_table, page_list_to_tsv,
+            sort_page_stats, stats_to_table, PageListSort, PageStats,
+        },
+    },
+    notion::{is_api_version, Notion, NotionBuilder},
+};
+use log::{error, info, warn};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process,
+};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+/// A lookback long enough to include everything ever written in Notion
+const NO_CUTOFF_DAYS: i64 = 365 * 100;
+
+#[derive(Parser)]
+#[command(
+    name = "dross",
+    about = "A tool for expanding the power of your exobrain"
+)]
+struct Cli {
+    /// How many days back to look for edited Notion Pages
+    #[arg(long, global = true, value_parser = clap::value_parser!(i64).range(1..))]
+    days: Option<i64>,
+
+    /// How to order each Page's changed Blocks before growing them into trees [default: none]
+    #[arg(long, value_enum)]
+    sort_blocks: Option<BlockSort>,
+
+    /// The order to output Pages in
+    #[arg(long, value_enum, default_value_t = PageOrder::Edited)]
+    page_order: PageOrder,
+
+    /// How to lay out the generated markdown
+    #[arg(long = "format", value_enum, default_value_t = OutputFormat::Markdown)]
+    output_format: OutputFormat,
+
+    /// Add a field to the frontmatter of --format frontmatter-yaml, replacing any field with the
+    /// same key. Can be passed more than once
+    #[arg(long, value_name = "KEY:VALUE", value_parser = parse_frontmatter_field)]
+    frontmatter_extra: Vec<(String, String)>,
+
+    /// Wrap long lines of the generated markdown at this many columns, for reading it in a
+    /// terminal rather than pasting it into a prompt
+    #[arg(long, value_name = "WIDTH", num_args = 0..=1, default_missing_value = "80")]
+    word_wrap: Option<usize>,
+
+    /// Write the generated markdown to this file instead of stdout
+    #[arg(long)]
+    output_file: Option<PathBuf>,
+
+    /// Write each Page to its own <title>.md file in this directory instead of stdout
+    #[arg(long, conflicts_with = "output_file")]
+    output_dir: Option<PathBuf>,
+
+    /// Add the generated markdown to the top of --output-file under a timestamped header,
+    /// rather than overwriting it
+    #[arg(long, requires = "output_file")]
+    output_append: bool,
+
+    /// Also copy the generated markdown to the clipboard, with pbcopy, clip, wl-copy, or xclip
+    #[arg(long)]
+    clipboard: bool,
+
+    /// Record which Pages this run includes, for `dross stats --access-history`
+    #[arg(long)]
+    track_access: bool,
+
+    /// Leave out Pages from the end of the output until it's at most this many kilobytes, for
+    /// tools that limit how much they'll take. Pages are never cut in half
+    #[arg(long)]
+    max_output_size_kb: Option<usize>,
+
+    /// With --format slack-mrkdwn, leave out Pages from the end of the output until it's at
+    /// most this many characters, so Slack doesn't cut the message off
+    #[arg(long, value_name = "N", default_value_t = SLACK_MAX_CHARS)]
+    slack_max_chars: usize,
+
+    /// With --output-append, archive --output-file to <file>.1 and start a new one once it
+    /// grows past this many kilobytes
+    #[arg(long, requires = "output_append")]
+    output_append_max_size_kb: Option<u64>,
+
+    /// Refetch the cached map of Page titles even if it hasn't expired
+    #[arg(long)]
+    refresh_page_map: bool,
+
+    /// How many hours the cached map of Page titles is used for before it is refetched [default: 24]
+    #[arg(long, value_parser = clap::value_parser!(i64).range(1..))]
+    page_map_ttl_hours: Option<i64>,
+
+    /// How many minutes a search for recently edited Pages is reused by later runs with the
+    /// same --days, or 0 to always search [default: 0]
+    #[arg(long, value_parser = clap::value_parser!(i64).range(0..))]
+    search_cache_ttl_minutes: Option<i64>,
+
+    /// Skip the rest of a Block's children when searching for recently edited Blocks after
+    /// seeing this many old ones in a row [default: 10]
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    consecutive_old_blocks_abort_threshold: Option<usize>,
+
+    /// Search below at most this many old Blocks on each Page, skipping the children of the
+    /// rest. A more predictable bound on huge Pages than the search's time limit
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_blocks_per_page: Option<usize>,
+
+    /// Log every Notion API request and response at TRACE level, and append them as JSON lines
+    /// to ~/.local/share/dross/http_log.ndjson. Also turned on by DROSS_LOG_HTTP=true
+    #[arg(long)]
+    log_http: bool,
+
+    /// Print how long each stage of the run took to stderr, and append it as a JSON line to
+    /// ~/.local/share/dross/perf_history.ndjson. Also turned on by DROSS_PERF=1
+    #[arg(long)]
+    perf: bool,
+
+    /// Stop with an error instead of making more than this many Notion API calls
+    #[arg(long, value_name = "N")]
+    api_budget: Option<u32>,
+
+    /// The Notion API version to make requests against, e.g. 2022-06-28. Features the version
+    /// doesn't support are skipped [default: 2022-06-28]
+    #[arg(long, value_name = "VERSION", value_parser = parse_notion_version)]
+    notion_version: Option<String>,
+
+    /// The order to search below old Blocks for recently edited ones in [default: dfs]
+    #[arg(long, value_enum)]
+    traversal_strategy: Option<TraversalStrategy>,
+
+    /// Only use the N most recently edited Pages, or with --created the N most recently
+    /// created ones. Combine with --days to get the N most recent Pages within that window,
+    /// otherwise there is no time window at all
+    #[arg(long)]
+    last_n_pages: Option<usize>,
+
+    /// Drop Pages until the output has at most this many words, lowest --prioritize-by first
+    #[arg(long)]
+    max_words: Option<usize>,
+
+    /// Which Pages to keep when --max-words or --max-tokens cuts the output down
+    #[arg(long, value_enum, default_value_t = PagePriority::Recency)]
+    prioritize_by: PagePriority,
+
+    /// Put Notion's headings this many levels deeper, e.g. 1 turns heading 1 into ##, for
+    /// embedding the output in a document with headings of its own. Headings deeper than ######
+    /// become bold text
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-2..=3))]
+    heading_offset: i8,
+
+    /// The line written between the columns of a multi-column layout, since markdown has no
+    /// columns of its own
+    #[arg(long, default_value = DEFAULT_COLUMN_SEPARATOR)]
+    column_separator: String,
+
+    /// How to tell that a Block in more than one grown tree was already written: by its ID
+    /// alone, or by its ID, content and parent, ignoring Notion's timestamp updates
+    #[arg(long, value_enum, default_value_t = DuplicateCheck::ById)]
+    duplicate_check: DuplicateCheck,
+
+    /// Cut Pages down until the output, as it's written, is at most this many LLM tokens, lowest
+    /// --prioritize-by first. Pages lose whole sections from their end before being dropped
+    #[arg(long)]
+    max_tokens: Option<usize>,
+
+    /// The model whose tokenizer counts tokens for --max-tokens
+    #[arg(long, default_value = DEFAULT_TOKEN_MODEL, requires = "max_tokens")]
+    token_model: String,
+
+    /// Only include Pages whose --tag-property multi-select has every one of these tags. Can
+    /// be repeated, and each Page's properties are fetched to check them
+    #[arg(long, num_args = 1.., requires = "tag_property")]
+    page_tags: Vec<String>,
+
+    /// The multi-select property of database rows that --page-tags are looked for in, e.g.
+    /// "Tags" or "Project"
+    #[arg(long, requires = "page_tags")]
+    tag_property: Option<String>,
+
+    /// Select Pages created within --days, rather than edited within it
+    #[arg(long)]
+    created: bool,
+
+    /// Leave out Pages last edited after this time, given as an RFC 3339 timestamp or a date
+    /// (which means the end of that day, UTC). Combine with --days to look back at a particular
+    /// day or week. Not supported by tree, doctor, or benchmark
+    #[arg(long, value_parser = parse_until, conflicts_with = "created")]
+    until: Option<DateTime<Utc>>,
+
+    /// Only include Pages created by the Notion user with this ID
+    #[arg(long)]
+    created_by: Option<String>,
+
+    /// Only include Pages created by the Notion user whose name best fuzzy matches this
+    #[arg(long, conflicts_with = "created_by")]
+    created_by_name: Option<String>,
+
+    /// Prepend database row Pages with their properties as YAML frontmatter
+    #[arg(long)]
+    include_properties: bool,
+
+    /// Pick which of the recently edited Pages to process from a checklist. Skipped when not
+    /// running in a terminal
+    #[arg(long)]
+    interactive: bool,
+
+    /// Replace Notion Page and Block IDs that show up in Block text with [ref]
+    #[arg(long)]
+    sanitize_ids: bool,
+
+    /// Only output the recently edited Blocks themselves, without the Blocks nested under
+    /// them. Much faster, and makes far fewer Notion API calls
+    #[arg(long)]
+    blocks_only: bool,
+
+    /// POST the generated markdown as JSON to this URL
+    #[arg(long)]
+    webhook_url: Option<String>,
+
+    /// Sign --webhook-url requests with HMAC-SHA256 using this secret, sent in the X-Dross-Signature header
+    #[arg(long, requires = "webhook_url")]
+    webhook_secret: Option<String>,
+
+    /// Fail the run if any output step (like the webhook) fails, instead of just logging it
+    #[arg(long)]
+    strict: bool,
+
+    /// Record every Notion API response to this directory, for use as test fixtures
+    #[arg(long)]
+    record_fixtures: Option<PathBuf>,
+
+    /// The encoding to use for --output-file [default: utf8]
+    #[arg(long, value_enum)]
+    output_encoding: Option<OutputEncoding>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Report page, block, and word counts for recently edited Pages
+    Stats {
+        /// csv emits one row per Block, rather than one per Page
+        #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+        /// Leave out the header row of --format csv
+        #[arg(long)]
+        no_header: bool,
+        /// Instead, report how many runs in the last 30 days included each Page, most often
+        /// included first. Runs are only recorded with --track-access
+        #[arg(long)]
+        access_history: bool,
+    },
+    /// Report text length statistics for each type of recently edited Block, to help spot
+    /// Block types whose text isn't being extracted
+    Analyze {
+        #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+    },
+    /// List every URL linked to from recently edited Blocks
+    Urls {
+        #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+    },
+    /// Export a Page and every Page nested under it as markdown, mirroring the Notion hierarchy as directories
+    Export {
+        /// The ID or URL of the Page to export
+        #[arg(long, value_parser = parse_page_id)]
+        page: String,
+        /// The directory to export into
+        #[arg(long, default_value = ".")]
+        output_dir: PathBuf,
+    },
+    /// Print the grown trees of a Page's recently edited Blocks, one Block per line like the
+    /// Unix `tree` command, for debugging how its output was put together
+    Tree {
+        /// The ID or URL of the Page to draw
+        #[arg(value_parser = parse_page_id)]
+        page_id: String,
+    },
+    /// Upload the generated markdown to a GitHub gist, one file per Page, and print its URL
+    ExportGist {
+        /// Make the gist public rather than secret
+        #[arg(long)]
+        public: bool,
+        #[arg(long, default_value = "Recently edited Notion Pages")]
+        description: String,
+        /// A GitHub token with the gist scope. It's saved to the system keyring so it only
+        /// has to be passed once [default: the saved token, or $GITHUB_TOKEN]
+        #[arg(long)]
+        token: Option<String>,
+        /// Replace the files of this existing gist instead of creating a new one
+        #[arg(long)]
+        update_gist_id: Option<String>,
+    },
+    /// List recently edited Pages, optionally fuzzy searching their titles
+    Pages {
+        /// Only list Pages whose titles fuzzy match this query, best match first
+        #[arg(long)]
+        search: Option<String>,
+        /// Search every Page in the workspace, not just the recently edited ones
+        #[arg(long, requires = "search")]
+        all: bool,
+        /// Sort the Pages by this instead of most recently edited first
+        #[arg(long, value_enum, conflicts_with = "search")]
+        sort: Option<PageListSort>,
+        /// Reverse the --sort order
+        #[arg(long, requires = "sort")]
+        desc: bool,
+    },
+    /// Create a new Notion Page from a local markdown file
+    Push {
+        /// The markdown file to create the Page from
+        #[arg(long)]
+        file: PathBuf,
+        /// The ID or URL of the Page to create the new Page under
+        #[arg(long, value_parser = parse_page_id)]
+        parent: String,
+        /// The new Page's title [default: the file's name, without its extension]
+        #[arg(long)]
+        title: Option<String>,
+    },
+    /// Diagnose a Page's Blocks, listing any edited within --days whose parent Block isn't on
+    /// the Page. Fetches every Block on the Page, so it can take a while on big Pages
+    Doctor {
+        /// The ID or URL of the Page to diagnose
+        #[arg(long, value_parser = parse_page_id)]
+        page: String,
+    },
+    /// Check that the Notion API is reachable and the token is valid, exiting with an error if not
+    Health {
+        /// Consider the API unreachable if it hasn't responded within this many seconds
+        #[arg(long, default_value_t = 10)]
+        timeout_secs: u64,
+    },
+    /// Time each phase of an ingest of a few recently edited Pages, to find where runs spend
+    /// their time. Results are also added to ~/.local/share/dross/benchmark.json
+    Benchmark {
+        /// How many of the most recently edited Pages to ingest
+        #[arg(long, default_value_t = 10)]
+        pages: usize,
+        #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+    },
+    /// List the users in the Notion workspace
+    Users {
+        #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+    },
+    /// Show or change the settings in ~/.config/dross/config.toml
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Delete dross's local state from ~/.local/share/dross/. Without any flags, deletes the
+    /// cache, the state, and the page map
+    Clean {
+        /// Delete the cache of recent Page searches
+        #[arg(long)]
+        cache: bool,
+        /// Delete the state carried between runs, like the last run's timestamp
+        #[arg(long)]
+        state: bool,
+        /// Delete the cached map of Page titles
+        #[arg(long)]
+        page_map: bool,
+        /// Delete all of the above, and offer to delete the config file too
+        #[arg(long)]
+        all: bool,
+        /// Print what would be deleted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective settings, merged from the config file, the environment, and the
+    /// command line flags, along with where each one came from
+    Show,
+    /// Print the value of a single setting from the config file, e.g. `ingest.days`
+    Get { key: String },
+    /// Validate a value and save it to the config file, e.g. `dross config set ingest.days 3`
+    Set { key: String, value: String },
+}
+
+/// Accepts a Page's URL as well as its ID, so it can be copied straight from Notion
+fn parse_page_id(page: &str) -> Result<String, String> {
+    Ok(Page::url_to_id(page).unwrap_or_else(|| page.to_string()))
+}
+
+/// Parses `--until`, where a date on its own means the last second of that day
+fn parse_until(until: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(until) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(until, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(23, 59, 59))
+        .map(|time| time.and_utc())
+        .ok_or_else(|| {
+            format!(
+                "expected an RFC 3339 timestamp or YYYY-MM-DD, got {}",
+                until
+            )
+        })
+}
+
+/// Splits a `--frontmatter-extra` argument on its first colon
+fn parse_frontmatter_field(field: &str) -> Result<(String, String), String> {
+    match field.split_once(':') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("expected KEY:VALUE, got {}", field)),
+    }
+}
+
+fn parse_notion_version(version: &str) -> Result<String, String> {
+    if is_api_version(version) {
+        Ok(version.to_string())
+    } else {
+        Err(format!("expected a date like 2022-06-28, got {}", version))
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Table,
+    Json,
+    Csv,
+}
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     env_logger::init();
 
-    let dur: Duration = Duration::days(match env::var("RUST_LOG") {
-        Ok(log_level) => match log_level.to_lowercase().as_str() {
-            "debug" | "trace" => 1,
-            _ => 7,
-        },
-        Err(_) => 7,
-    }); // TODO, make this a CLI arg, for now we're just differentiating
-        // between DEBUG and non-debug to speed iterating on debugging
+    let cli = Cli::parse();
+
+    let file_config = Config::load(&config_path()).unwrap_or_else(|e| {
+        error!("failed to read {}: {}", config_path().display(), e);
+        process::exit(1);
+    });
+    let env_config = Config::from_env().unwrap_or_else(|e| {
+        error!("failed to read settings from the environment: {}", e);
+        process::exit(1);
+    });
+    let (config, config_sources) = Config::merge(&[
+        (ConfigSource::File, &file_config),
+        (ConfigSource::Env, &env_config),
+        (ConfigSource::Cli, &cli_config(&cli)),
+    ])
+    .unwrap_or_else(|e| {
+        error!("{}", e);
+        process::exit(1);
+    });
+
+    if let Some(Command::Config { action }) = &cli.command {
+        let succeeded = manage_config(action, file_config, &config, &config_sources);
+        process::exit(if succeeded { 0 } else { 1 });
+    }
+
+    let dur: Duration = Duration::days(config.ingest.days.unwrap_or_else(|| {
+        if cli.last_n_pages.is_some() {
+            // --last-n-pages on its own shouldn't be limited by any time window
+            return NO_CUTOFF_DAYS;
+        }
+        match env::var("RUST_LOG") {
+            Ok(log_level) => match log_level.to_lowercase().as_str() {
+                "debug" | "trace" => 1,
+                _ => 7,
+            },
+            Err(_) => 7,
+        }
+    })); // when --days isn't passed we differentiate between DEBUG and
+         // non-debug to speed iterating on debugging
+
+    if let Some(Command::Clean {
+        cache,
+        state,
+        page_map,
+        all,
+        dry_run,
+    }) = cli.command
+    {
+        // with nothing picked, clean everything but the config file
+        let default = !(cache || state || page_map || all);
+        let succeeded = clean(
+            cache || all || default,
+            state || all || default,
+            page_map || all || default,
+            all,
+            dry_run,
+        );
+        process::exit(if succeeded { 0 } else { 1 });
+    }
+
+    if let Some(until) = cli.until {
+        if until < Utc::now() - dur {
+            error!(
+                "--until {} is before the start of the last {} days, so no Pages could match. Pass a later --until or more --days",
+                until.to_rfc3339(),
+                dur.num_days()
+            );
+            process::exit(1);
+        }
+        if matches!(
+            cli.command,
+            Some(Command::Tree { .. } | Command::Doctor { .. } | Command::Benchmark { .. })
+        ) {
+            error!("--until doesn't apply to the tree, doctor, and benchmark subcommands");
+            process::exit(1);
+        }
+    }
 
     // ingest notes data from Notion
-    let notion_token: String = env::var("NOTION_TOKEN").expect("NOTION_TOKEN must be set");
-    let notion = Notion::new(notion_token).unwrap();
+    let notion_token = config
+        .notion
+        .token
+        .clone()
+        .expect("NOTION_TOKEN must be set");
+    let mut builder = NotionBuilder::new(notion_token);
+    if let Some(fixtures_dir) = &cli.record_fixtures {
+        builder = builder.record_fixtures(fixtures_dir.clone());
+    }
+    if let Some(budget) = cli.api_budget {
+        builder = builder.api_call_budget(budget);
+    }
+    let log_http = cli.log_http
+        || env::var("DROSS_LOG_HTTP").is_ok_and(|value| value == "true" || value == "1");
+    if log_http {
+        builder = builder.log_http(http_log_path());
+    }
+    if let Some(version) = &cli.notion_version {
+        builder = builder.api_version(version.clone());
+    }
+    let mut notion = builder.build().unwrap_or_else(|e| {
+        report_error(&e);
+        process::exit(1);
+    });
+    if let Some(threshold) = config.ingest.consecutive_old_blocks_abort_threshold {
+        notion.set_consecutive_old_blocks_abort_threshold(threshold);
+    }
+    if let Some(strategy) = config.ingest.traversal_strategy {
+        notion.set_traversal_strategy(strategy);
+    }
+    notion.set_max_blocks_per_page(config.ingest.max_blocks_per_page);
 
-    let pages_edited_within_dur = notion.get_last_edited_pages(dur).await.unwrap();
-    info!(target: "notion", "retrieved {} Pages edited in the last {} days", pages_edited_within_dur.len(), dur.num_days());
-    let mut pages_and_block_roots = Vec::new();
-    for page in pages_edited_within_dur {
-        debug!(target: "notion", "Page URL: {}", page.url);
+    let result = run(&notion, &cli, &config, dur).await;
+    info!(target: "notion", "made {} Notion API calls", notion.api_calls_made());
+    if let Err(e) = result {
+        match e {
+            CommandError::Dross(e) => report_error(&e),
+            CommandError::Other(message) => error!("{}", message),
+        }
+        process::exit(1);
+    }
+}
 
-        let new_block_roots = notion.get_page_block_roots(&page, dur).await.unwrap();
-        pages_and_block_roots.push((page, new_block_roots));
+/// Why a subcommand failed
+#[derive(Debug)]
+enum CommandError {
+    /// Explained by `report_error`
+    Dross(DrossError),
+    /// Anything else, explained by this message
+    Other(String),
+}
+
+impl From<DrossError> for CommandError {
+    fn from(e: DrossError) -> Self {
+        CommandError::Dross(e)
+    }
+}
+
+/// Runs the subcommand in `cli`, or the main ingest when there isn't one
+async fn run(
+    notion: &Notion,
+    cli: &Cli,
+    config: &Config,
+    dur: Duration,
+) -> Result<(), CommandError> {
+    match &cli.command {
+        Some(Command::Stats {
+            format,
+            access_history: true,
+            ..
+        }) => {
+            access_history_stats(*format);
+            Ok(())
+        }
+        Some(Command::Stats {
+            format, no_header, ..
+        }) => stats(notion, dur, cli.until, *format, *no_header).await,
+        Some(Command::Analyze { format }) => analyze(notion, dur, cli.until, *format).await,
+        Some(Command::Urls { format }) => urls(notion, dur, cli.until, *format).await,
+        Some(Command::Export { page, output_dir }) => {
+            let page_count = export_page_tree(notion, page, output_dir).await?;
+            println!("Exported {} Pages to {}", page_count, output_dir.display());
+            Ok(())
+        }
+        Some(Command::Tree { page_id }) => tree(notion, dur, page_id).await,
+        Some(Command::Pages {
+            search,
+            all,
+            sort,
+            desc,
+        }) => {
+            let page_map_ttl = Duration::hours(config.ingest.page_map_ttl_hours.unwrap_or(24));
+            match search {
+                Some(query) => {
+                    search_pages(notion, dur, cli.until, query, *all, page_map_ttl).await
+                }
+                None => list_pages(notion, dur, cli.until, *sort, *desc).await,
+            }
+        }
+        Some(Command::Push {
+            file,
+            parent,
+            title,
+        }) => push(notion, file, parent, title.as_deref()).await,
+        Some(Command::Doctor { page }) => doctor(notion, dur, page).await,
+        Some(Command::Health { timeout_secs }) => health(notion, *timeout_secs).await,
+        Some(Command::Users { format }) => users(notion, *format).await,
+        Some(Command::Benchmark { pages, format }) => benchmark(notion, dur, *pages, *format).await,
+        Some(Command::Config { .. }) | Some(Command::Clean { .. }) => {
+            unreachable!("config and clean are handled before connecting to Notion")
+        }
+        Some(Command::ExportGist {
+            public,
+            description,
+            token,
+            update_gist_id,
+        }) => {
+            let options = ingest_options(notion, cli, config, dur).await?;
+            export_gist(
+                notion,
+                &options,
+                *public,
+                description,
+                token.as_deref(),
+                update_gist_id.as_deref(),
+            )
+            .await
+        }
+        None => {
+            let options = ingest_options(notion, cli, config, dur).await?;
+            ingest(notion, &options, cli, config).await
+        }
+    }
+}
+
+/// The Pages last edited within `dur`, leaving out those edited after `until`, for the
+/// subcommands that report on recently edited Pages
+async fn recently_edited_pages(
+    notion: &Notion,
+    dur: Duration,
+    until: Option<DateTime<Utc>>,
+) -> Result<Vec<Page>, DrossError> {
+    match until {
+        Some(until) => notion.search_by_date_range(Utc::now() - dur, until).await,
+        None => notion.get_last_edited_pages(dur).await,
     }
+}
 
-    debug!(target: "notion", "retrieved {} pages and their block roots, now we will grow them!", pages_and_block_roots.len());
+/// How far back `dross stats --access-history` looks
+const ACCESS_HISTORY_REPORT_DAYS: i64 = 30;
 
-    let mut every_prompt_markdown = Vec::new();
-    for (page, block_roots) in pages_and_block_roots {
-        let trees = notion.grow_the_roots(block_roots).await.unwrap();
-        debug!(target: "notion", "grown {} trees, and they look like:", trees.len());
-        debug!(target: "notion", "{:?}", trees);
+/// The ingest options for the main run and the subcommands that build the same output
+async fn ingest_options(
+    notion: &Notion,
+    cli: &Cli,
+    config: &Config,
+    dur: Duration,
+) -> Result<IngestOptions, CommandError> {
+    Ok(IngestOptions {
+        dur,
+        sort_blocks: config.ingest.sort_blocks.unwrap_or_default(),
+        page_order: cli.page_order,
+        page_map_ttl: Duration::hours(config.ingest.page_map_ttl_hours.unwrap_or(24)),
+        refresh_page_map: cli.refresh_page_map,
+        page_map_path: page_map_path(),
+        search_cache_ttl: Duration::minutes(config.ingest.search_cache_ttl_minutes.unwrap_or(0)),
+        search_cache_path: search_cache_path(),
+        last_n_pages: cli.last_n_pages,
+        until: cli.until,
+        select_by_creation: cli.created,
+        created_by: match &cli.created_by_name {
+            Some(name) => Some(user_id_for_name(notion, name).await?),
+            None => cli.created_by.clone(),
+        },
+        page_tags: cli.page_tags.clone(),
+        tag_property: cli.tag_property.clone(),
+        include_properties: cli.include_properties,
+        sanitize_ids: cli.sanitize_ids,
+        blocks_only: cli.blocks_only,
+        interactive: cli.interactive,
+        heading_offset: cli.heading_offset,
+        column_separator: cli.column_separator.clone(),
+        duplicate_check: cli.duplicate_check,
+    })
+}
 
-        let single_page_prompt_markdown = build_markdown_from_trees(trees);
-        every_prompt_markdown.push(format!(
-            "Page Title: {}\n{:?}",
-            page.title, single_page_prompt_markdown
+async fn ingest(
+    notion: &Notion,
+    options: &IngestOptions,
+    cli: &Cli,
+    config: &Config,
+) -> Result<(), CommandError> {
+    let mut prompt_context = ingest_notion(notion, options).await?;
+    if let Some(max_words) = cli.max_words {
+        let dropped = prompt_context.truncate_to_words(max_words, cli.prioritize_by);
+        if dropped > 0 {
+            info!(
+                "dropped {} Pages to fit within {} words",
+                dropped, max_words
+            );
+        }
+    }
+    if let Some(width) = cli.word_wrap {
+        for output in &mut prompt_context.pages {
+            output.markdown = wrap_markdown(&output.markdown, width);
+        }
+    }
+    let render_page = |output: &PageOutput| match cli.output_format {
+        OutputFormat::Markdown => output.to_markdown(),
+        OutputFormat::FrontmatterYaml => output.to_frontmatter_markdown(&cli.frontmatter_extra),
+        OutputFormat::PlainText => strip_markdown(&output.to_markdown()),
+        OutputFormat::SlackMrkdwn => markdown_to_slack_mrkdwn(&output.to_markdown()),
+    };
+    // each Page as it's written out, so --max-tokens can count exactly what's written
+    let render_pages = |context: &PromptContext| match cli.output_format {
+        OutputFormat::Markdown => context.to_markdown_pages(),
+        OutputFormat::FrontmatterYaml => {
+            context.to_frontmatter_markdown_pages(&cli.frontmatter_extra)
+        }
+        OutputFormat::PlainText => context
+            .to_markdown_pages()
+            .iter()
+            .map(|page| strip_markdown(page))
+            .collect(),
+        OutputFormat::SlackMrkdwn => context
+            .to_markdown_pages()
+            .iter()
+            .map(|page| markdown_to_slack_mrkdwn(page))
+            .collect(),
+    };
+    let page_separator = match cli.output_format {
+        OutputFormat::FrontmatterYaml => "\n",
+        _ => "\n\n",
+    };
+    let render = |context: &PromptContext| render_pages(context).join(page_separator);
+    // the notes are written in markdown, so they're rendered like the rest of the output
+    let render_note = |note: &str| match cli.output_format {
+        OutputFormat::Markdown | OutputFormat::FrontmatterYaml => note.to_string(),
+        OutputFormat::PlainText => strip_markdown(note),
+        OutputFormat::SlackMrkdwn => markdown_to_slack_mrkdwn(note),
+    };
+    let slack_note = matches!(cli.output_format, OutputFormat::SlackMrkdwn).then(|| {
+        render_note(&format!(
+            "\n\n_[Output truncated at Slack's {} character limit. Use --slack-max-chars to adjust.]_",
+            cli.slack_max_chars
+        ))
+    });
+    // when both limits are hit, both notes are added, so each limit leaves room for both
+    let mut truncation_notes = Vec::new();
+    if let Some(max_kb) = cli.max_output_size_kb {
+        let note = render_note(&format!(
+            "\n\n_[Output truncated at {}KB limit. Use --max-output-size-kb to adjust.]_",
+            max_kb
         ));
+        let max_bytes = max_kb
+            .saturating_mul(1024)
+            .saturating_sub(note.len() + slack_note.as_ref().map_or(0, String::len));
+        let dropped = prompt_context.truncate_to_bytes(max_bytes, render);
+        if !dropped.is_empty() {
+            let titles = dropped
+                .iter()
+                .map(|output| output.page.title.as_str())
+                .collect::<Vec<_>>();
+            info!(
+                "dropped {} Pages to fit within {}KB: {}",
+                dropped.len(),
+                max_kb,
+                titles.join(", ")
+            );
+            truncation_notes.push(note);
+        }
+    }
+    if let Some(note) = slack_note {
+        let notes_chars = truncation_notes
+            .iter()
+            .chain([&note])
+            .map(|note| note.chars().count())
+            .sum::<usize>();
+        let max_chars = cli.slack_max_chars.saturating_sub(notes_chars);
+        let dropped = prompt_context.truncate_to_chars(max_chars, render);
+        if !dropped.is_empty() {
+            let titles = dropped
+                .iter()
+                .map(|output| output.page.title.as_str())
+                .collect::<Vec<_>>();
+            info!(
+                "dropped {} Pages to fit within {} characters: {}",
+                dropped.len(),
+                cli.slack_max_chars,
+                titles.join(", ")
+            );
+            truncation_notes.push(note);
+        }
+    }
+    let mut prompt_info = match cli.max_tokens {
+        Some(max_tokens) => {
+            // the token limit comes last, so it can leave room for the notes of the others
+            let mut window =
+                ContextWindow::new(max_tokens, &cli.token_model).with_separator(page_separator);
+            let notes_tokens = truncation_notes
+                .iter()
+                .map(|note| window.count_tokens(note))
+                .sum::<usize>();
+            window.max_tokens = max_tokens.saturating_sub(notes_tokens);
+            let dropped =
+                prompt_context.truncate_to_tokens(&mut window, cli.prioritize_by, render_pages);
+            if !dropped.is_empty() {
+                info!(
+                    "dropped {} Pages to fit within {} tokens, {} tokens to spare",
+                    dropped.len(),
+                    max_tokens,
+                    window.remaining_tokens()
+                );
+            }
+            window.to_prompt()
+        }
+        None => render(&prompt_context),
+    };
+    for note in truncation_notes {
+        prompt_info.push_str(&note);
+    }
+
+    let output_encoding = config.output.encoding.unwrap_or_default();
+    if let Some(dir) = &cli.output_dir {
+        let page_count = write_pages_to_dir(&prompt_context, dir, render_page)?;
+        info!("wrote {} Pages to {}", page_count, dir.display());
+    }
+    let mut sinks: Vec<Box<dyn OutputSink>> = Vec::new();
+    match &cli.output_file {
+        _ if cli.output_dir.is_some() => {}
+        Some(path) => sinks.push(Box::new(FileSink {
+            path: path.clone(),
+            encoding: output_encoding,
+            append: cli.output_append,
+            max_size_bytes: cli
+                .output_append_max_size_kb
+                .map(|kb| kb.saturating_mul(1024)),
+        })),
+        None => {
+            if output_encoding != OutputEncoding::Utf8 {
+                warn!("--output-encoding only applies to --output-file, writing UTF-8 to stdout");
+            }
+            sinks.push(Box::new(StdoutSink));
+        }
+    }
+    if cli.clipboard {
+        sinks.push(Box::new(ClipboardSink));
+    }
+    if let Some(url) = &config.output.webhook_url {
+        sinks.push(Box::new(WebhookSink {
+            url: url.clone(),
+            secret: config.output.webhook_secret.clone(),
+        }));
+    }
+    let errors = write_to_sinks(&sinks, &prompt_context, &prompt_info).await;
+    // failing to write locally (a file, stdout, the clipboard) always fails the run, but
+    // failing to reach a remote destination only does with --strict
+    let fatal = cli.strict || errors.iter().any(|e| matches!(e, DrossError::Io(_)));
+    if fatal && !errors.is_empty() {
+        let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+        return Err(CommandError::Other(format!(
+            "failed to write output: {}",
+            errors.join("; ")
+        )));
+    }
+    for e in &errors {
+        warn!("failed to write output, continuing anyway: {}", e);
+    }
+
+    if config.ingest.track_access == Some(true) {
+        let path = access_history_path();
+        let mut history = AccessHistory::load(&path);
+        let pages = prompt_context
+            .pages
+            .iter()
+            .map(|output| output.page.clone())
+            .collect::<Vec<_>>();
+        history.record_run(&pages, prompt_context.generated_at);
+        if let Err(e) = history.save(&path) {
+            warn!(
+                "failed to save the access history to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    if cli.perf || env::var("DROSS_PERF").is_ok_and(|value| value == "true" || value == "1") {
+        eprint!(
+            "{}",
+            performance_report_to_table(&prompt_context.performance)
+        );
+        let path = perf_history_path();
+        if let Err(e) = prompt_context.performance.append_to(&path) {
+            warn!(
+                "failed to append the performance report to {}: {}",
+                path.display(),
+                e
+            );
+        }
     }
-    let prompt_info = every_prompt_markdown.join("\n\n");
-    debug!(target: "notion", "prompt info:\n{}", prompt_info);
 
     info!(target: "notion", "notion page ingestion successful");
+    Ok(())
+}
+
+/// Reports an error that stopped the run, explaining how to fix the Notion integration when
+/// that's what went wrong
+fn report_error(e: &DrossError) {
+    match e {
+        DrossError::InvalidToken => error!(
+            "{}. Check that NOTION_TOKEN is the \"Internal Integration Secret\" shown at https://www.notion.so/profile/integrations",
+            e
+        ),
+        DrossError::InsufficientPermissions { .. } => error!(
+            "{}. Share the Page (or one of its parents) with your Notion integration from its \"Connections\" menu",
+            e
+        ),
+        DrossError::EmptyWorkspace => error!(
+            "{}. Share your Notion integration with at least one Page from the Page's \"Connections\" menu",
+            e
+        ),
+        DrossError::BudgetExhausted { .. } => {
+            error!("{}. Raise --api-budget to let the run make more", e)
+        }
+        _ => error!("{}", e),
+    }
+}
+
+/// Uploads the generated markdown for each Page as a file in a GitHub gist
+async fn export_gist(
+    notion: &Notion,
+    options: &IngestOptions,
+    public: bool,
+    description: &str,
+    token: Option<&str>,
+    update_gist_id: Option<&str>,
+) -> Result<(), CommandError> {
+    let token = match token {
+        Some(token) => {
+            save_github_token(token);
+            token.to_string()
+        }
+        None => saved_github_token()
+            .or_else(|| env::var("GITHUB_TOKEN").ok())
+            .ok_or_else(|| {
+                CommandError::Other(
+                    "no GitHub token found, pass one with --token or set GITHUB_TOKEN".to_string(),
+                )
+            })?,
+    };
+
+    let prompt_context = ingest_notion(notion, options).await?;
+    if prompt_context.pages.is_empty() {
+        return Err(CommandError::Other(
+            "no recently edited Pages to upload".to_string(),
+        ));
+    }
+
+    let sink = GistSink {
+        token,
+        description: description.to_string(),
+        public,
+        update_gist_id: update_gist_id.map(str::to_string),
+    };
+    let url = sink.upload(&prompt_context).await?;
+    println!("{}", url);
+    Ok(())
+}
+
+/// Creates a new Page under `parent` from the markdown in `file`
+async fn push(
+    notion: &Notion,
+    file: &Path,
+    parent: &str,
+    title: Option<&str>,
+) -> Result<(), CommandError> {
+    let markdown = fs::read_to_string(file)
+        .map_err(|e| CommandError::Other(format!("failed to read {}: {}", file.display(), e)))?;
+    let title = title.map_or_else(
+        || {
+            file.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        },
+        str::to_string,
+    );
+
+    let blocks = markdown_to_blocks(&markdown);
+    let url = notion.create_page(parent, &title, blocks).await?;
+    println!("Created {}", url);
+    Ok(())
+}
+
+/// Prints statistics about the recently edited Pages, counting every `Block` on each Page
+/// but not those on the Pages nested inside it. The per-`Block` CSV export instead lists
+/// the recently edited `Block`s and their descendants, with their depth.
+async fn stats(
+    notion: &Notion,
+    dur: Duration,
+    until: Option<DateTime<Utc>>,
+    format: ReportFormat,
+    no_header: bool,
+) -> Result<(), CommandError> {
+    let pages = recently_edited_pages(notion, dur, until).await?;
+    info!(target: "notion", "retrieved {} Pages edited in the last {} days", pages.len(), dur.num_days());
+
+    let mut pages_and_trees = Vec::new();
+    for page in pages {
+        let trees = if let ReportFormat::Csv = format {
+            let block_roots = notion.get_page_block_roots(&page, dur).await?;
+            notion.grow_the_roots(block_roots).await
+        } else {
+            notion
+                .grow_the_roots_within_page(page.child_blocks.clone())
+                .await
+        }?;
+        pages_and_trees.push((page, trees));
+    }
+
+    match format {
+        ReportFormat::Table => print!("{}", stats_to_table(&collect_page_stats(&pages_and_trees))),
+        ReportFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&collect_page_stats(&pages_and_trees)).unwrap()
+        ),
+        ReportFormat::Csv => {
+            let rows = collect_block_stats_rows(&pages_and_trees);
+            print!("{}", block_stats_to_csv(&rows, !no_header).unwrap());
+        }
+    }
+    Ok(())
+}
+
+/// Prints how many runs in the last 30 days included each Page, from the access history
+fn access_history_stats(format: ReportFormat) {
+    let history = AccessHistory::load(&access_history_path());
+    let counts = history.most_accessed(Utc::now() - Duration::days(ACCESS_HISTORY_REPORT_DAYS));
+    if counts.is_empty() && matches!(format, ReportFormat::Table) {
+        println!("No runs recorded in the last {} days. Pass --track-access (or set ingest.track_access) to record them", ACCESS_HISTORY_REPORT_DAYS);
+        return;
+    }
+
+    match format {
+        ReportFormat::Table => print!("{}", access_counts_to_table(&counts)),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&counts).unwrap()),
+        ReportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            for count in &counts {
+                writer.serialize(count).unwrap();
+            }
+            writer.flush().unwrap();
+        }
+    }
+}
+
+/// Benchmarks the ingest pipeline on the `page_count` most recently edited Pages, and prints
+/// the report after adding it to the history of benchmark runs
+async fn benchmark(
+    notion: &Notion,
+    dur: Duration,
+    page_count: usize,
+    format: ReportFormat,
+) -> Result<(), CommandError> {
+    let report = run_benchmark(notion, dur, page_count).await?;
+    if let Err(e) = save_benchmark(&report, &benchmark_path()) {
+        warn!(
+            "failed to save the benchmark to {}: {}",
+            benchmark_path().display(),
+            e
+        );
+    }
+
+    match format {
+        ReportFormat::Table => {
+            println!(
+                "Ingested {} Pages with {} Notion API calls\n",
+                report.page_count, report.api_calls
+            );
+            print!("{}", benchmark_to_table(&report));
+        }
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+        ReportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            writer.serialize(&report).unwrap();
+            writer.flush().unwrap();
+        }
+    }
+    Ok(())
+}
+
+/// Prints the text statistics of each type of recently edited Block, and the Blocks nested under them
+async fn analyze(
+    notion: &Notion,
+    dur: Duration,
+    until: Option<DateTime<Utc>>,
+    format: ReportFormat,
+) -> Result<(), CommandError> {
+    let mut trees = Vec::new();
+    for page in recently_edited_pages(notion, dur, until).await? {
+        let block_roots = notion.get_page_block_roots(&page, dur).await?;
+        trees.extend(notion.grow_the_roots(block_roots).await?);
+    }
+
+    let stats = collect_block_type_stats(&trees);
+    match format {
+        ReportFormat::Table => print!("{}", block_type_stats_to_table(&stats)),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&stats).unwrap()),
+        ReportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            for row in stats {
+                writer.serialize(row).unwrap();
+            }
+            writer.flush().unwrap();
+        }
+    }
+    Ok(())
+}
+
+/// Draws the Block trees grown from the recently edited Blocks of the Page with ID `page_id`,
+/// with each Block's type colored by `block_type_color`
+async fn tree(notion: &Notion, dur: Duration, page_id: &str) -> Result<(), CommandError> {
+    let page = notion.get_page_by_id(&page_id.to_string()).await?;
+    let block_roots = notion.get_page_block_roots(&page, dur).await?;
+    let trees = notion.grow_the_roots(block_roots).await?;
+
+    let mut stdout = StandardStream::stdout(ColorChoice::Auto);
+    writeln!(stdout, "{}", page.title).unwrap();
+    for line in block_tree_lines(&trees) {
+        write!(stdout, "{}", line.prefix).unwrap();
+        stdout
+            .set_color(&block_type_color(&line.type_name))
+            .unwrap();
+        write!(stdout, "{}", line.type_name).unwrap();
+        stdout.reset().unwrap();
+        writeln!(stdout, " {} {}", line.block_id, line.text).unwrap();
+    }
+    Ok(())
+}
+
+/// Prints the orphan Blocks `Notion::find_orphan_blocks` finds on the Page with ID `page_id`
+async fn doctor(notion: &Notion, dur: Duration, page_id: &str) -> Result<(), CommandError> {
+    let page = notion.get_page_by_id(&page_id.to_string()).await?;
+    let orphans = notion.find_orphan_blocks(&page, Utc::now() - dur).await?;
+
+    if orphans.is_empty() {
+        println!("No orphan Blocks found on {}", page.title);
+        return Ok(());
+    }
+    println!("{} orphan Blocks found on {}:", orphans.len(), page.title);
+    for block in orphans {
+        println!(
+            "{} {} (parent {}): {}",
+            block.type_name(),
+            block.id,
+            block.parent_block_id.unwrap_or_default(),
+            truncate_for_log(&block.text, log_text_max())
+        );
+    }
+    Ok(())
+}
+
+/// Colors the type names drawn by `dross tree`, so Blocks of the same kind stand out together
+fn block_type_color(type_name: &str) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    match type_name {
+        "heading_1" | "heading_2" | "heading_3" => spec.set_fg(Some(Color::Blue)).set_bold(true),
+        "bulleted_list_item" | "numbered_list_item" | "to_do" | "toggle" => {
+            spec.set_fg(Some(Color::Green))
+        }
+        "code" | "equation" => spec.set_fg(Some(Color::Magenta)),
+        "child_page" | "child_database" | "link_to_page" => spec.set_fg(Some(Color::Cyan)),
+        "divider" | "table_of_contents" | "breadcrumb" | "column_list" | "column" => {
+            spec.set_dimmed(true)
+        }
+        _ => spec.set_fg(Some(Color::Yellow)),
+    };
+    spec
+}
+
+/// Prints the URLs linked to from every recently edited Block, and from the Blocks nested under them
+async fn urls(
+    notion: &Notion,
+    dur: Duration,
+    until: Option<DateTime<Utc>>,
+    format: ReportFormat,
+) -> Result<(), CommandError> {
+    let mut url_extractor = UrlExtractor::default();
+    let mut page_titles = HashMap::new();
+    let mut page_urls = HashMap::new();
+    for page in recently_edited_pages(notion, dur, until).await? {
+        let block_roots = notion.get_page_block_roots(&page, dur).await?;
+        for tree in notion.grow_the_roots(block_roots).await? {
+            url_extractor.extract(&tree);
+        }
+        page_titles.insert(page.id.clone(), page.title);
+        page_urls.insert(page.id, page.url);
+    }
+
+    let urls = url_extractor.into_urls();
+    let page_title = |block: &Block| block.page_title(&page_titles).cloned().unwrap_or_default();
+    let page_url = |block: &Block| block.page_url(&page_urls).cloned().unwrap_or_default();
+    match format {
+        ReportFormat::Table => {
+            for (block, url) in urls {
+                println!("{}  {}", block.id, url);
+            }
+        }
+        ReportFormat::Json => {
+            let urls = urls
+                .into_iter()
+                .map(|(block, url)| {
+                    serde_json::json!({
+                        "block_id": block.id,
+                        "page_title": page_title(&block),
+                        "page_url": page_url(&block),
+                        "url": url,
+                    })
+                })
+                .collect::<Vec<_>>();
+            println!("{}", serde_json::to_string_pretty(&urls).unwrap());
+        }
+        ReportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            writer
+                .write_record(["block_id", "page_title", "page_url", "url"])
+                .unwrap();
+            for (block, url) in urls {
+                writer
+                    .write_record([block.id.clone(), page_title(&block), page_url(&block), url])
+                    .unwrap();
+            }
+            writer.flush().unwrap();
+        }
+    }
+    Ok(())
+}
+
+/// Prints whether the Notion API is reachable and the token is valid, and fails if either
+/// isn't
+async fn health(notion: &Notion, timeout_secs: u64) -> Result<(), CommandError> {
+    let timeout = std::time::Duration::from_secs(timeout_secs);
+    let status = match tokio::time::timeout(timeout, notion.health_check()).await {
+        Ok(status) => status?,
+        Err(_) => {
+            return Err(CommandError::Other(format!(
+                "the Notion API didn't respond within {} seconds",
+                timeout_secs
+            )))
+        }
+    };
+
+    println!("API reachable: {}", status.api_reachable);
+    println!("Token valid: {}", status.token_valid);
+    if status.token_valid && !status.users_readable {
+        warn!("the integration can't read the workspace's users, give it the \"Read user information\" capability to use --created-by-name");
+    }
+    println!("Latency: {} ms", status.latency_ms);
+    if !(status.api_reachable && status.token_valid) {
+        return Err(CommandError::Other(
+            "the Notion API isn't reachable with this token".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Prints every user in the workspace
+async fn users(notion: &Notion, format: ReportFormat) -> Result<(), CommandError> {
+    let users = notion.list_all_users().await?;
+    match format {
+        ReportFormat::Table => {
+            for user in users {
+                println!(
+                    "{}  {}  {}",
+                    user.id,
+                    user.name,
+                    user.email.unwrap_or_default()
+                );
+            }
+        }
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&users).unwrap()),
+        ReportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            for user in users {
+                writer.serialize(user).unwrap();
+            }
+            writer.flush().unwrap();
+        }
+    }
+    Ok(())
+}
+
+/// Resolves a display name to the ID of the workspace user whose name best fuzzy matches
+/// it, failing if nobody matches
+async fn user_id_for_name(notion: &Notion, name: &str) -> Result<String, CommandError> {
+    let users = notion.list_all_users().await?;
+    match fuzzy_rank(users, name, |user| &user.name)
+        .into_iter()
+        .next()
+    {
+        Some((_, user)) => {
+            info!(
+                "--created-by-name {} matched user {} ({})",
+                name, user.name, user.id
+            );
+            Ok(user.id)
+        }
+        None => Err(CommandError::Other(format!(
+            "no user in the workspace has a name matching {}",
+            name
+        ))),
+    }
+}
+
+/// Prints the recently edited Pages as a numbered list. When `search` is given only the
+/// Pages whose titles fuzzy match it are printed, along with their match scores.
+async fn search_pages(
+    notion: &Notion,
+    dur: Duration,
+    until: Option<DateTime<Utc>>,
+    query: &str,
+    all: bool,
+    page_map_ttl: Duration,
+) -> Result<(), CommandError> {
+    if all {
+        let page_map =
+            PageMap::load_or_fetch(notion, &page_map_path(), page_map_ttl, false).await?;
+        let titles = page_map.titles.into_iter().collect::<Vec<_>>();
+        for (i, (score, (id, title))) in fuzzy_rank(titles, query, |(_, title)| title)
+            .into_iter()
+            .enumerate()
+        {
+            println!("{}. {} ({}) [score: {}]", i + 1, title, id, score);
+        }
+        return Ok(());
+    }
+
+    let pages = recently_edited_pages(notion, dur, until).await?;
+    for (i, (score, page)) in fuzzy_rank(pages, query, |page| &page.title)
+        .into_iter()
+        .enumerate()
+    {
+        println!(
+            "{}. {} ({}) [score: {}]",
+            i + 1,
+            page.title,
+            page.url,
+            score
+        );
+    }
+    Ok(())
+}
+
+/// Prints the recently edited Pages as a table sized to the terminal, or as tab-separated
+/// values when the output is piped into another program
+async fn list_pages(
+    notion: &Notion,
+    dur: Duration,
+    until: Option<DateTime<Utc>>,
+    sort: Option<PageListSort>,
+    desc: bool,
+) -> Result<(), CommandError> {
+    let pages = recently_edited_pages(notion, dur, until).await?;
+    let mut page_stats = pages.iter().map(PageStats::from_page).collect::<Vec<_>>();
+    if let Some(sort) = sort {
+        sort_page_stats(&mut page_stats, sort, desc);
+    }
+
+    if io::stdout().is_terminal() {
+        print!(
+            "{}",
+            page_list_to_table(&page_stats, Utc::now(), textwrap::termwidth())
+        );
+    } else {
+        print!("{}", page_list_to_tsv(&page_stats));
+    }
+    Ok(())
+}
+
+/// The settings that were passed as command line flags
+fn cli_config(cli: &Cli) -> Config {
+    let mut config = Config::default();
+    config.ingest.days = cli.days;
+    config.ingest.page_map_ttl_hours = cli.page_map_ttl_hours;
+    config.ingest.search_cache_ttl_minutes = cli.search_cache_ttl_minutes;
+    config.ingest.sort_blocks = cli.sort_blocks;
+    config.ingest.consecutive_old_blocks_abort_threshold =
+        cli.consecutive_old_blocks_abort_threshold;
+    config.ingest.traversal_strategy = cli.traversal_strategy;
+    config.ingest.max_blocks_per_page = cli.max_blocks_per_page;
+    config.ingest.track_access = cli.track_access.then_some(true);
+    config.output.encoding = cli.output_encoding;
+    config.output.webhook_url = cli.webhook_url.clone();
+    config.output.webhook_secret = cli.webhook_secret.clone();
+    config
+}
+
+/// Runs a `dross config` subcommand. `get` and `set` only look at the config file, since
+/// that's what `set` changes, while `show` prints the effective settings.
+///
+/// # Returns
+/// Whether the subcommand succeeded
+fn manage_config(
+    action: &ConfigAction,
+    mut file_config: Config,
+    effective_config: &Config,
+    sources: &[(&'static str, ConfigSource)],
+) -> bool {
+    let result = match action {
+        ConfigAction::Show => {
+            println!("# {}", config_path().display());
+            println!("{}", effective_config.to_annotated_string(sources));
+            Ok(())
+        }
+        ConfigAction::Get { key } => file_config.get(key).map(|value| {
+            if let Some(value) = value {
+                println!("{}", value);
+            }
+        }),
+        ConfigAction::Set { key, value } => file_config
+            .set(key, value)
+            .and_then(|()| file_config.save(&config_path())),
+    };
+
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{}", e);
+            false
+        }
+    }
+}
+
+/// Deletes the selected local state files, printing each one that was deleted along with its
+/// size. The config file is only deleted if `include_config` is set and the user confirms it.
+///
+/// # Returns
+/// Whether every deletion succeeded
+fn clean(cache: bool, state: bool, page_map: bool, include_config: bool, dry_run: bool) -> bool {
+    let mut paths = Vec::new();
+    if cache {
+        paths.push(search_cache_path());
+    }
+    if state {
+        paths.push(run_state_path());
+    }
+    if page_map {
+        paths.push(page_map_path());
+    }
+    if include_config && config_path().exists() {
+        print!(
+            "Also delete the config file at {}? [y/N] ",
+            config_path().display()
+        );
+        io::stdout().flush().ok();
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).ok();
+        if answer.trim().eq_ignore_ascii_case("y") {
+            paths.push(config_path());
+        }
+    }
+
+    let verb = if dry_run { "Would delete" } else { "Deleted" };
+    let mut succeeded = true;
+    for path in paths {
+        match remove_state_file(&path, dry_run) {
+            Ok(Some(size)) => println!("{} {} ({} bytes)", verb, path.display(), size),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Failed to delete {}: {}", path.display(), e);
+                succeeded = false;
+            }
+        }
+    }
+
+    succeeded
 }
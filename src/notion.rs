@@ -1,56 +1,677 @@
-use crate::core::datatypes::{Block, Page};
-use chrono::{Duration, Utc};
-use dendron::{Node, Tree};
-use log::{debug, error, trace};
+pub mod api;
+pub mod fake;
+
+use crate::core::{
+    datatypes::{
+        Block, BlockID, BlockTree, DatabaseSchema, Page, PageID, PropertyValue, WorkspaceUser,
+    },
+    error::DrossError,
+    graph::WorkspaceGraph,
+    helpers::{find_orphans, log_text_max, truncate_for_log, TraversalStrategy},
+};
+use async_stream::try_stream;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use dendron::Node;
+use futures::{pin_mut, Stream, StreamExt};
+use log::{debug, error, info, trace, warn};
 use notion_client::{
     endpoints::{
-        blocks::retrieve::response::RetrieveBlockChilerenResponse,
+        blocks::append::request::AppendBlockChildrenRequest,
+        pages::create::request::CreateAPageRequest,
         search::title::{
             request::{Filter, SearchByTitleRequestBuilder, Sort, SortDirection, Timestamp},
             response::PageOrDatabase,
         },
         Client,
     },
-    objects::page::Page as NotionPage,
+    objects::{
+        block::{Block as NotionBlock, BlockType},
+        page::{Page as NotionPage, PageProperty},
+        parent::Parent,
+        rich_text::{RichText, Text},
+    },
     NotionClientError,
 };
-use std::collections::{HashSet, VecDeque};
+use reqwest::header::HeaderValue;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration as StdDuration,
+};
+use tokio::{
+    sync::{mpsc, oneshot, OnceCell},
+    time::Instant,
+};
+
+use api::{NotionApi, RateLimitFeedback, RecordingClient, RequestLogger, VersionedClient};
+
+/// Notion's API allows an average of 3 requests per second per integration
+/// https://developers.notion.com/reference/request-limits
+const NOTION_REQUESTS_PER_SECOND: u32 = 3;
+
+/// The most child Blocks the API accepts in a single request that creates or appends them
+/// https://developers.notion.com/reference/request-limits#limits-for-property-values
+const MAX_CHILDREN_PER_CREATE: usize = 100;
+
+/// How many old `Block`s in a row the block root search sees before it gives up on a Page
+pub const DEFAULT_CONSECUTIVE_OLD_BLOCKS_ABORT_THRESHOLD: usize = 10;
+
+/// The Notion API version requests are made against unless `NotionBuilder::api_version` says
+/// otherwise, which is the version `notion_client` was written for
+/// https://developers.notion.com/reference/versioning
+pub const DEFAULT_NOTION_API_VERSION: &str = "2022-06-28";
+
+/// Notion API versions are release dates, e.g. `2022-06-28`
+#[must_use]
+pub fn is_api_version(version: &str) -> bool {
+    version.len() == "YYYY-MM-DD".len() && NaiveDate::parse_from_str(version, "%Y-%m-%d").is_ok()
+}
 
+/// The first API version whose Block objects say which Page or Block they're nested under
+const PARENT_FIELDS_API_VERSION: &str = "2022-06-28";
+/// The first API version the comments endpoints accept
+const COMMENTS_API_VERSION: &str = "2022-06-28";
+/// The first API version that returns `mention` rich text for users
+const USER_MENTIONS_API_VERSION: &str = "2021-05-13";
+
+/// Which parts of the Notion API can be used with a given API version, so that code using a
+/// newer feature can fall back rather than fail against an older version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FeatureSet {
+    pub supports_comments: bool,
+    /// Pages and Blocks report their parent, so they can be grouped by it
+    pub supports_parent_filtering: bool,
+    pub supports_user_mentions: bool,
+}
+
+impl FeatureSet {
+    /// API versions are `YYYY-MM-DD` dates, so comparing them as strings orders them by release
+    #[must_use]
+    pub fn from_api_version(api_version: &str) -> Self {
+        FeatureSet {
+            supports_comments: api_version >= COMMENTS_API_VERSION,
+            supports_parent_filtering: api_version >= PARENT_FIELDS_API_VERSION,
+            supports_user_mentions: api_version >= USER_MENTIONS_API_VERSION,
+        }
+    }
+}
+
+/// Token buckets count tokens in thousandths so that partially refilled tokens aren't lost
+const MILLITOKENS_PER_TOKEN: u64 = 1000;
+
+/// Two rate limited (`429`) responses at least this close together mean requests are being
+/// made faster than the workspace allows, so the refill rate is halved
+const RATE_LIMITED_CLUSTER_WINDOW: StdDuration = StdDuration::from_secs(1);
+/// How long the refill rate has to go unchanged without a rate limited response before it's
+/// raised back toward the maximum
+const RATE_LIMIT_RECOVERY_PERIOD: StdDuration = StdDuration::from_secs(30);
+/// How much of the maximum refill rate is added back after each `RATE_LIMIT_RECOVERY_PERIOD`
+const RATE_LIMIT_RECOVERY_STEP: f64 = 0.25;
+/// However many rate limited responses there are, the refill rate isn't halved below this
+/// fraction of the maximum
+const MIN_REFILL_RATE_FRACTION: f64 = 1.0 / 16.0;
+
+/// How many pages of search results can be waiting to be turned into `Page`s before the search
+/// waits for them to catch up
+const SEARCH_BATCHES_BUFFERED: usize = 4;
+
+/// How many more times a page of Block children is requested when neither notion-client nor
+/// our own parsing can read the response, which is usually a truncated or garbled body
+const UNREADABLE_RESPONSE_RETRIES: u32 = 3;
+/// How long to wait before requesting an unreadable page of Block children again
+const UNREADABLE_RESPONSE_RETRY_DELAY: StdDuration = StdDuration::from_secs(1);
+/// The most random jitter added to `UNREADABLE_RESPONSE_RETRY_DELAY`, in milliseconds
+const UNREADABLE_RESPONSE_RETRY_JITTER_MS: u32 = 250;
+
+/// A token bucket rate limiter. Each API request takes a token, and tokens are refilled
+/// continuously at `refill_rate` per second up to `capacity`, so requests can burst up
+/// to `capacity` and are then spaced out to match the refill rate.
+///
+/// The refill rate adapts to the workspace's actual limit: it's halved whenever rate limited
+/// responses cluster together (see `record_rate_limited`), and creeps back up to the rate the
+/// bucket was created with once they stop.
+pub struct TokenBucket {
+    capacity: u32,
+    /// The most tokens added per second, which is the rate the bucket was created with
+    max_refill_rate: f64,
+    /// Tokens currently available, in millitokens
+    available: AtomicU64,
+    last_refill: Mutex<Instant>,
+    throttle: Mutex<Throttle>,
+}
+
+/// How far `TokenBucket` has slowed its refill rate in response to rate limited responses
+struct Throttle {
+    /// Tokens added per second
+    refill_rate: f64,
+    last_rate_limited: Option<Instant>,
+    /// When `refill_rate` was last lowered or raised, or a rate limited response was seen
+    last_changed: Instant,
+}
+
+impl TokenBucket {
+    #[must_use]
+    pub fn new(capacity: u32, refill_rate: f64) -> Self {
+        TokenBucket {
+            capacity,
+            max_refill_rate: refill_rate,
+            available: AtomicU64::new(u64::from(capacity) * MILLITOKENS_PER_TOKEN),
+            last_refill: Mutex::new(Instant::now()),
+            throttle: Mutex::new(Throttle {
+                refill_rate,
+                last_rate_limited: None,
+                last_changed: Instant::now(),
+            }),
+        }
+    }
+
+    /// The tokens currently added per second, first raising the rate a step toward the
+    /// maximum if it was lowered and there haven't been rate limited responses in a while
+    pub fn refill_rate(&self) -> f64 {
+        let mut throttle = self.throttle.lock().unwrap();
+        if throttle.refill_rate < self.max_refill_rate
+            && throttle.last_changed.elapsed() >= RATE_LIMIT_RECOVERY_PERIOD
+        {
+            let raised = (throttle.refill_rate + self.max_refill_rate * RATE_LIMIT_RECOVERY_STEP)
+                .min(self.max_refill_rate);
+            debug!(target: "notion", "no rate limited responses for {:?}, raising the request rate from {:.2}/s to {:.2}/s", RATE_LIMIT_RECOVERY_PERIOD, throttle.refill_rate, raised);
+            throttle.refill_rate = raised;
+            throttle.last_changed = Instant::now();
+        }
+        throttle.refill_rate
+    }
+
+    /// Records that the API answered a request with a `429`. If the previous one was within
+    /// `RATE_LIMITED_CLUSTER_WINDOW`, the refill rate is halved.
+    pub fn record_rate_limited(&self) {
+        let mut throttle = self.throttle.lock().unwrap();
+        let now = Instant::now();
+        let clustered = throttle
+            .last_rate_limited
+            .is_some_and(|last| now.duration_since(last) <= RATE_LIMITED_CLUSTER_WINDOW);
+        if clustered {
+            let lowered =
+                (throttle.refill_rate / 2.0).max(self.max_refill_rate * MIN_REFILL_RATE_FRACTION);
+            if lowered < throttle.refill_rate {
+                debug!(target: "notion", "rate limited twice within {:?}, lowering the request rate from {:.2}/s to {:.2}/s", RATE_LIMITED_CLUSTER_WINDOW, throttle.refill_rate, lowered);
+                throttle.refill_rate = lowered;
+            }
+        }
+        throttle.last_rate_limited = Some(now);
+        throttle.last_changed = now;
+    }
+
+    /// Takes a token, sleeping until one is available if the bucket is empty
+    pub async fn acquire(&self) {
+        loop {
+            let refill_rate = self.refill_rate();
+            let wait = {
+                // the lock makes the refill and the take atomic with respect to each other
+                let mut last_refill = self.last_refill.lock().unwrap();
+                let now = Instant::now();
+                let elapsed = now.duration_since(*last_refill).as_secs_f64();
+                let refilled = (elapsed * refill_rate * MILLITOKENS_PER_TOKEN as f64) as u64;
+                if refilled > 0 {
+                    let capacity = u64::from(self.capacity) * MILLITOKENS_PER_TOKEN;
+                    let available = self.available.load(Ordering::Relaxed);
+                    self.available
+                        .store((available + refilled).min(capacity), Ordering::Relaxed);
+                    *last_refill = now;
+                }
+
+                let available = self.available.load(Ordering::Relaxed);
+                if available >= MILLITOKENS_PER_TOKEN {
+                    self.available
+                        .fetch_sub(MILLITOKENS_PER_TOKEN, Ordering::Relaxed);
+                    None
+                } else {
+                    let missing = (MILLITOKENS_PER_TOKEN - available) as f64;
+                    Some(StdDuration::from_secs_f64(
+                        missing / MILLITOKENS_PER_TOKEN as f64 / refill_rate,
+                    ))
+                }
+            };
+
+            match wait {
+                None => return,
+                Some(wait) => {
+                    trace!(target: "notion", "rate limited, waiting {:?} for a token", wait);
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+    }
+}
+
+/// A rate limiter that keeps to Notion's request limits
+#[must_use]
+pub fn default_rate_limiter() -> TokenBucket {
+    TokenBucket::new(
+        NOTION_REQUESTS_PER_SECOND,
+        f64::from(NOTION_REQUESTS_PER_SECOND),
+    )
+}
+
+/// A connection to the Notion API.
+///
+/// `Notion` is `Send + Sync`, and cloning it is cheap: clones share the underlying API client
+/// (and so its HTTP connection pool), the rate limiter, and the cached workspace users. To use
+/// it from several tasks at once, clone it into each `tokio::spawn`ed future. `NotionApi`
+/// requires `Send + Sync`, so `notion_client::Client` implementing it is what guarantees the
+/// real client is safe to share. Nothing in it needs to be behind a lock.
+#[derive(Clone)]
 pub struct Notion {
-    client: Client,
+    api: Arc<dyn NotionApi>,
+    /// Shared between clones, so that together they stay within Notion's request limits
+    rate_limiter: Arc<TokenBucket>,
+    /// The workspace's users rarely change, so they're only fetched once per run
+    users: Arc<OnceCell<Vec<WorkspaceUser>>>,
+    /// See `set_consecutive_old_blocks_abort_threshold`
+    consecutive_old_blocks_abort_threshold: usize,
+    /// See `set_traversal_strategy`
+    traversal_strategy: TraversalStrategy,
+    /// See `set_max_blocks_per_page`
+    max_blocks_per_page: Option<usize>,
+    /// How many API requests have been made, shared between clones like the rate limiter
+    api_calls: Arc<AtomicU32>,
+    /// See `set_api_call_budget`
+    api_call_budget: Option<u32>,
+    /// The version sent as the `Notion-Version` header of every request
+    api_version: String,
+    /// See `token_hash`
+    token_hash: String,
+    /// What `api_version` supports, worked out once when the `Notion` is built
+    feature_set: FeatureSet,
+    /// When `health_check` last got a response from the API
+    last_successful_health_check: Arc<Mutex<Option<DateTime<Utc>>>>,
+    /// A database's schema rarely changes, so each one is only fetched once per run
+    database_schemas: Arc<Mutex<HashMap<String, DatabaseSchema>>>,
+    /// Filtering by tags and outputting properties can both want a Page's properties, so
+    /// they're only fetched once per run
+    page_properties: Arc<Mutex<HashMap<PageID, HashMap<String, PropertyValue>>>>,
+    /// How many lookups `database_schemas` and `page_properties` have answered, and how many
+    /// they couldn't, shared between clones like `api_calls`
+    cache_hits: Arc<AtomicU32>,
+    cache_misses: Arc<AtomicU32>,
+}
+
+/// The result of `Notion::health_check`
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthStatus {
+    pub api_reachable: bool,
+    pub token_valid: bool,
+    /// Whether the integration may read the workspace's users. A valid token can still be
+    /// refused, if the integration wasn't given the "Read user information" capability.
+    pub users_readable: bool,
+    /// When the API last responded to a health check, which is now if this one succeeded
+    pub last_successful_request: Option<DateTime<Utc>>,
+    /// How long the health check's request took, including any wait for the rate limiter
+    pub latency_ms: u64,
+}
+
+/// The children of a Block being fetched by `Notion::prefetch_block_children`
+type PendingChildren = oneshot::Receiver<Result<Vec<Block>, DrossError>>;
+
+/// Configures a `Notion` that talks to the real API
+pub struct NotionBuilder {
+    token: String,
+    api_version: String,
+    fixtures_dir: Option<PathBuf>,
+    api_call_budget: Option<u32>,
+    http_log_path: Option<PathBuf>,
+}
+
+impl NotionBuilder {
+    #[must_use]
+    pub fn new(token: String) -> Self {
+        NotionBuilder {
+            token,
+            api_version: DEFAULT_NOTION_API_VERSION.to_string(),
+            fixtures_dir: None,
+            api_call_budget: None,
+            http_log_path: None,
+        }
+    }
+
+    /// Pins requests to a Notion API version other than `DEFAULT_NOTION_API_VERSION`, e.g. to
+    /// try out block types that only newer versions return. It's sent as the `Notion-Version`
+    /// header of every request, and must be a date like `2022-06-28`.
+    #[must_use]
+    pub fn api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.api_version = api_version.into();
+        self
+    }
+
+    /// Also writes every API response to `fixtures_dir` so it can be replayed in tests
+    #[must_use]
+    pub fn record_fixtures(mut self, fixtures_dir: PathBuf) -> Self {
+        self.fixtures_dir = Some(fixtures_dir);
+        self
+    }
+
+    /// See `Notion::set_api_call_budget`
+    #[must_use]
+    pub fn api_call_budget(mut self, budget: u32) -> Self {
+        self.api_call_budget = Some(budget);
+        self
+    }
+
+    /// Logs every API request and response at `TRACE` level, and appends them to the
+    /// newline-delimited JSON file at `http_log_path` (see `RequestLogger`)
+    #[must_use]
+    pub fn log_http(mut self, http_log_path: PathBuf) -> Self {
+        self.http_log_path = Some(http_log_path);
+        self
+    }
+
+    pub fn build(self) -> Result<Notion, DrossError> {
+        info!(target: "notion", "using Notion API version {}", self.api_version);
+        let token_hash = hex::encode(Sha256::digest(self.token.as_bytes()));
+        let api: Box<dyn NotionApi> = if self.api_version == DEFAULT_NOTION_API_VERSION {
+            Box::new(Client::new(self.token, None)?)
+        } else {
+            let invalid_version = || DrossError::InvalidApiVersion {
+                version: self.api_version.clone(),
+            };
+            if !is_api_version(&self.api_version) {
+                return Err(invalid_version());
+            }
+            let api_version =
+                HeaderValue::from_str(&self.api_version).map_err(|_| invalid_version())?;
+            Box::new(VersionedClient::new(&self.token, api_version)?)
+        };
+
+        let api: Box<dyn NotionApi> = match self.fixtures_dir {
+            Some(fixtures_dir) => Box::new(RecordingClient::new(api, fixtures_dir)),
+            None => api,
+        };
+        let api: Box<dyn NotionApi> = match &self.http_log_path {
+            Some(http_log_path) => Box::new(RequestLogger::new(api, http_log_path)),
+            None => api,
+        };
+        let mut notion = Notion::with_api(api, default_rate_limiter());
+        notion.set_api_call_budget(self.api_call_budget);
+        notion.feature_set = FeatureSet::from_api_version(&self.api_version);
+        notion.api_version = self.api_version;
+        notion.token_hash = token_hash;
+        Ok(notion)
+    }
 }
 
 impl Notion {
-    pub fn new(token: String) -> Result<Self, NotionClientError> {
-        let client = Client::new(token, None);
-        match client {
-            Ok(c) => Ok(Notion { client: c }),
-            Err(e) => Err(e),
+    pub fn new(token: String) -> Result<Self, DrossError> {
+        NotionBuilder::new(token).build()
+    }
+
+    /// Like `new`, but every API response is also written to `fixtures_dir` so it can be
+    /// replayed in tests
+    pub fn recording(token: String, fixtures_dir: PathBuf) -> Result<Self, DrossError> {
+        NotionBuilder::new(token)
+            .record_fixtures(fixtures_dir)
+            .build()
+    }
+
+    /// Talks to Notion through `api` rather than the real API client, e.g. to replay fixtures
+    pub fn with_api(api: impl NotionApi + 'static, rate_limiter: TokenBucket) -> Self {
+        let rate_limiter = Arc::new(rate_limiter);
+        Notion {
+            api: Arc::new(RateLimitFeedback::new(api, Arc::clone(&rate_limiter))),
+            rate_limiter,
+            users: Arc::new(OnceCell::new()),
+            consecutive_old_blocks_abort_threshold: DEFAULT_CONSECUTIVE_OLD_BLOCKS_ABORT_THRESHOLD,
+            traversal_strategy: TraversalStrategy::default(),
+            max_blocks_per_page: None,
+            api_calls: Arc::new(AtomicU32::new(0)),
+            api_call_budget: None,
+            api_version: DEFAULT_NOTION_API_VERSION.to_string(),
+            token_hash: String::new(),
+            feature_set: FeatureSet::from_api_version(DEFAULT_NOTION_API_VERSION),
+            last_successful_health_check: Arc::new(Mutex::new(None)),
+            database_schemas: Arc::new(Mutex::new(HashMap::new())),
+            page_properties: Arc::new(Mutex::new(HashMap::new())),
+            cache_hits: Arc::new(AtomicU32::new(0)),
+            cache_misses: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// The Notion API version requests are made against
+    #[must_use]
+    pub fn api_version(&self) -> &str {
+        &self.api_version
+    }
+
+    /// A SHA-256 hex digest of the integration token, which tells apart the workspaces whose
+    /// results are cached on disk without storing the token. Empty for a `Notion` made with
+    /// `with_api`.
+    #[must_use]
+    pub fn token_hash(&self) -> &str {
+        &self.token_hash
+    }
+
+    /// Which API features `api_version` supports
+    #[must_use]
+    pub fn feature_set(&self) -> FeatureSet {
+        self.feature_set
+    }
+
+    /// Caps how many API requests this `Notion` (and its clones) will make. Once `budget`
+    /// requests have been made, every further one fails with `DrossError::BudgetExhausted`
+    /// instead of being sent. `None` removes the cap.
+    pub fn set_api_call_budget(&mut self, budget: Option<u32>) {
+        self.api_call_budget = budget;
+    }
+
+    /// How many API requests have been made so far, whether or not there's a budget
+    #[must_use]
+    pub fn api_calls_made(&self) -> u32 {
+        self.api_calls.load(Ordering::Relaxed)
+    }
+
+    /// How many times a Page's properties or a database's schema were already cached, so no
+    /// request was needed
+    #[must_use]
+    pub fn cache_hits(&self) -> u32 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+
+    /// How many times a Page's properties or a database's schema had to be requested
+    #[must_use]
+    pub fn cache_misses(&self) -> u32 {
+        self.cache_misses.load(Ordering::Relaxed)
+    }
+
+    fn record_cache_lookup<T>(&self, cached: Option<T>) -> Option<T> {
+        let counter = if cached.is_some() {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    /// Counts a request against the budget and then waits for the rate limiter. Every API
+    /// request goes through here first.
+    async fn start_api_call(&self) -> Result<(), DrossError> {
+        let counted = self
+            .api_calls
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |calls| {
+                match self.api_call_budget {
+                    Some(budget) if calls >= budget => None,
+                    _ => Some(calls.saturating_add(1)),
+                }
+            });
+        if let Err(calls_made) = counted {
+            return Err(DrossError::BudgetExhausted { calls_made });
+        }
+
+        self.rate_limiter.acquire().await;
+        Ok(())
+    }
+
+    /// Checks that the API is reachable and the token is valid by retrieving the token's own
+    /// bot user from `users/me`, then whether the integration may read the workspace's users by
+    /// listing a single one. The statuses are read before they're turned into `DrossError`s,
+    /// which only tell a `403` apart for requests about a Page.
+    pub async fn health_check(&self) -> Result<HealthStatus, DrossError> {
+        let start = Instant::now();
+        self.start_api_call().await?;
+        let res = self.api.retrieve_your_token_s_bot_user().await;
+        let latency_ms = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+
+        let (api_reachable, token_valid) = match res {
+            Ok(_) => (true, true),
+            Err(NotionClientError::InvalidStatusCode { error }) if error.status == 401 => {
+                (true, false)
+            }
+            Err(e) => {
+                debug!(target: "notion", "health check failed: {}", e);
+                (false, false)
+            }
+        };
+        let users_readable = if token_valid {
+            self.start_api_call().await?;
+            match self.api.list_all_users(None, Some(1)).await {
+                Ok(_) => true,
+                Err(NotionClientError::InvalidStatusCode { error }) if error.status == 403 => false,
+                Err(e) => {
+                    debug!(target: "notion", "health check couldn't list users: {}", e);
+                    false
+                }
+            }
+        } else {
+            false
+        };
+        let mut last_success = self.last_successful_health_check.lock().unwrap();
+        if api_reachable {
+            *last_success = Some(Utc::now());
         }
+
+        Ok(HealthStatus {
+            api_reachable,
+            token_valid,
+            users_readable,
+            last_successful_request: *last_success,
+            latency_ms,
+        })
+    }
+
+    /// Notion returns Blocks in the order they appear on the Page, which is usually the order
+    /// they were written in, so once the block root search has seen `threshold` Blocks in a row
+    /// that pre-date the cutoff among one parent's children it assumes the rest of them are old
+    /// too and skips them. The old Blocks it saw before that are still searched below.
+    pub fn set_consecutive_old_blocks_abort_threshold(&mut self, threshold: usize) {
+        self.consecutive_old_blocks_abort_threshold = threshold;
+    }
+
+    /// Caps how many old Blocks the block root search queues up to search below on a single
+    /// Page. Once `max_blocks` have been queued, the children of any further old Blocks are
+    /// skipped, which bounds the memory and API calls a huge Page can take more predictably
+    /// than the search's time limit does. `None` removes the cap.
+    pub fn set_max_blocks_per_page(&mut self, max_blocks: Option<usize>) {
+        self.max_blocks_per_page = max_blocks;
+    }
+
+    /// Whether the block root search goes depth first (the default) or breadth first below
+    /// old Blocks. Either way it finds the same Blocks, only the order they're found in and how
+    /// many Block IDs are waiting to be searched at once differ.
+    pub fn set_traversal_strategy(&mut self, strategy: TraversalStrategy) {
+        self.traversal_strategy = strategy;
+    }
+
+    pub async fn get_last_edited_pages(&self, dur: Duration) -> Result<Vec<Page>, DrossError> {
+        self.search_last_edited_pages(Utc::now() - dur, None, None)
+            .await
     }
 
-    pub async fn get_last_edited_pages(
+    /// Like `get_last_edited_pages`, but stops paginating as soon as the `n` most recently
+    /// edited Pages have been found
+    pub async fn get_last_n_edited_pages(
         &self,
         dur: Duration,
-    ) -> Result<Vec<Page>, NotionClientError> {
+        n: usize,
+    ) -> Result<Vec<Page>, DrossError> {
+        self.search_last_edited_pages(Utc::now() - dur, None, Some(n))
+            .await
+    }
+
+    /// Like `get_last_edited_pages`, but only returns the Pages last edited between `start` and
+    /// `end` (inclusive), for looking back at a particular day or week. The search API can only
+    /// find Pages edited after a cutoff, so Pages edited after `end` are searched through and
+    /// dropped before their Blocks are fetched.
+    pub async fn search_by_date_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<Page>, DrossError> {
+        self.search_last_edited_pages(start, Some(end), None).await
+    }
+
+    /// Searches for the Pages last edited after `cutoff`, and before `until` if it's given, up
+    /// to `limit` of them. `limit` counts the Pages edited after `until` too.
+    async fn search_last_edited_pages(
+        &self,
+        cutoff: DateTime<Utc>,
+        until: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Page>, DrossError> {
+        // turning a search result into a `Page` fetches its top-level Blocks, so the search is
+        // paginated on its own task and each batch of results is converted while the next one
+        // is being fetched
+        let (sender, mut receiver) = mpsc::channel(SEARCH_BATCHES_BUFFERED);
+        let notion = self.clone();
+        let search = tokio::spawn(async move {
+            if let Err(e) = notion
+                .paginate_last_edited_pages(cutoff, limit, &sender)
+                .await
+            {
+                // the receiver is gone if converting the Pages already failed, so there's no one to tell
+                let _ = sender.send(Err(e)).await;
+            }
+        });
+
         let mut pages: Vec<Page> = Vec::new();
-        let cutoff = Utc::now() - dur;
+        while let Some(batch) = receiver.recv().await {
+            for notion_page in batch? {
+                if until.is_some_and(|until| notion_page.last_edited_time > until) {
+                    continue;
+                }
+                pages.push(self.notion_page_to_dross_page(notion_page).await?);
+            }
+        }
+        if let Err(e) = search.await {
+            if e.is_panic() {
+                std::panic::resume_unwind(e.into_panic());
+            }
+        }
+
+        Ok(pages)
+    }
+
+    /// Pages through the search for Pages edited after `cutoff`, most recent first, sending
+    /// each page of results to `sender` until the cutoff or `limit` is reached. Stops early if
+    /// the receiver hangs up.
+    async fn paginate_last_edited_pages(
+        &self,
+        cutoff: DateTime<Utc>,
+        limit: Option<usize>,
+        sender: &mpsc::Sender<Result<Vec<NotionPage>, DrossError>>,
+    ) -> Result<(), DrossError> {
+        let mut pages_found = 0;
         let mut current_cursor: Option<String> = None;
 
-        let mut req_builder = SearchByTitleRequestBuilder::default();
-        req_builder
-            .filter(Filter {
-                value: notion_client::endpoints::search::title::request::FilterValue::Page,
-                property: notion_client::endpoints::search::title::request::FilterProperty::Object,
-            })
-            .sort(Sort {
-                timestamp: Timestamp::LastEditedTime,
-                direction: SortDirection::Descending,
-            })
-            .page_size(100);
+        let mut req_builder = last_edited_pages_search();
+        req_builder.page_size(limit.map_or(100, |limit| limit.clamp(1, 100) as u32));
 
         loop {
             // paging
+            let first_request = current_cursor.is_none();
             if let Some(cursor) = current_cursor {
                 req_builder.start_cursor(cursor);
             }
@@ -58,14 +679,18 @@ impl Notion {
             // Send request
             // TODO might be able to use retrieve_page_property api here and get only last_edited, id, and title, which would
             // conserve bandwidth
+            self.start_api_call().await?;
             let res = self
-                .client
-                .search
+                .api
                 .search_by_title(req_builder.build().unwrap())
                 .await?;
 
             current_cursor = res.next_cursor;
             let res_len = res.results.len();
+            if first_request && res_len == 0 {
+                // search returns every Page the integration can see, regardless of `cutoff`
+                return Err(DrossError::EmptyWorkspace);
+            }
             let mut current_notion_pages = res
                 .results
                 .into_iter()
@@ -79,8 +704,8 @@ impl Notion {
                 panic!("something other than a page was found in returned info. res_len: {res_len} currentpages.len(): {}", current_notion_pages.len());
             }
 
-            // we only care about pages edited within `dur`, so we need to
-            // cut out the Pages that were edited after `dur`
+            // we only care about pages edited after `cutoff`, so we need to
+            // cut out the Pages that were edited before it
             let cutoff_index = current_notion_pages
                 .iter()
                 .position(|page| page.last_edited_time < cutoff);
@@ -88,36 +713,59 @@ impl Notion {
                 current_notion_pages = current_notion_pages.split_at(index).0.to_vec();
             }
 
-            for notion_page in current_notion_pages {
-                let page = self.notion_page_to_dross_page(notion_page).await?;
-                pages.push(page);
+            // and we don't want more than `limit` of them
+            let mut limit_reached = false;
+            if let Some(limit) = limit {
+                let remaining = limit - pages_found;
+                if current_notion_pages.len() >= remaining {
+                    current_notion_pages.truncate(remaining);
+                    limit_reached = true;
+                }
             }
 
-            if !res.has_more || cutoff_index.is_some() {
-                break;
+            pages_found += current_notion_pages.len();
+            if sender.send(Ok(current_notion_pages)).await.is_err() {
+                debug!(target: "notion", "stopped searching for Pages, their conversion failed");
+                return Ok(());
+            }
+
+            if !res.has_more || cutoff_index.is_some() || limit_reached {
+                return Ok(());
             }
         }
+    }
 
-        Ok(pages)
+    /// Retrieves the Pages created at or after `cutoff`.
+    ///
+    /// Notion's search API can only sort by last edited time, but a Page created after
+    /// `cutoff` must also have been edited after it, so we search for the Pages edited since
+    /// `cutoff` and filter out the ones that were created before it.
+    pub async fn get_pages_created_after(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<Vec<Page>, DrossError> {
+        let pages = self.get_last_edited_pages(Utc::now() - cutoff).await?;
+
+        Ok(pages
+            .into_iter()
+            .filter(|page| page.creation_date >= cutoff)
+            .collect())
     }
 
     /// For a given Notion `Page`, retrieve all of its non-empty children, grandchildren, etc... `Block`s that were edited within the specified duration.
     ///
-    /// Uses breadth-first-search to recursively fetch all the block descendants of the page.
+    /// This collects `stream_page_block_roots`, aborting after a fixed amount of time for huge Pages.
     ///
     /// # Returns
     /// A `Result` containing a `Vec` of all the `Page`'s descentant `Block`s that were updated between within `dur`. Note
-    /// that this includes the `Page` `Block` itself. Also note that the order of the `Block`s is not guaranteed and
-    /// cannot be relied upon.
+    /// that this includes the `Page` `Block` itself. The `Block`s are in the order they appear on the Page, top to
+    /// bottom, whichever order the search found them in.
     pub async fn get_page_block_roots(
         &self,
         page: &Page,
         dur: Duration,
-    ) -> Result<Vec<Block>, NotionClientError> {
-        let cutoff = Utc::now() - dur;
-        let mut block_ids_to_process = VecDeque::new();
-        let mut block_roots: Vec<Block> = Vec::new();
-        let mut already_visited: HashSet<String> = HashSet::new();
+    ) -> Result<Vec<Block>, DrossError> {
+        let mut positioned_block_roots = Vec::new();
 
         // some user's Pages are huuuge, so long that we don't know if we'll spend too much time
         // much time fetching all their children. So, as a heuristic for when to abort we use
@@ -126,56 +774,139 @@ impl Notion {
         let time_to_spend_fetching_children = Duration::seconds(30);
         let abort_time = Utc::now() + time_to_spend_fetching_children;
 
-        block_ids_to_process.push_back(page.id.clone());
+        let block_roots_stream = self.stream_positioned_block_roots(page, dur, abort_time);
+        pin_mut!(block_roots_stream);
+        while let Some(positioned_block) = block_roots_stream.next().await {
+            positioned_block_roots.push(positioned_block?);
+        }
+        positioned_block_roots.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let block_roots = positioned_block_roots
+            .into_iter()
+            .map(|(_, block)| block)
+            .collect::<Vec<Block>>();
 
-        while let Some(block_id) = block_ids_to_process.pop_front() {
-            if already_visited.contains(&block_id) {
-                trace!(
-                    target: "notion",
-                    "already visited this block {}, skipping it...",
-                    &block_id
-                );
-                // we've already processed this block, so skip it
-                continue;
-            }
-            trace!(
-                target: "notion",
-                "getting block root with id {}",
-                &block_id
-            );
-            let children = self
-                .retrieve_all_block_children(&page.id, &block_id)
-                .await?;
+        debug!(target: "notion", "fetched {} descendant Blocks from Page {}", block_roots.len(), page.url);
+        for block in &block_roots {
+            debug!(target: "notion", "  {} ({}): {}", block.id, block.type_name(), truncate_for_log(&block.text, log_text_max()));
+        }
 
-            for block in children {
-                if block.update_date >= cutoff {
-                    // is the Block's edit time within the duration?
-                    if !block.is_empty() {
-                        // note, there may be further descendants of this block that were
-                        // edited within the duration, but we will process those in a later
-                        // function
-                        block_roots.push(block);
-                    }
-                } else {
-                    // keep recursing down the tree of children blocks
-                    block_ids_to_process.push_back(block.id);
+        Ok(block_roots)
+    }
+
+    /// Lazily finds the same `Block` roots as `get_page_block_roots`, yielding each one as soon as it is
+    /// found so callers can start working on them before the whole Page has been searched.
+    ///
+    /// Searches the block descendants of the page in the order set by `set_traversal_strategy`. A Block that was
+    /// edited before the cutoff may still have children that were edited after it (Notion doesn't bump a
+    /// parent's edit time when its children change), so we keep searching below old Blocks, but we never
+    /// fetch the children of a Block that doesn't have any. Searching stops once `abort_time` has passed,
+    /// the rest of a Block's children are skipped once the search has seen too many old ones in a row (see
+    /// `set_consecutive_old_blocks_abort_threshold`), and it searches below at most `set_max_blocks_per_page` old
+    /// Blocks.
+    pub fn stream_page_block_roots<'a>(
+        &'a self,
+        page: &'a Page,
+        dur: Duration,
+        abort_time: DateTime<Utc>,
+    ) -> impl Stream<Item = Result<Block, DrossError>> + 'a {
+        self.stream_positioned_block_roots(page, dur, abort_time)
+            .map(|positioned_block| positioned_block.map(|(_, block)| block))
+    }
+
+    /// `stream_page_block_roots`, with each `Block` root's position on the Page: the index of
+    /// each of its ancestors among their siblings, from the top of the Page down, followed by
+    /// its own. Sorting by position puts the roots in the order they appear on the Page.
+    fn stream_positioned_block_roots<'a>(
+        &'a self,
+        page: &'a Page,
+        dur: Duration,
+        abort_time: DateTime<Utc>,
+    ) -> impl Stream<Item = Result<(Vec<usize>, Block), DrossError>> + 'a {
+        let cutoff = Utc::now() - dur;
+
+        try_stream! {
+            let mut block_ids_to_process = VecDeque::from([page.id.clone()]);
+            // the positions of the Blocks waiting to be searched below, so their children's
+            // positions can be worked out
+            let mut positions: HashMap<BlockID, Vec<usize>> = HashMap::from([(page.id.clone(), Vec::new())]);
+            let mut already_visited: HashSet<String> = HashSet::new();
+            let mut block_roots_found = 0;
+            let mut blocks_queued = 0;
+            let mut blocks_skipped = 0;
+
+            while let Some(block_id) = match self.traversal_strategy {
+                TraversalStrategy::Dfs => block_ids_to_process.pop_back(),
+                TraversalStrategy::Bfs => block_ids_to_process.pop_front(),
+            } {
+                if !already_visited.insert(block_id.clone()) {
+                    // we've already processed this block, so skip it
+                    trace!(target: "notion", "already visited this block {}, skipping it...", &block_id);
+                    continue;
                 }
-            }
+                trace!(target: "notion", "getting block root with id {}", &block_id);
+                let children = self
+                    .retrieve_all_block_children(&page.id, &block_id)
+                    .await?;
+                let parent_position = positions.remove(&block_id).unwrap_or_default();
 
-            already_visited.insert(block_id);
+                let mut old_blocks_with_children = Vec::new();
+                let children_count = children.len();
+                let mut consecutive_old_blocks = 0;
+                for (i, block) in children.into_iter().enumerate() {
+                    let position = [parent_position.as_slice(), &[i]].concat();
+                    if block.update_date >= cutoff {
+                        consecutive_old_blocks = 0;
+                    } else {
+                        consecutive_old_blocks += 1;
+                        if consecutive_old_blocks >= self.consecutive_old_blocks_abort_threshold {
+                            debug!(target: "notion", "saw {} old Blocks in a row below {} on Page {}, skipping the remaining {} of them", consecutive_old_blocks, block_id, page.title, children_count - i - 1);
+                            break;
+                        }
+                    }
 
-            if Utc::now() > abort_time {
-                // we've spent too much time fetching children, so just return what we have
-                debug!(target: "notion", "aborting block retrieval due to time limit");
-                debug!(target: "notion", "returning {} block roots for page: {}", block_roots.len(), page.title);
-                break;
+                    let is_column_list = matches!(block.block_type, BlockType::ColumnList { .. });
+                    if block.update_date >= cutoff && (!block.is_empty() || is_column_list) {
+                        // the Block's edit time is within the duration. Note, there may be
+                        // further descendants of this block that were edited within the
+                        // duration, but we will process those in a later function. A column
+                        // list has no text of its own, but it's grown like any other root so
+                        // its columns stay together
+                        block_roots_found += 1;
+                        yield (position, block);
+                    } else if block.has_children {
+                        // the Block is old, or has nothing to output itself (e.g. a column),
+                        // so keep recursing down the tree of children blocks
+                        positions.insert(block.id.clone(), position);
+                        old_blocks_with_children.push(block.id);
+                    }
+                }
+                if let Some(max_blocks) = self.max_blocks_per_page {
+                    let room = max_blocks.saturating_sub(blocks_queued);
+                    if old_blocks_with_children.len() > room {
+                        blocks_skipped += old_blocks_with_children.len() - room;
+                        old_blocks_with_children.truncate(room);
+                    }
+                }
+                blocks_queued += old_blocks_with_children.len();
+                match self.traversal_strategy {
+                    // the stack is last-in-first-out, so push in reverse to visit children top to bottom
+                    TraversalStrategy::Dfs => {
+                        block_ids_to_process.extend(old_blocks_with_children.into_iter().rev());
+                    }
+                    TraversalStrategy::Bfs => block_ids_to_process.extend(old_blocks_with_children),
+                }
+
+                if Utc::now() > abort_time {
+                    // we've spent too much time fetching children, so just return what we have
+                    debug!(target: "notion", "aborting block retrieval due to time limit");
+                    debug!(target: "notion", "returning {} block roots for page: {}", block_roots_found, page.title);
+                    break;
+                }
+            }
+            if blocks_skipped > 0 {
+                debug!(target: "notion", "Page {} has more than {} old Blocks to search below, skipped the children of {} of them", page.title, blocks_queued, blocks_skipped);
             }
         }
-
-        debug!(target: "notion", "fetched {} descendant Blocks from Page {}", block_roots.len(), page.url);
-        debug!(target: "notion", "{:#?}", block_roots);
-
-        Ok(block_roots)
     }
 
     /// Given a `Vec` of `Block`s (call these `Block`s "roots") that have been updated recently,
@@ -205,27 +936,68 @@ impl Notion {
     pub async fn grow_the_roots(
         &self,
         block_roots: Vec<Block>,
-    ) -> Result<Vec<Tree<Block>>, NotionClientError> {
+    ) -> Result<Vec<BlockTree>, DrossError> {
+        self.grow(block_roots, true).await
+    }
+
+    /// Same as `grow_the_roots`, except the Pages nested in the Page (its `ChildPage` Blocks)
+    /// are left as leaves, so the trees only hold the Page's own content
+    pub async fn grow_the_roots_within_page(
+        &self,
+        block_roots: Vec<Block>,
+    ) -> Result<Vec<BlockTree>, DrossError> {
+        self.grow(block_roots, false).await
+    }
+
+    async fn grow(
+        &self,
+        block_roots: Vec<Block>,
+        into_child_pages: bool,
+    ) -> Result<Vec<BlockTree>, DrossError> {
+        let should_grow = |block: &Block| {
+            block.has_children
+                && (into_child_pages || !matches!(block.block_type, BlockType::ChildPage { .. }))
+        };
         let mut blossomed_roots = Vec::new();
         for block in block_roots {
             let root = Node::new_tree(block);
-            blossomed_roots.push(root.tree());
+            blossomed_roots.push(BlockTree::from(root.clone()));
             let mut queue = VecDeque::new();
 
             queue.push_back(root);
+            // the children of the next Block in the queue that has any, fetched in the
+            // background while the current Block's children are fetched and added
+            let mut prefetched: Option<(BlockID, PendingChildren)> = None;
             while let Some(node) = queue.pop_front() {
                 let grant = node.tree().grant_hierarchy_edit().unwrap();
 
                 // TODO: figure out how to make this more efficient by not copying every block value
                 let page_id = node.borrow_data().page_id.clone();
                 let block_id = node.borrow_data().id.clone();
-                let has_children = node.borrow_data().has_children;
+                let has_children = should_grow(&node.borrow_data());
 
                 if has_children {
-                    let children = self
-                        .retrieve_all_block_children(&page_id, &block_id)
-                        .await?;
+                    let pending = match prefetched.take() {
+                        Some((prefetched_id, pending)) if prefetched_id == block_id => pending,
+                        _ => self.prefetch_block_children(page_id, block_id.clone()),
+                    };
+                    prefetched = queue
+                        .iter()
+                        .find(|next| should_grow(&next.borrow_data()))
+                        .map(|next| {
+                            let next = next.borrow_data();
+                            let pending =
+                                self.prefetch_block_children(next.page_id.clone(), next.id.clone());
+                            (next.id.clone(), pending)
+                        });
+
+                    let children = pending
+                        .await
+                        .expect("prefetch tasks always send their result")?;
                     for child in children {
+                        if child.parent_block_id.as_ref() != Some(&block_id) {
+                            warn!(target: "notion", "Block {} was returned as a child of Block {}, but its parent is {:?}", child.id, block_id, child.parent_block_id);
+                        }
                         node.create_as_last_child(&grant, child);
                         queue.push_back(node.last_child().unwrap());
                     }
@@ -236,87 +1008,1103 @@ impl Notion {
         Ok(blossomed_roots)
     }
 
+    /// Fetches every Block on `page` and returns the ones edited at or after `cutoff` whose
+    /// parent isn't one of the others, e.g. because the parent was deleted or hasn't synced.
+    /// This fetches the children of every Block that has any, so it's only meant for diagnosing
+    /// a single Page.
+    pub async fn find_orphan_blocks(
+        &self,
+        page: &Page,
+        cutoff: DateTime<Utc>,
+    ) -> Result<Vec<Block>, DrossError> {
+        let mut blocks = Vec::new();
+        let mut block_ids_to_process = VecDeque::from([page.id.clone()]);
+        let mut already_visited: HashSet<BlockID> = HashSet::new();
+        while let Some(block_id) = block_ids_to_process.pop_front() {
+            if !already_visited.insert(block_id.clone()) {
+                continue;
+            }
+            for block in self
+                .retrieve_all_block_children(&page.id, &block_id)
+                .await?
+            {
+                if block.has_children {
+                    block_ids_to_process.push_back(block.id.clone());
+                }
+                blocks.push(block);
+            }
+        }
+        debug!(target: "notion", "fetched all {} Blocks of Page {}", blocks.len(), page.title);
+
+        let mut orphans = find_orphans(&blocks, &page.id);
+        orphans.retain(|block| block.update_date >= cutoff);
+        for block in &orphans {
+            warn!(target: "notion", "Block {} on Page {} has parent {:?}, which isn't on the Page", block.id, page.title, block.parent_block_id);
+        }
+        Ok(orphans)
+    }
+
+    /// Starts `retrieve_all_block_children` on its own task, so the children can be fetched
+    /// while the caller gets on with something else. They still wait on the rate limiter.
+    fn prefetch_block_children(&self, page_id: PageID, block_id: BlockID) -> PendingChildren {
+        let (sender, receiver) = oneshot::channel();
+        let notion = self.clone();
+        tokio::spawn(async move {
+            let children = notion
+                .retrieve_all_block_children(&page_id, &block_id)
+                .await;
+            // the receiver is gone if growing the trees already failed, so there's no one to tell
+            let _ = sender.send(children);
+        });
+        receiver
+    }
+
     /// Retrieves all of the children (potentially multiple pages worth) of a Block with the given ID.
     ///
     /// Notion's API only allows for retrieving 100 children at a time, so this
     /// function exists to paginate through the results and return them all at once.
+    /// The children are returned in the order they appear in Notion, top to bottom.
     pub async fn retrieve_all_block_children(
         &self,
-        block_id: &str,
         page_id: &str,
-    ) -> Result<Vec<Block>, NotionClientError> {
+        block_id: &str,
+    ) -> Result<Vec<Block>, DrossError> {
         let mut children_blocks: Vec<Block> = Vec::new();
+        let mut seen_ids: HashSet<String> = HashSet::new();
         let mut current_cursor: Option<String> = None;
 
         loop {
+            let (blocks, has_more, next_cursor) = self
+                .retrieve_block_children_page(page_id, block_id, current_cursor.as_deref())
+                .await?;
+
+            // the API occasionally returns the same Block twice, so drop the repeats here
+            // rather than carrying them through the rest of the pipeline
+            for block in blocks {
+                if seen_ids.insert(block.id.clone()) {
+                    children_blocks.push(block);
+                } else {
+                    warn!(target: "notion", "skipping duplicate Block {} in Page {}", block.id, page_id);
+                }
+            }
+
+            if !has_more {
+                break;
+            }
+            current_cursor = next_cursor;
+        }
+
+        Ok(children_blocks)
+    }
+
+    /// Retrieves a single page of a Block's children, starting at `cursor`, along with whether
+    /// there are more and the cursor to get them with. If the response can't be read, the
+    /// request is retried a few times before giving up with `DrossError::Deserialization`.
+    async fn retrieve_block_children_page(
+        &self,
+        page_id: &str,
+        block_id: &str,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<Block>, bool, Option<String>), DrossError> {
+        let mut retries = 0;
+        loop {
+            self.start_api_call().await?;
             let res = self
-                .client
-                .blocks
-                .retrieve_block_children(block_id, current_cursor.as_deref(), Some(100))
+                .api
+                .retrieve_block_children(block_id, cursor, Some(100))
                 .await;
 
-            let res: RetrieveBlockChilerenResponse = match res {
-                Ok(res) => res,
-                Err(e) => match e {
-                    NotionClientError::FailedToDeserialize { source: _, body } => {
-                        let json_value: serde_json::Value = serde_json::from_str(&body).unwrap();
-                        let pretty_json = serde_json::to_string_pretty(&json_value).unwrap();
-                        debug!(target: "notion", "Custom Failed to deserialize response body");
-                        debug!(target: "notion", "{}", pretty_json);
-                        // there seems to be some bug in notion-client where it's unable to handle these
-                        // Response bodies, so I need to manually deserialize them here
-                        // TODO research further what's going on here
-                        serde_json::from_str(&body).unwrap()
-                    }
-                    _ => {
-                        error!(target: "notion", "Custom error in retrieve_block_children {}", e);
-                        panic!("{}", e)
+            let (source, body) = match res {
+                Ok(res) => {
+                    return Ok((
+                        res.results
+                            .into_iter()
+                            .map(|block| Block::from_notion_block(block, page_id.to_string()))
+                            .collect::<Vec<Block>>(),
+                        res.has_more,
+                        res.next_cursor,
+                    ))
+                }
+                Err(NotionClientError::FailedToDeserialize { source: _, body }) => {
+                    // notion-client can't deserialize some response bodies, usually because of a
+                    // Block type it doesn't know about. So we parse the Blocks one at a time
+                    // ourselves, and only lose the ones that really can't be read
+                    debug!(target: "notion", "notion-client failed to deserialize the children of Block {}, parsing them one at a time", block_id);
+                    match serde_json::from_str::<serde_json::Value>(&body) {
+                        Ok(json) => return Ok(parse_block_children(&json, page_id)),
+                        Err(e) => (e, body),
                     }
-                },
+                }
+                Err(e) => {
+                    error!(target: "notion", "Custom error in retrieve_block_children {}", e);
+                    return Err(DrossError::from_notion(e, Some(page_id)));
+                }
             };
 
-            children_blocks.append(
-                &mut res
-                    .results
-                    .into_iter()
-                    .map(|block| Block::from_notion_block(block, page_id.to_string()))
-                    .collect(),
-            );
+            if retries == UNREADABLE_RESPONSE_RETRIES {
+                return Err(DrossError::Deserialization { source, body });
+            }
+            retries += 1;
+            let delay = UNREADABLE_RESPONSE_RETRY_DELAY + retry_jitter();
+            warn!(target: "notion", "couldn't read the children of Block {} ({}), retrying in {:?} ({} of {})", block_id, source, delay, retries, UNREADABLE_RESPONSE_RETRIES);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Retrieves the title of every Page the integration has access to, keyed by Page ID.
+    ///
+    /// This paginates through the entire workspace, so prefer the cached `PageMap` over calling this directly.
+    pub async fn get_all_pages_map(&self) -> Result<HashMap<PageID, String>, DrossError> {
+        let pages_map = self
+            .search_all_pages()
+            .await?
+            .into_iter()
+            .map(|page| (page.id.clone(), page_title_from_url(&page.url)))
+            .collect::<HashMap<_, _>>();
+
+        debug!(target: "notion", "fetched titles for {} Pages", pages_map.len());
+
+        Ok(pages_map)
+    }
+
+    /// Arranges every Page the integration has access to into the workspace's Page hierarchy.
+    ///
+    /// The hierarchy comes from the `parent` of each search result, so no Blocks are fetched
+    /// and the graph's Pages have no `child_blocks`. Like `get_all_pages_map`, this paginates
+    /// through the entire workspace.
+    pub async fn build_workspace_graph(&self) -> Result<WorkspaceGraph, DrossError> {
+        let pages = self
+            .search_all_pages()
+            .await?
+            .into_iter()
+            .map(page_without_blocks)
+            .collect::<Vec<_>>();
+        debug!(target: "notion", "built the workspace graph from {} Pages", pages.len());
+        Ok(WorkspaceGraph::from_pages(pages))
+    }
+
+    /// Every Page the integration has access to, as the search returns them
+    async fn search_all_pages(&self) -> Result<Vec<NotionPage>, DrossError> {
+        let mut pages = Vec::new();
+        let mut current_cursor: Option<String> = None;
+
+        let mut req_builder = all_pages_search();
+        req_builder.page_size(100);
+
+        loop {
+            if let Some(cursor) = current_cursor {
+                req_builder.start_cursor(cursor);
+            }
+
+            self.start_api_call().await?;
+            let res = self
+                .api
+                .search_by_title(req_builder.build().unwrap())
+                .await?;
+
+            for page_or_db in res.results {
+                if let PageOrDatabase::Page(page) = page_or_db {
+                    pages.push(page);
+                }
+            }
 
             if !res.has_more {
                 break;
             }
-            current_cursor = res.next_cursor.clone();
+            current_cursor = res.next_cursor;
         }
 
-        Ok(children_blocks)
+        Ok(pages)
+    }
+
+    /// Lists every user in the workspace, people and bots alike. The result is cached, so
+    /// only the first call makes any requests.
+    pub async fn list_all_users(&self) -> Result<Vec<WorkspaceUser>, DrossError> {
+        let users = self
+            .users
+            .get_or_try_init(|| async {
+                let mut users = Vec::new();
+                let mut current_cursor: Option<String> = None;
+
+                loop {
+                    self.start_api_call().await?;
+                    let res = self
+                        .api
+                        .list_all_users(current_cursor.as_deref(), Some(100))
+                        .await?;
+
+                    for user in res.results {
+                        match serde_json::to_value(&user) {
+                            Ok(json) => users.push(WorkspaceUser::from_json(&json)),
+                            Err(e) => error!(target: "notion", "failed to read user: {}", e),
+                        }
+                    }
+
+                    if !res.has_more {
+                        break;
+                    }
+                    current_cursor = res.next_cursor;
+                }
+
+                debug!(target: "notion", "fetched {} workspace users", users.len());
+                Ok::<_, DrossError>(users)
+            })
+            .await?;
+
+        Ok(users.clone())
+    }
+
+    /// Retrieves the title of a single Page, for when it's missing from the `PageMap`
+    pub async fn get_page_title(&self, page_id: &str) -> Result<String, DrossError> {
+        self.start_api_call().await?;
+        let notion_page = self.api.retrieve_a_page(page_id).await?;
+        Ok(page_title_from_url(&notion_page.url))
+    }
+
+    /// The URL a Page has been published to the web at, or `None` if it isn't shared publicly.
+    /// Notion only sets `public_url` in its page objects for published Pages.
+    pub async fn get_page_public_url(
+        &self,
+        page_id: &PageID,
+    ) -> Result<Option<String>, DrossError> {
+        self.start_api_call().await?;
+        let notion_page = self
+            .api
+            .retrieve_a_page(page_id)
+            .await
+            .map_err(|e| DrossError::from_notion(e, Some(page_id)))?;
+        Ok(notion_page.public_url)
+    }
+
+    /// Creates a new Page titled `title` under the Page with ID `parent_page_id`, filled with
+    /// `blocks`, and returns its URL. Notion only accepts `MAX_CHILDREN_PER_CREATE` Blocks per
+    /// request, so the Page is created with the first of them and the rest are appended to it
+    /// in chunks of that size.
+    pub async fn create_page(
+        &self,
+        parent_page_id: &str,
+        title: &str,
+        blocks: Vec<Block>,
+    ) -> Result<String, DrossError> {
+        let mut children: Vec<NotionBlock> = blocks
+            .into_iter()
+            .map(|block| NotionBlock {
+                block_type: block.block_type,
+                ..Default::default()
+            })
+            .collect();
+        let appended = children.split_off(children.len().min(MAX_CHILDREN_PER_CREATE));
+        let title = vec![RichText::Text {
+            plain_text: Some(title.to_string()),
+            href: None,
+            annotations: None,
+            text: Text {
+                content: title.to_string(),
+                link: None,
+            },
+        }];
+        let request = CreateAPageRequest {
+            parent: Parent::PageId {
+                page_id: parent_page_id.to_string(),
+            },
+            properties: BTreeMap::from([(
+                "title".to_string(),
+                PageProperty::Title { id: None, title },
+            )]),
+            children: Some(children),
+            ..Default::default()
+        };
+
+        self.start_api_call().await?;
+        let notion_page = self
+            .api
+            .create_a_page(request)
+            .await
+            .map_err(|e| DrossError::from_notion(e, Some(parent_page_id)))?;
+
+        for chunk in appended.chunks(MAX_CHILDREN_PER_CREATE) {
+            let request = AppendBlockChildrenRequest {
+                children: chunk.to_vec(),
+                after: None,
+            };
+            self.start_api_call().await?;
+            self.api
+                .append_block_children(&notion_page.id, request)
+                .await
+                .map_err(|e| DrossError::from_notion(e, Some(&notion_page.id)))?;
+        }
+        Ok(notion_page.url)
+    }
+
+    pub async fn get_page_by_id(&self, page_id: &PageID) -> Result<Page, DrossError> {
+        self.start_api_call().await?;
+        let notion_page = self
+            .api
+            .retrieve_a_page(page_id)
+            .await
+            .map_err(|e| DrossError::from_notion(e, Some(page_id)))?;
+        self.notion_page_to_dross_page(notion_page).await
+    }
+
+    /// Retrieves the property values of a Page. Only Pages that are rows in a database have
+    /// interesting properties, every other Page just has its title. Properties are cached, so
+    /// asking for the same Page again in a run doesn't make another request.
+    pub async fn retrieve_page_properties(
+        &self,
+        page_id: &PageID,
+    ) -> Result<HashMap<String, PropertyValue>, DrossError> {
+        if let Some(properties) = self.cached_page_properties(page_id) {
+            return Ok(properties);
+        }
+
+        self.start_api_call().await?;
+        let notion_page = self.api.retrieve_a_page(page_id).await?;
+
+        let properties = notion_page
+            .properties
+            .into_iter()
+            .map(|(name, property)| {
+                let value = match serde_json::to_value(&property) {
+                    Ok(json) => PropertyValue::from_json(&json),
+                    Err(e) => PropertyValue::Unsupported(e.to_string()),
+                };
+                (name, value)
+            })
+            .collect::<HashMap<_, _>>();
+        self.page_properties
+            .lock()
+            .unwrap()
+            .insert(page_id.clone(), properties.clone());
+        Ok(properties)
+    }
+
+    fn cached_page_properties(&self, page_id: &PageID) -> Option<HashMap<String, PropertyValue>> {
+        let cached = self.page_properties.lock().unwrap().get(page_id).cloned();
+        self.record_cache_lookup(cached)
+    }
+
+    /// Retrieves the title and property types of a database. Schemas are cached, so asking for
+    /// the same database again in a run doesn't make another request.
+    pub async fn retrieve_database_schema(
+        &self,
+        database_id: &str,
+    ) -> Result<DatabaseSchema, DrossError> {
+        if let Some(schema) = self.cached_database_schema(database_id) {
+            return Ok(schema);
+        }
+
+        self.start_api_call().await?;
+        let notion_database = self
+            .api
+            .retrieve_a_database(database_id)
+            .await
+            .map_err(|e| DrossError::from_notion(e, Some(database_id)))?;
+        let schema = DatabaseSchema::from_json(&serde_json::to_value(&notion_database)?);
+
+        self.database_schemas
+            .lock()
+            .unwrap()
+            .insert(database_id.to_string(), schema.clone());
+        Ok(schema)
+    }
+
+    fn cached_database_schema(&self, database_id: &str) -> Option<DatabaseSchema> {
+        let cached = self
+            .database_schemas
+            .lock()
+            .unwrap()
+            .get(database_id)
+            .cloned();
+        self.record_cache_lookup(cached)
     }
 
     /// Converts a Notion page to a Dross page.
     ///
     /// Note that the title extraction is a bit hacky and may not work for every page title, but it's good enough for getting the gist of what the page is called.
-    async fn notion_page_to_dross_page(
-        &self,
-        notion_page: NotionPage,
-    ) -> Result<Page, NotionClientError> {
+    async fn notion_page_to_dross_page(&self, notion_page: NotionPage) -> Result<Page, DrossError> {
+        let child_blocks = self
+            .retrieve_all_block_children(&notion_page.id, &notion_page.id)
+            .await?;
         Ok(Page {
-            id: notion_page.id.clone(),
-            // convert https://www.notion.so/August-19-2024-651d530e07a14f9c97b4084614c5049b -> August 19 2024
-            // Note: yes, this is kinda hacky and won't work for every page title, but it's good enough
-            // for getting the gist of what the page is called
-            title: match notion_page.url.split("/").last() {
-                Some(name) => {
-                    let parts = name.split("-").collect::<Vec<&str>>();
-                    parts.split_at(parts.len() - 1).0.join(" ")
-                }
-                None => "Unknown Page Title".to_string(),
-            },
-            url: notion_page.url.clone(),
-            creation_date: notion_page.created_time,
-            update_date: notion_page.last_edited_time,
-            child_blocks: self
-                .retrieve_all_block_children(&notion_page.id, &notion_page.id)
-                .await?,
+            child_blocks,
+            ..page_without_blocks(notion_page)
+        })
+    }
+}
+
+/// Converts a Notion page to a Dross page without fetching its Blocks, for when only the
+/// Page's place in the hierarchy is needed
+/// The search for every Page the integration has access to
+fn all_pages_search() -> SearchByTitleRequestBuilder {
+    let mut req_builder = SearchByTitleRequestBuilder::default();
+    req_builder.filter(Filter {
+        value: notion_client::endpoints::search::title::request::FilterValue::Page,
+        property: notion_client::endpoints::search::title::request::FilterProperty::Object,
+    });
+    req_builder
+}
+
+/// `all_pages_search`, most recently edited first
+fn last_edited_pages_search() -> SearchByTitleRequestBuilder {
+    let mut req_builder = all_pages_search();
+    req_builder.sort(Sort {
+        timestamp: Timestamp::LastEditedTime,
+        direction: SortDirection::Descending,
+    });
+    req_builder
+}
+
+fn page_without_blocks(notion_page: NotionPage) -> Page {
+    Page {
+        id: notion_page.id.clone(),
+        title: page_title_from_url(&notion_page.url),
+        url: notion_page.url.clone(),
+        creation_date: notion_page.created_time,
+        update_date: notion_page.last_edited_time,
+        created_by_id: notion_page.created_by.id.clone(),
+        child_blocks: Vec::new(),
+        parent_database_id: match &notion_page.parent {
+            Parent::DatabaseId { database_id } => Some(database_id.clone()),
+            _ => None,
+        },
+        parent_page_id: match notion_page.parent {
+            Parent::PageId { page_id } => Some(page_id),
+            _ => None,
+        },
+    }
+}
+
+/// The URL that downloads a Page as a PDF, i.e. its notion.so URL with a `.pdf` extension.
+/// This isn't part of the official API, so it may stop working, and the person opening it
+/// still needs access to the Page.
+#[must_use]
+pub fn page_export_url(page_id: &PageID) -> String {
+    format!("https://www.notion.so/{}.pdf", page_id.replace('-', ""))
+}
+
+/// The Blocks in a page of Block children that notion-client couldn't deserialize, along with
+/// whether there are more and the cursor to get them with. Blocks that can't be read on their
+/// own are skipped.
+fn parse_block_children(
+    json: &serde_json::Value,
+    page_id: &str,
+) -> (Vec<Block>, bool, Option<String>) {
+    let blocks = json["results"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|raw| match Block::from_notion_json(&raw.to_string()) {
+            Ok(block) => Some(Block {
+                page_id: page_id.to_string(),
+                ..block
+            }),
+            Err(e) => {
+                warn!(target: "notion", "skipping unreadable Block {} in Page {}: {}", raw["id"], page_id, e);
+                None
+            }
         })
+        .collect();
+    (
+        blocks,
+        json["has_more"].as_bool().unwrap_or_default(),
+        json["next_cursor"].as_str().map(str::to_string),
+    )
+}
+
+/// Up to `UNREADABLE_RESPONSE_RETRY_JITTER_MS` of randomness to add to a retry delay, so that
+/// concurrent requests that failed together don't all retry at the same moment. It's taken
+/// from the clock rather than a random number generator, which is plenty for spreading retries.
+fn retry_jitter() -> StdDuration {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    StdDuration::from_millis(u64::from(
+        nanos % (UNREADABLE_RESPONSE_RETRY_JITTER_MS * 1_000_000) / 1_000_000,
+    ))
+}
+
+/// Extracts a `Page`'s title from its URL.
+///
+/// Note that this is a bit hacky and may not work for every page title, but it's good enough for getting the gist of what the page is called.
+fn page_title_from_url(url: &str) -> String {
+    // convert https://www.notion.so/August-19-2024-651d530e07a14f9c97b4084614c5049b -> August 19 2024
+    // Note: yes, this is kinda hacky and won't work for every page title, but it's good enough
+    // for getting the gist of what the page is called
+    match url.split("/").last() {
+        Some(name) => {
+            let parts = name.split("-").collect::<Vec<&str>>();
+            parts.split_at(parts.len() - 1).0.join(" ")
+        }
+        None => "Unknown Page Title".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::core::ingest::{ingest_notion, IngestOptions};
+    use api::FixtureRequest;
+    use fake::{page_json, FakeNotionApi, FakeResponse, FAKE_USER_ID};
+
+    #[test]
+    fn test_notion_can_be_shared_across_tasks() {
+        fn assert_spawnable<T: Clone + Send + Sync + 'static>() {}
+        assert_spawnable::<Notion>();
+    }
+
+    #[test]
+    fn test_searches_are_recorded_under_their_own_keys() {
+        let key = |search: SearchByTitleRequestBuilder| api::search_key(&search.build().unwrap());
+
+        // the search fixtures in tests/fixtures are recorded under these
+        assert_eq!(key(all_pages_search()), "f9b947de19599ac3");
+        assert_eq!(key(last_edited_pages_search()), "9c01f1beaf0ce71f");
+        // the next page of the same search is recorded next to the first
+        let mut next_page = last_edited_pages_search();
+        next_page.start_cursor("cursor".to_string()).page_size(10);
+        assert_eq!(key(next_page), "9c01f1beaf0ce71f");
+    }
+
+    #[test]
+    fn test_page_export_url() {
+        assert_eq!(
+            page_export_url(&"651d530e-07a1-4f9c-97b4-084614c5049b".to_string()),
+            "https://www.notion.so/651d530e07a14f9c97b4084614c5049b.pdf"
+        );
+    }
+
+    #[test]
+    fn test_build_rejects_api_versions_that_arent_dates() {
+        assert!(is_api_version("2022-02-22"));
+        for version in ["latest", "2022-6-28", "2022-02-30"] {
+            assert!(!is_api_version(version));
+            assert!(matches!(
+                NotionBuilder::new("secret".to_string())
+                    .api_version(version)
+                    .build(),
+                Err(DrossError::InvalidApiVersion { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_feature_set_from_api_version() {
+        assert_eq!(
+            FeatureSet::from_api_version(DEFAULT_NOTION_API_VERSION),
+            FeatureSet {
+                supports_comments: true,
+                supports_parent_filtering: true,
+                supports_user_mentions: true,
+            }
+        );
+        assert_eq!(
+            FeatureSet::from_api_version("2022-02-22"),
+            FeatureSet {
+                supports_comments: false,
+                supports_parent_filtering: false,
+                supports_user_mentions: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_token_bucket_adapts_to_rate_limiting() {
+        let bucket = TokenBucket::new(3, 4.0);
+
+        // a lone 429 could be a blip, so it doesn't slow anything down
+        bucket.record_rate_limited();
+        assert_eq!(bucket.refill_rate(), 4.0);
+        bucket.record_rate_limited();
+        assert_eq!(bucket.refill_rate(), 2.0);
+        bucket.record_rate_limited();
+        assert_eq!(bucket.refill_rate(), 1.0);
+
+        bucket.throttle.lock().unwrap().last_changed -= RATE_LIMIT_RECOVERY_PERIOD;
+        assert_eq!(bucket.refill_rate(), 2.0);
+        // the next step up waits for another quiet period
+        assert_eq!(bucket.refill_rate(), 2.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_bucket_waits_once_empty() {
+        let bucket = TokenBucket::new(2, 10.0);
+
+        let start = Instant::now();
+        bucket.acquire().await;
+        bucket.acquire().await;
+        assert_eq!(start.elapsed(), StdDuration::ZERO);
+
+        // the bucket is empty, so the next token takes 1/10th of a second to refill
+        bucket.acquire().await;
+        let waited = start.elapsed();
+        assert!(waited >= StdDuration::from_millis(100));
+        assert!(waited < StdDuration::from_millis(110));
+    }
+
+    /// SplitMix64, a tiny seedable random number generator, so generated workspaces don't
+    /// need a dependency only for tests
+    struct SplitMix64(u64);
+
+    impl SplitMix64 {
+        fn next(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
+
+        /// A number from 0 up to, but not including, `n`
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// The types of Block a generated workspace is made of, which are the ones dross renders
+    /// differently from plain text plus paragraphs
+    const GENERATED_BLOCK_TYPES: [&str; 10] = [
+        "paragraph",
+        "heading_1",
+        "heading_2",
+        "heading_3",
+        "bulleted_list_item",
+        "numbered_list_item",
+        "to_do",
+        "toggle",
+        "quote",
+        "code",
+    ];
+
+    const GENERATED_WORDS: [&str; 12] = [
+        "invoice", "roadmap", "meeting", "draft", "launch", "notes", "Dana", "follow", "up",
+        "budget", "review", "ideas",
+    ];
+
+    /// Generates a fake workspace with `generate_workspace`, so that the same seed always gives
+    /// the same Pages and Blocks, to serve with `into_api`. Like the recorded fixtures in
+    /// `tests/`, but for workspaces too big to record.
+    #[derive(Default)]
+    struct TestNotion {
+        pages: Vec<serde_json::Value>,
+        /// The children of each Page and Block, by its ID
+        children: HashMap<String, Vec<serde_json::Value>>,
+        next_id: u64,
+    }
+
+    impl TestNotion {
+        /// Adds `page_count` Pages to the workspace, each with `blocks_per_page` top-level
+        /// Blocks and up to `depth` levels of Blocks in all, of every type in
+        /// `GENERATED_BLOCK_TYPES`. Every Block was edited after August 1st, 2024, and the
+        /// Pages were edited an hour apart before then.
+        ///
+        /// # Returns
+        /// Each Page with every one of its Blocks, parents before their children
+        fn generate_workspace(
+            &mut self,
+            seed: u64,
+            page_count: usize,
+            blocks_per_page: usize,
+            depth: usize,
+        ) -> Vec<(Page, Vec<Block>)> {
+            let mut rng = SplitMix64(seed);
+            (0..page_count)
+                .map(|i| {
+                    let page_id = self.new_id();
+                    let edited = generated_workspace_time() - Duration::hours(i as i64);
+                    let title = format!("{} {}", generated_text(&mut rng, 2), i + 1);
+                    let url = format!(
+                        "https://www.notion.so/{}-{}",
+                        title.replace(' ', "-"),
+                        page_id.replace('-', "")
+                    );
+                    self.pages.push(page_json(&page_id, &url, edited));
+
+                    let mut blocks = Vec::new();
+                    let child_blocks = self.generate_blocks(
+                        &mut rng,
+                        &page_id,
+                        &page_id,
+                        blocks_per_page,
+                        depth,
+                        &mut blocks,
+                    );
+                    let page = Page {
+                        url,
+                        creation_date: edited,
+                        update_date: edited,
+                        child_blocks,
+                        ..Page::for_test(&page_id, &title)
+                    };
+                    (page, blocks)
+                })
+                .collect()
+        }
+
+        /// Generates `count` children of `parent_id` with up to `depth - 1` levels below them,
+        /// adding them all to `blocks` and returning only the children
+        fn generate_blocks(
+            &mut self,
+            rng: &mut SplitMix64,
+            page_id: &str,
+            parent_id: &str,
+            count: usize,
+            depth: usize,
+            blocks: &mut Vec<Block>,
+        ) -> Vec<Block> {
+            let parent = if parent_id == page_id {
+                serde_json::json!({"type": "page_id", "page_id": parent_id})
+            } else {
+                serde_json::json!({"type": "block_id", "block_id": parent_id})
+            };
+            let mut children = Vec::new();
+            for _ in 0..count {
+                let id = self.new_id();
+                let type_name = GENERATED_BLOCK_TYPES[rng.below(GENERATED_BLOCK_TYPES.len())];
+                let text = generated_text(rng, 1 + rng.below(8));
+                let edited = generated_workspace_time() + Duration::minutes(rng.below(60) as i64);
+                let child_count = if depth > 1 { rng.below(3) } else { 0 };
+                let json =
+                    generated_block_json(&id, &parent, type_name, &text, edited, child_count > 0);
+
+                let mut block = Block::from_notion_json(&json.to_string())
+                    .expect("generated Blocks are valid Notion Blocks");
+                block.page_id = page_id.to_string();
+                self.children
+                    .entry(parent_id.to_string())
+                    .or_default()
+                    .push(json);
+                blocks.push(block.clone());
+                children.push(block);
+                self.generate_blocks(
+                    rng,
+                    page_id,
+                    &id,
+                    child_count,
+                    depth.saturating_sub(1),
+                    blocks,
+                );
+            }
+            children
+        }
+
+        fn new_id(&mut self) -> String {
+            self.next_id += 1;
+            format!("00000000-0000-4000-8000-{:012x}", self.next_id)
+        }
+
+        /// A fake that serves the workspace. Every search returns every Page, most recently
+        /// edited first, and every list of children fits in one response.
+        fn into_api(self) -> FakeNotionApi {
+            let api = FakeNotionApi::new();
+            api.set_search_results(self.pages);
+            for (block_id, children) in self.children {
+                api.set_children(&block_id, children);
+            }
+            api
+        }
+    }
+
+    /// When every generated Block was edited, give or take an hour
+    fn generated_workspace_time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 8, 1, 0, 0, 0).unwrap()
+    }
+
+    fn generated_text(rng: &mut SplitMix64, words: usize) -> String {
+        (0..words)
+            .map(|_| GENERATED_WORDS[rng.below(GENERATED_WORDS.len())])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn generated_block_json(
+        id: &str,
+        parent: &serde_json::Value,
+        type_name: &str,
+        text: &str,
+        edited: DateTime<Utc>,
+        has_children: bool,
+    ) -> serde_json::Value {
+        let mut value = match type_name {
+            "heading_1" | "heading_2" | "heading_3" => {
+                serde_json::json!({"is_toggleable": false, "color": "default"})
+            }
+            "to_do" => serde_json::json!({"checked": false, "color": "default"}),
+            "code" => serde_json::json!({"language": "rust"}),
+            _ => serde_json::json!({"color": "default"}),
+        };
+        value["rich_text"] = serde_json::json!([{
+            "type": "text",
+            "text": {"content": text, "link": null},
+            "plain_text": text,
+            "href": null
+        }]);
+        serde_json::json!({
+            "object": "block",
+            "id": id,
+            "parent": parent,
+            "created_time": edited.to_rfc3339(),
+            "last_edited_time": edited.to_rfc3339(),
+            "created_by": {"object": "user", "id": FAKE_USER_ID},
+            "last_edited_by": {"object": "user", "id": FAKE_USER_ID},
+            "has_children": has_children,
+            "archived": false,
+            "in_trash": false,
+            "type": type_name,
+            type_name: value
+        })
+    }
+
+    #[test]
+    fn test_generated_workspaces_are_deterministic() {
+        let summarize = |workspace: Vec<(Page, Vec<Block>)>| {
+            workspace
+                .into_iter()
+                .map(|(page, blocks)| {
+                    let blocks = blocks
+                        .into_iter()
+                        .map(|block| (block.type_name(), block.text, block.parent_block_id))
+                        .collect::<Vec<_>>();
+                    (page.title, blocks)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = summarize(TestNotion::default().generate_workspace(7, 5, 10, 3));
+        let second = summarize(TestNotion::default().generate_workspace(7, 5, 10, 3));
+        let other_seed = summarize(TestNotion::default().generate_workspace(8, 5, 10, 3));
+
+        assert_eq!(first, second);
+        assert_ne!(first, other_seed);
+        assert!(first.iter().all(|(_, blocks)| blocks.len() >= 10));
+    }
+
+    #[tokio::test]
+    async fn test_ingest_generated_workspace() {
+        let mut test_notion = TestNotion::default();
+        let workspace = test_notion.generate_workspace(42, 20, 15, 4);
+        // nothing here talks to the real API, so there's no reason to wait on the rate limiter
+        let notion = Notion::with_api(test_notion.into_api(), TokenBucket::new(u32::MAX, 1e9));
+        let options = IngestOptions {
+            dur: Utc::now() - (generated_workspace_time() - Duration::days(1)),
+            page_map_path: std::env::temp_dir().join(format!(
+                "dross-test-notion-{}-pages_map.json",
+                std::process::id()
+            )),
+            ..IngestOptions::default()
+        };
+
+        let context = ingest_notion(&notion, &options).await.unwrap();
+        std::fs::remove_file(&options.page_map_path).ok();
+
+        assert_eq!(context.pages.len(), workspace.len());
+        for (output, (page, blocks)) in context.pages.iter().zip(&workspace) {
+            assert_eq!(output.page.title, page.title);
+            assert_eq!(output.block_count, blocks.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_consecutive_old_blocks_only_skip_the_rest_of_their_parents_children() {
+        let page = Page::for_test("page", "Notes");
+        let parent = |id: &str| serde_json::json!({"type": "block_id", "block_id": id});
+        let old = Utc::now() - Duration::days(10);
+        let recent = Utc::now();
+        let api = FakeNotionApi::new();
+        api.set_children(
+            "page",
+            vec![
+                generated_block_json("toggle", &parent("page"), "toggle", "Old", old, true),
+                generated_block_json("old", &parent("page"), "paragraph", "Old", old, false),
+                generated_block_json(
+                    "after",
+                    &parent("page"),
+                    "paragraph",
+                    "After",
+                    recent,
+                    false,
+                ),
+            ],
+        );
+        // the count of old Blocks starts over here, rather than carrying on from the Page's
+        api.set_children(
+            "toggle",
+            vec![
+                generated_block_json(
+                    "inside-old",
+                    &parent("toggle"),
+                    "paragraph",
+                    "Old",
+                    old,
+                    false,
+                ),
+                generated_block_json(
+                    "inside",
+                    &parent("toggle"),
+                    "paragraph",
+                    "Inside",
+                    recent,
+                    false,
+                ),
+            ],
+        );
+        let mut notion = Notion::with_api(api, TokenBucket::new(u32::MAX, 1e9));
+        notion.set_consecutive_old_blocks_abort_threshold(2);
+
+        let block_roots = notion
+            .get_page_block_roots(&page, Duration::days(1))
+            .await
+            .unwrap();
+
+        let texts = block_roots
+            .iter()
+            .map(|block| block.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["Inside"]);
+    }
+
+    #[tokio::test]
+    async fn test_create_page_appends_the_blocks_past_the_create_limit() {
+        let api = Arc::new(FakeNotionApi::new());
+        let notion = Notion::with_api(api.clone(), TokenBucket::new(u32::MAX, 1e9));
+        let blocks = (0..250)
+            .map(|i| Block::from_text(&format!("line {}", i)))
+            .collect();
+
+        let url = notion.create_page("parent", "Notes", blocks).await.unwrap();
+
+        let created = api.created_pages();
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0]["children"].as_array().unwrap().len(), 100);
+        let appended = api.appended_children();
+        assert_eq!(
+            appended
+                .iter()
+                .map(|(block_id, body)| (
+                    url.ends_with(&block_id.replace('-', "")),
+                    body["children"].as_array().unwrap().len()
+                ))
+                .collect::<Vec<_>>(),
+            vec![(true, 100), (true, 50)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_workspace_graph_reads_the_hierarchy_from_the_search() {
+        const ROOT: &str = "a0000000-0000-4000-8000-000000000001";
+        const CHILD: &str = "a0000000-0000-4000-8000-000000000002";
+        const GRANDCHILD: &str = "a0000000-0000-4000-8000-000000000003";
+        let page = |id: &str, parent_id: Option<&str>| {
+            let url = format!("https://www.notion.so/{}", id.replace('-', ""));
+            let mut json = page_json(id, &url, Utc::now());
+            if let Some(parent_id) = parent_id {
+                json["parent"] = serde_json::json!({"type": "page_id", "page_id": parent_id});
+            }
+            json
+        };
+        let api = Arc::new(FakeNotionApi::new());
+        api.set_search_results(vec![
+            page(ROOT, None),
+            page(CHILD, Some(ROOT)),
+            page(GRANDCHILD, Some(CHILD)),
+        ]);
+        let notion = Notion::with_api(api.clone(), TokenBucket::new(u32::MAX, 1e9));
+
+        let graph = notion.build_workspace_graph().await.unwrap();
+
+        assert_eq!(graph.pages.len(), 3);
+        assert_eq!(graph.descendants(ROOT), vec![CHILD, GRANDCHILD]);
+        assert_eq!(graph.ancestors(GRANDCHILD), vec![CHILD, ROOT]);
+        // the search was the only request, no Blocks were fetched
+        assert_eq!(api.requested().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_health_check_tells_a_refused_request_from_an_unreachable_api() {
+        let health_check = |bot_user: FakeResponse, users: FakeResponse| async move {
+            let api = FakeNotionApi::new();
+            api.respond(FixtureRequest::BotUser, bot_user);
+            api.respond(FixtureRequest::Users { start_cursor: None }, users);
+            let notion = Notion::with_api(api, TokenBucket::new(u32::MAX, 1e9));
+            let status = notion.health_check().await.unwrap();
+            (
+                status.api_reachable,
+                status.token_valid,
+                status.users_readable,
+            )
+        };
+        let bot_user =
+            || FakeResponse::Json(serde_json::json!({"object": "user", "id": FAKE_USER_ID}));
+        let users = || FakeResponse::Json(fake::list_json(Vec::new(), "user"));
+
+        assert_eq!(health_check(bot_user(), users()).await, (true, true, true));
+        assert_eq!(
+            health_check(FakeResponse::Status(401), users()).await,
+            (true, false, false)
+        );
+        assert_eq!(
+            health_check(bot_user(), FakeResponse::Status(403)).await,
+            (true, true, false)
+        );
+        assert_eq!(
+            health_check(FakeResponse::Status(500), users()).await,
+            (false, false, false)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unreadable_block_children_are_retried() {
+        let request = || FixtureRequest::BlockChildren {
+            block_id: "parent",
+            start_cursor: None,
+        };
+        let api = Arc::new(FakeNotionApi::new());
+        for _ in 0..UNREADABLE_RESPONSE_RETRIES - 1 {
+            api.respond(
+                request(),
+                FakeResponse::Unreadable("{\"results\": [".to_string()),
+            );
+        }
+        api.respond(
+            request(),
+            FakeResponse::Json(fake::list_json(Vec::new(), "block")),
+        );
+        let notion = Notion::with_api(api.clone(), TokenBucket::new(u32::MAX, 1e9));
+
+        let res = notion
+            .retrieve_block_children_page("page", "parent", None)
+            .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            api.request_count(request()),
+            UNREADABLE_RESPONSE_RETRIES as usize
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_block_children_that_stay_unreadable_are_an_error() {
+        let request = || FixtureRequest::BlockChildren {
+            block_id: "parent",
+            start_cursor: None,
+        };
+        let api = Arc::new(FakeNotionApi::new());
+        api.respond(
+            request(),
+            FakeResponse::Unreadable("{\"results\": [".to_string()),
+        );
+        let notion = Notion::with_api(api.clone(), TokenBucket::new(u32::MAX, 1e9));
+
+        let res = notion
+            .retrieve_block_children_page("page", "parent", None)
+            .await;
+
+        assert!(matches!(res, Err(DrossError::Deserialization { .. })));
+        assert_eq!(
+            api.request_count(request()),
+            UNREADABLE_RESPONSE_RETRIES as usize + 1
+        );
     }
 }
@@ -0,0 +1,929 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{trace, warn};
+use notion_client::{
+    endpoints::{
+        blocks::{
+            append::{request::AppendBlockChildrenRequest, response::AppendBlockChildrenResponse},
+            retrieve::response::RetrieveBlockChilerenResponse,
+        },
+        pages::create::request::CreateAPageRequest,
+        search::title::{request::SearchByTitleRequest, response::SearchByTitleResponse},
+        users::list::response::ListAllUsersResponse,
+        Client,
+    },
+    objects::{
+        database::Database as NotionDatabase, page::Page as NotionPage, user::User as NotionUser,
+    },
+    NotionClientError,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use super::TokenBucket;
+use crate::core::error::DrossError;
+
+/// The handful of Notion API endpoints dross uses. `Notion` talks to the API through this
+/// trait so that tests can swap the real `notion_client::Client` for one that replays
+/// recorded responses, and so live runs can record those responses in the first place.
+#[async_trait]
+pub trait NotionApi: Send + Sync {
+    async fn search_by_title(
+        &self,
+        request: SearchByTitleRequest,
+    ) -> Result<SearchByTitleResponse, NotionClientError>;
+
+    async fn retrieve_block_children(
+        &self,
+        block_id: &str,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<RetrieveBlockChilerenResponse, NotionClientError>;
+
+    async fn retrieve_a_page(&self, page_id: &str) -> Result<NotionPage, NotionClientError>;
+
+    async fn retrieve_a_database(
+        &self,
+        database_id: &str,
+    ) -> Result<NotionDatabase, NotionClientError>;
+
+    async fn create_a_page(
+        &self,
+        request: CreateAPageRequest,
+    ) -> Result<NotionPage, NotionClientError>;
+
+    async fn append_block_children(
+        &self,
+        block_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<AppendBlockChildrenResponse, NotionClientError>;
+
+    async fn list_all_users(
+        &self,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<ListAllUsersResponse, NotionClientError>;
+
+    /// The bot user the integration token belongs to, from `users/me`
+    async fn retrieve_your_token_s_bot_user(&self) -> Result<NotionUser, NotionClientError>;
+}
+
+#[async_trait]
+impl NotionApi for Client {
+    async fn search_by_title(
+        &self,
+        request: SearchByTitleRequest,
+    ) -> Result<SearchByTitleResponse, NotionClientError> {
+        self.search.search_by_title(request).await
+    }
+
+    async fn retrieve_block_children(
+        &self,
+        block_id: &str,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<RetrieveBlockChilerenResponse, NotionClientError> {
+        self.blocks
+            .retrieve_block_children(block_id, start_cursor, page_size)
+            .await
+    }
+
+    async fn retrieve_a_page(&self, page_id: &str) -> Result<NotionPage, NotionClientError> {
+        self.pages.retrieve_a_page(page_id, None).await
+    }
+
+    async fn retrieve_a_database(
+        &self,
+        database_id: &str,
+    ) -> Result<NotionDatabase, NotionClientError> {
+        self.databases.retrieve_a_database(database_id).await
+    }
+
+    async fn create_a_page(
+        &self,
+        request: CreateAPageRequest,
+    ) -> Result<NotionPage, NotionClientError> {
+        self.pages.create_a_page(request).await
+    }
+
+    async fn append_block_children(
+        &self,
+        block_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<AppendBlockChildrenResponse, NotionClientError> {
+        self.blocks.append_block_children(block_id, request).await
+    }
+
+    async fn list_all_users(
+        &self,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<ListAllUsersResponse, NotionClientError> {
+        self.users.list_all_users(start_cursor, page_size).await
+    }
+
+    async fn retrieve_your_token_s_bot_user(&self) -> Result<NotionUser, NotionClientError> {
+        self.users.retrieve_your_token_s_bot_user().await
+    }
+}
+
+/// Talks to the Notion API with `reqwest` directly, sending `api_version` as the
+/// `Notion-Version` header. `notion_client::Client` sets that header itself, over any default
+/// headers it's given, so this is what `NotionBuilder` uses for any version other than
+/// `DEFAULT_NOTION_API_VERSION`.
+pub struct VersionedClient {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl VersionedClient {
+    pub fn new(token: &str, api_version: HeaderValue) -> Result<Self, DrossError> {
+        let mut authorization = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| DrossError::InvalidToken)?;
+        authorization.set_sensitive(true);
+        let headers = HeaderMap::from_iter([
+            (AUTHORIZATION, authorization),
+            (HeaderName::from_static("notion-version"), api_version),
+            (CONTENT_TYPE, HeaderValue::from_static("application/json")),
+        ]);
+        Ok(VersionedClient {
+            http: reqwest::Client::builder()
+                .default_headers(headers)
+                .build()?,
+            base_url: NOTION_API_URL.to_string(),
+        })
+    }
+
+    /// Sends `request`, reading the response the way `notion_client` does
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, NotionClientError> {
+        let response = request
+            .send()
+            .await
+            .map_err(|source| NotionClientError::FailedToRequest { source })?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|source| NotionClientError::FailedToText { source })?;
+        if !status.is_success() {
+            return match serde_json::from_str(&body) {
+                Ok(error) => Err(NotionClientError::InvalidStatusCode { error }),
+                Err(source) => Err(NotionClientError::FailedToDeserialize { source, body }),
+            };
+        }
+        serde_json::from_str(&body)
+            .map_err(|source| NotionClientError::FailedToDeserialize { source, body })
+    }
+}
+
+#[async_trait]
+impl NotionApi for VersionedClient {
+    async fn search_by_title(
+        &self,
+        request: SearchByTitleRequest,
+    ) -> Result<SearchByTitleResponse, NotionClientError> {
+        let url = format!("{}/search", self.base_url);
+        self.send(self.http.post(url).json(&request)).await
+    }
+
+    async fn retrieve_block_children(
+        &self,
+        block_id: &str,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<RetrieveBlockChilerenResponse, NotionClientError> {
+        let url = format!(
+            "{}/blocks/{}/children{}",
+            self.base_url,
+            block_id,
+            pagination_query(start_cursor, page_size)
+        );
+        self.send(self.http.get(url)).await
+    }
+
+    async fn retrieve_a_page(&self, page_id: &str) -> Result<NotionPage, NotionClientError> {
+        let url = format!("{}/pages/{}", self.base_url, page_id);
+        self.send(self.http.get(url)).await
+    }
+
+    async fn retrieve_a_database(
+        &self,
+        database_id: &str,
+    ) -> Result<NotionDatabase, NotionClientError> {
+        let url = format!("{}/databases/{}", self.base_url, database_id);
+        self.send(self.http.get(url)).await
+    }
+
+    async fn create_a_page(
+        &self,
+        request: CreateAPageRequest,
+    ) -> Result<NotionPage, NotionClientError> {
+        let url = format!("{}/pages", self.base_url);
+        self.send(self.http.post(url).json(&request)).await
+    }
+
+    async fn append_block_children(
+        &self,
+        block_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<AppendBlockChildrenResponse, NotionClientError> {
+        let url = format!("{}/blocks/{}/children", self.base_url, block_id);
+        self.send(self.http.patch(url).json(&request)).await
+    }
+
+    async fn list_all_users(
+        &self,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<ListAllUsersResponse, NotionClientError> {
+        let url = format!(
+            "{}/users{}",
+            self.base_url,
+            pagination_query(start_cursor, page_size)
+        );
+        self.send(self.http.get(url)).await
+    }
+    async fn retrieve_your_token_s_bot_user(&self) -> Result<NotionUser, NotionClientError> {
+        let url = format!("{}/users/me", self.base_url);
+        self.send(self.http.get(url)).await
+    }
+}
+
+/// Lets `NotionBuilder` stack up wrappers (recording, logging) around the real client
+/// without naming each combination's type
+#[async_trait]
+impl NotionApi for Box<dyn NotionApi> {
+    async fn search_by_title(
+        &self,
+        request: SearchByTitleRequest,
+    ) -> Result<SearchByTitleResponse, NotionClientError> {
+        (**self).search_by_title(request).await
+    }
+
+    async fn retrieve_block_children(
+        &self,
+        block_id: &str,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<RetrieveBlockChilerenResponse, NotionClientError> {
+        (**self)
+            .retrieve_block_children(block_id, start_cursor, page_size)
+            .await
+    }
+
+    async fn retrieve_a_page(&self, page_id: &str) -> Result<NotionPage, NotionClientError> {
+        (**self).retrieve_a_page(page_id).await
+    }
+
+    async fn retrieve_a_database(
+        &self,
+        database_id: &str,
+    ) -> Result<NotionDatabase, NotionClientError> {
+        (**self).retrieve_a_database(database_id).await
+    }
+
+    async fn create_a_page(
+        &self,
+        request: CreateAPageRequest,
+    ) -> Result<NotionPage, NotionClientError> {
+        (**self).create_a_page(request).await
+    }
+
+    async fn append_block_children(
+        &self,
+        block_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<AppendBlockChildrenResponse, NotionClientError> {
+        (**self).append_block_children(block_id, request).await
+    }
+
+    async fn list_all_users(
+        &self,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<ListAllUsersResponse, NotionClientError> {
+        (**self).list_all_users(start_cursor, page_size).await
+    }
+
+    async fn retrieve_your_token_s_bot_user(&self) -> Result<NotionUser, NotionClientError> {
+        (**self).retrieve_your_token_s_bot_user().await
+    }
+}
+
+/// Lets a test hold on to the fake it gives `Notion`, to look at the requests made to it
+#[async_trait]
+impl<A: NotionApi + ?Sized> NotionApi for Arc<A> {
+    async fn search_by_title(
+        &self,
+        request: SearchByTitleRequest,
+    ) -> Result<SearchByTitleResponse, NotionClientError> {
+        (**self).search_by_title(request).await
+    }
+
+    async fn retrieve_block_children(
+        &self,
+        block_id: &str,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<RetrieveBlockChilerenResponse, NotionClientError> {
+        (**self)
+            .retrieve_block_children(block_id, start_cursor, page_size)
+            .await
+    }
+
+    async fn retrieve_a_page(&self, page_id: &str) -> Result<NotionPage, NotionClientError> {
+        (**self).retrieve_a_page(page_id).await
+    }
+
+    async fn retrieve_a_database(
+        &self,
+        database_id: &str,
+    ) -> Result<NotionDatabase, NotionClientError> {
+        (**self).retrieve_a_database(database_id).await
+    }
+
+    async fn create_a_page(
+        &self,
+        request: CreateAPageRequest,
+    ) -> Result<NotionPage, NotionClientError> {
+        (**self).create_a_page(request).await
+    }
+
+    async fn append_block_children(
+        &self,
+        block_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<AppendBlockChildrenResponse, NotionClientError> {
+        (**self).append_block_children(block_id, request).await
+    }
+
+    async fn list_all_users(
+        &self,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<ListAllUsersResponse, NotionClientError> {
+        (**self).list_all_users(start_cursor, page_size).await
+    }
+
+    async fn retrieve_your_token_s_bot_user(&self) -> Result<NotionUser, NotionClientError> {
+        (**self).retrieve_your_token_s_bot_user().await
+    }
+}
+
+/// Where the recorded response to each request lives within a fixtures directory:
+///
+/// ```text
+/// search/<search key>/<cursor>.json
+/// blocks/<block id>/<cursor>.json
+/// pages/<page id>.json
+/// databases/<database id>.json
+/// users/<cursor>.json
+/// users/me.json
+/// ```
+///
+/// where `<cursor>` is `first` for the first page of results, and `<search key>` tells apart
+/// the searches made in the same run (see `search_key`).
+pub enum FixtureRequest<'a> {
+    Search {
+        search: &'a str,
+        start_cursor: Option<&'a str>,
+    },
+    BlockChildren {
+        block_id: &'a str,
+        start_cursor: Option<&'a str>,
+    },
+    Page {
+        page_id: &'a str,
+    },
+    Database {
+        database_id: &'a str,
+    },
+    Users {
+        start_cursor: Option<&'a str>,
+    },
+    /// The token's bot user, from `users/me`
+    BotUser,
+}
+
+impl FixtureRequest<'_> {
+    #[must_use]
+    pub fn path(&self, fixtures_dir: &Path) -> PathBuf {
+        let cursor_file = |cursor: Option<&str>| format!("{}.json", cursor.unwrap_or("first"));
+        match self {
+            FixtureRequest::Search {
+                search,
+                start_cursor,
+            } => fixtures_dir
+                .join("search")
+                .join(search)
+                .join(cursor_file(*start_cursor)),
+            FixtureRequest::BlockChildren {
+                block_id,
+                start_cursor,
+            } => fixtures_dir
+                .join("blocks")
+                .join(block_id)
+                .join(cursor_file(*start_cursor)),
+            FixtureRequest::Page { page_id } => {
+                fixtures_dir.join("pages").join(format!("{}.json", page_id))
+            }
+            FixtureRequest::Database { database_id } => fixtures_dir
+                .join("databases")
+                .join(format!("{}.json", database_id)),
+            FixtureRequest::Users { start_cursor } => {
+                fixtures_dir.join("users").join(cursor_file(*start_cursor))
+            }
+            FixtureRequest::BotUser => fixtures_dir.join("users").join("me.json"),
+        }
+    }
+}
+
+/// A hash of the query, filter and sort of a search, which `FixtureRequest` keys its fixtures
+/// by. The cursor and page size are left out, since they only pick a part of the same results.
+#[must_use]
+pub fn search_key(request: &SearchByTitleRequest) -> String {
+    let mut search = serde_json::to_value(request).unwrap_or_default();
+    if let Some(fields) = search.as_object_mut() {
+        fields.remove("start_cursor");
+        fields.remove("page_size");
+        fields.retain(|_, value| !value.is_null());
+    }
+    // `serde_json::Value` sorts object keys, so equal searches always hash the same
+    hex::encode(Sha256::digest(search.to_string().as_bytes()))[..16].to_string()
+}
+
+/// Passes every request through to `api`, usually the real Notion API, and writes each response to
+/// `fixtures_dir` in the layout described by `FixtureRequest` so it can be replayed in tests
+pub struct RecordingClient {
+    api: Box<dyn NotionApi>,
+    fixtures_dir: PathBuf,
+}
+
+impl RecordingClient {
+    #[must_use]
+    pub fn new(api: Box<dyn NotionApi>, fixtures_dir: PathBuf) -> Self {
+        RecordingClient { api, fixtures_dir }
+    }
+
+    /// Recording is best-effort, a fixture that fails to write shouldn't fail the run
+    fn record(&self, request: FixtureRequest<'_>, response: &impl Serialize) {
+        let path = request.path(&self.fixtures_dir);
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| {
+                let json = serde_json::to_string_pretty(response).map_err(std::io::Error::from)?;
+                fs::write(&path, json)
+            });
+        if let Err(e) = written {
+            warn!(target: "notion", "failed to record fixture {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[async_trait]
+impl NotionApi for RecordingClient {
+    async fn search_by_title(
+        &self,
+        request: SearchByTitleRequest,
+    ) -> Result<SearchByTitleResponse, NotionClientError> {
+        let search = search_key(&request);
+        let start_cursor = request.start_cursor.clone();
+        let res = self.api.search_by_title(request).await?;
+        self.record(
+            FixtureRequest::Search {
+                search: &search,
+                start_cursor: start_cursor.as_deref(),
+            },
+            &res,
+        );
+        Ok(res)
+    }
+
+    async fn retrieve_block_children(
+        &self,
+        block_id: &str,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<RetrieveBlockChilerenResponse, NotionClientError> {
+        let res = self
+            .api
+            .retrieve_block_children(block_id, start_cursor, page_size)
+            .await?;
+        self.record(
+            FixtureRequest::BlockChildren {
+                block_id,
+                start_cursor,
+            },
+            &res,
+        );
+        Ok(res)
+    }
+
+    async fn retrieve_a_page(&self, page_id: &str) -> Result<NotionPage, NotionClientError> {
+        let res = self.api.retrieve_a_page(page_id).await?;
+        self.record(FixtureRequest::Page { page_id }, &res);
+        Ok(res)
+    }
+
+    async fn retrieve_a_database(
+        &self,
+        database_id: &str,
+    ) -> Result<NotionDatabase, NotionClientError> {
+        let res = self.api.retrieve_a_database(database_id).await?;
+        self.record(FixtureRequest::Database { database_id }, &res);
+        Ok(res)
+    }
+
+    /// Only reads are recorded, so this is passed straight through
+    async fn create_a_page(
+        &self,
+        request: CreateAPageRequest,
+    ) -> Result<NotionPage, NotionClientError> {
+        self.api.create_a_page(request).await
+    }
+
+    /// Only reads are recorded, so this is passed straight through
+    async fn append_block_children(
+        &self,
+        block_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<AppendBlockChildrenResponse, NotionClientError> {
+        self.api.append_block_children(block_id, request).await
+    }
+
+    async fn list_all_users(
+        &self,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<ListAllUsersResponse, NotionClientError> {
+        let res = self.api.list_all_users(start_cursor, page_size).await?;
+        self.record(FixtureRequest::Users { start_cursor }, &res);
+        Ok(res)
+    }
+
+    async fn retrieve_your_token_s_bot_user(&self) -> Result<NotionUser, NotionClientError> {
+        let res = self.api.retrieve_your_token_s_bot_user().await?;
+        self.record(FixtureRequest::BotUser, &res);
+        Ok(res)
+    }
+}
+
+/// Tells the rate limiter about every rate limited (`429`) response from `api`, so it can slow
+/// down to the workspace's actual limit. `Notion` wraps whichever `NotionApi` it's given in one.
+pub struct RateLimitFeedback<A> {
+    api: A,
+    rate_limiter: Arc<TokenBucket>,
+}
+
+impl<A: NotionApi> RateLimitFeedback<A> {
+    #[must_use]
+    pub fn new(api: A, rate_limiter: Arc<TokenBucket>) -> Self {
+        RateLimitFeedback { api, rate_limiter }
+    }
+
+    fn observe<T>(&self, res: Result<T, NotionClientError>) -> Result<T, NotionClientError> {
+        if let Err(NotionClientError::InvalidStatusCode { error }) = &res {
+            if error.status == 429 {
+                self.rate_limiter.record_rate_limited();
+            }
+        }
+        res
+    }
+}
+
+#[async_trait]
+impl<A: NotionApi> NotionApi for RateLimitFeedback<A> {
+    async fn search_by_title(
+        &self,
+        request: SearchByTitleRequest,
+    ) -> Result<SearchByTitleResponse, NotionClientError> {
+        self.observe(self.api.search_by_title(request).await)
+    }
+
+    async fn retrieve_block_children(
+        &self,
+        block_id: &str,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<RetrieveBlockChilerenResponse, NotionClientError> {
+        self.observe(
+            self.api
+                .retrieve_block_children(block_id, start_cursor, page_size)
+                .await,
+        )
+    }
+
+    async fn retrieve_a_page(&self, page_id: &str) -> Result<NotionPage, NotionClientError> {
+        self.observe(self.api.retrieve_a_page(page_id).await)
+    }
+
+    async fn retrieve_a_database(
+        &self,
+        database_id: &str,
+    ) -> Result<NotionDatabase, NotionClientError> {
+        self.observe(self.api.retrieve_a_database(database_id).await)
+    }
+
+    async fn create_a_page(
+        &self,
+        request: CreateAPageRequest,
+    ) -> Result<NotionPage, NotionClientError> {
+        self.observe(self.api.create_a_page(request).await)
+    }
+
+    async fn append_block_children(
+        &self,
+        block_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<AppendBlockChildrenResponse, NotionClientError> {
+        self.observe(self.api.append_block_children(block_id, request).await)
+    }
+
+    async fn list_all_users(
+        &self,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<ListAllUsersResponse, NotionClientError> {
+        self.observe(self.api.list_all_users(start_cursor, page_size).await)
+    }
+
+    async fn retrieve_your_token_s_bot_user(&self) -> Result<NotionUser, NotionClientError> {
+        self.observe(self.api.retrieve_your_token_s_bot_user().await)
+    }
+}
+
+const NOTION_API_URL: &str = "https://api.notion.com/v1";
+
+/// How much of each response body `RequestLogger` keeps, in bytes
+const MAX_LOGGED_RESPONSE_BYTES: usize = 2048;
+
+/// Logs every request to `api` and its response at `TRACE` level, and appends each exchange
+/// as a line of JSON to a log file for offline analysis. `notion_client` doesn't expose its
+/// HTTP requests, so each one is described by the endpoint's method and URL, and by the
+/// request and response bodies. Headers are never logged, so the integration token can't end
+/// up in the log.
+pub struct RequestLogger<A> {
+    api: A,
+    /// `None` if the log file couldn't be opened, in which case exchanges are only traced
+    log_file: Option<Mutex<File>>,
+}
+
+impl<A: NotionApi> RequestLogger<A> {
+    #[must_use]
+    pub fn new(api: A, log_path: &Path) -> Self {
+        let log_file = log_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| OpenOptions::new().create(true).append(true).open(log_path));
+        let log_file = match log_file {
+            Ok(file) => Some(Mutex::new(file)),
+            Err(e) => {
+                warn!(target: "notion", "failed to open the HTTP log at {}, only tracing requests: {}", log_path.display(), e);
+                None
+            }
+        };
+        RequestLogger { api, log_file }
+    }
+
+    /// Traces and writes out one exchange, given the response `res` to `method url`
+    fn log<T: Serialize>(
+        &self,
+        method: &str,
+        url: &str,
+        request_body: Option<serde_json::Value>,
+        res: &Result<T, NotionClientError>,
+        started: Instant,
+    ) {
+        let (status, response_body) = match res {
+            Ok(response) => (
+                Some(200),
+                serde_json::to_string(response).unwrap_or_default(),
+            ),
+            Err(e @ NotionClientError::InvalidStatusCode { error }) => {
+                (Some(error.status), e.to_string())
+            }
+            // the request never got a response, e.g. it failed to connect
+            Err(e) => (None, e.to_string()),
+        };
+        let response_body = truncate_to_bytes(&response_body, MAX_LOGGED_RESPONSE_BYTES);
+
+        trace!(target: "notion", "{} {} {}", method, url, request_body.as_ref().map(ToString::to_string).unwrap_or_default());
+        trace!(target: "notion", "{} {}: {:?} {}", method, url, status, response_body);
+
+        let Some(log_file) = &self.log_file else {
+            return;
+        };
+        let entry = serde_json::json!({
+            "timestamp": Utc::now(),
+            "method": method,
+            "url": url,
+            "request_body": request_body,
+            "status": status,
+            "response_body": response_body,
+            "duration_ms": started.elapsed().as_millis() as u64,
+        });
+        // logging is best-effort, a line that fails to write shouldn't fail the run
+        if let Err(e) = writeln!(log_file.lock().unwrap(), "{}", entry) {
+            warn!(target: "notion", "failed to write to the HTTP log: {}", e);
+        }
+    }
+}
+
+/// The first `max_bytes` bytes of `s`, cut back to the nearest character boundary
+fn truncate_to_bytes(s: &str, max_bytes: usize) -> &str {
+    let mut end = s.len().min(max_bytes);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// `?start_cursor=...&page_size=...`, leaving out whichever aren't set
+fn pagination_query(start_cursor: Option<&str>, page_size: Option<u32>) -> String {
+    let params = start_cursor
+        .map(|cursor| format!("start_cursor={}", cursor))
+        .into_iter()
+        .chain(page_size.map(|size| format!("page_size={}", size)))
+        .collect::<Vec<_>>();
+    if params.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", params.join("&"))
+    }
+}
+
+#[async_trait]
+impl<A: NotionApi> NotionApi for RequestLogger<A> {
+    async fn search_by_title(
+        &self,
+        request: SearchByTitleRequest,
+    ) -> Result<SearchByTitleResponse, NotionClientError> {
+        let url = format!("{}/search", NOTION_API_URL);
+        let request_body = serde_json::to_value(&request).ok();
+        let started = Instant::now();
+        let res = self.api.search_by_title(request).await;
+        self.log("POST", &url, request_body, &res, started);
+        res
+    }
+
+    async fn retrieve_block_children(
+        &self,
+        block_id: &str,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<RetrieveBlockChilerenResponse, NotionClientError> {
+        let url = format!(
+            "{}/blocks/{}/children{}",
+            NOTION_API_URL,
+            block_id,
+            pagination_query(start_cursor, page_size)
+        );
+        let started = Instant::now();
+        let res = self
+            .api
+            .retrieve_block_children(block_id, start_cursor, page_size)
+            .await;
+        self.log("GET", &url, None, &res, started);
+        res
+    }
+
+    async fn retrieve_a_page(&self, page_id: &str) -> Result<NotionPage, NotionClientError> {
+        let url = format!("{}/pages/{}", NOTION_API_URL, page_id);
+        let started = Instant::now();
+        let res = self.api.retrieve_a_page(page_id).await;
+        self.log("GET", &url, None, &res, started);
+        res
+    }
+
+    async fn retrieve_a_database(
+        &self,
+        database_id: &str,
+    ) -> Result<NotionDatabase, NotionClientError> {
+        let url = format!("{}/databases/{}", NOTION_API_URL, database_id);
+        let started = Instant::now();
+        let res = self.api.retrieve_a_database(database_id).await;
+        self.log("GET", &url, None, &res, started);
+        res
+    }
+
+    async fn create_a_page(
+        &self,
+        request: CreateAPageRequest,
+    ) -> Result<NotionPage, NotionClientError> {
+        let url = format!("{}/pages", NOTION_API_URL);
+        let request_body = serde_json::to_value(&request).ok();
+        let started = Instant::now();
+        let res = self.api.create_a_page(request).await;
+        self.log("POST", &url, request_body, &res, started);
+        res
+    }
+
+    async fn append_block_children(
+        &self,
+        block_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<AppendBlockChildrenResponse, NotionClientError> {
+        let url = format!("{}/blocks/{}/children", NOTION_API_URL, block_id);
+        let request_body = serde_json::to_value(&request).ok();
+        let started = Instant::now();
+        let res = self.api.append_block_children(block_id, request).await;
+        self.log("PATCH", &url, request_body, &res, started);
+        res
+    }
+
+    async fn list_all_users(
+        &self,
+        start_cursor: Option<&str>,
+        page_size: Option<u32>,
+    ) -> Result<ListAllUsersResponse, NotionClientError> {
+        let url = format!(
+            "{}/users{}",
+            NOTION_API_URL,
+            pagination_query(start_cursor, page_size)
+        );
+        let started = Instant::now();
+        let res = self.api.list_all_users(start_cursor, page_size).await;
+        self.log("GET", &url, None, &res, started);
+        res
+    }
+    async fn retrieve_your_token_s_bot_user(&self) -> Result<NotionUser, NotionClientError> {
+        let url = format!("{}/users/me", NOTION_API_URL);
+        let started = Instant::now();
+        let res = self.api.retrieve_your_token_s_bot_user().await;
+        self.log("GET", &url, None, &res, started);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_to_bytes() {
+        assert_eq!(truncate_to_bytes("short", 2048), "short");
+        // "é" is two bytes, so cutting after one byte would split it
+        assert_eq!(truncate_to_bytes("aé", 2), "a");
+    }
+
+    #[test]
+    fn test_pagination_query() {
+        assert_eq!(pagination_query(None, None), "");
+        assert_eq!(
+            pagination_query(Some("abc"), Some(100)),
+            "?start_cursor=abc&page_size=100"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_versioned_client_sends_its_api_version() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // answers a single request with a 404, and returns the request's head
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..read]);
+            }
+            let body = r#"{"object":"error","status":404,"code":"object_not_found","message":"no such Page"}"#;
+            let response = format!(
+                "HTTP/1.1 404 Not Found\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap().to_lowercase()
+        });
+        let mut client =
+            VersionedClient::new("secret", HeaderValue::from_static("2022-02-22")).unwrap();
+        client.base_url = format!("http://{}", address);
+
+        let res = client.retrieve_a_page("abc").await;
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("get /pages/abc "));
+        assert!(request.contains("\r\nnotion-version: 2022-02-22\r\n"));
+        assert!(request.contains("\r\nauthorization: bearer secret\r\n"));
+        assert!(matches!(
+            res,
+            Err(NotionClientError::InvalidStatusCode { error }) if error.status == 404
+        ));
+    }
+}
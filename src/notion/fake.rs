@@ -0,0 +1,366 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use notion_client::{
+    endpoints::{
+        blocks::{
+            append::{request::AppendBlockChildrenRequest, response::AppendBlockChildrenResponse},
+            retrieve::response::RetrieveBlockChilerenResponse,
+        },
+        pages::create::request::CreateAPageRequest,
+        search::title::{request::SearchByTitleRequest, response::SearchByTitleResponse},
+        users::list::response::ListAllUsersResponse,
+    },
+    objects::{
+        database::Database as NotionDatabase, page::Page as NotionPage, user::User as NotionUser,
+    },
+    NotionClientError,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use super::{
+    all_pages_search,
+    api::{search_key, FixtureRequest, NotionApi},
+    last_edited_pages_search,
+};
+
+/// The user every fake Page and Block is created and edited by
+pub const FAKE_USER_ID: &str = "d40e767c-d7af-4b18-a86d-55c61f1e39a4";
+
+/// What `FakeNotionApi` answers a request with
+#[derive(Debug, Clone)]
+pub enum FakeResponse {
+    /// A successful response with this body
+    Json(serde_json::Value),
+    /// A successful response whose body notion-client can't read, like the ones with Block
+    /// types it doesn't know about
+    Unreadable(String),
+    /// An error response with this HTTP status, e.g. `404` for a Page that was deleted
+    Status(u16),
+}
+
+/// A fake Notion workspace that answers `NotionApi` requests from memory, for tests and
+/// benchmarks. Responses are keyed the same way recorded fixtures are (see `FixtureRequest`),
+/// so a fake can be loaded from a fixtures directory, filled in by hand, or both. A request
+/// with no response gets a `404`, the way the API answers for a Page it can't find.
+///
+/// A request can be given several responses with `respond`, which are used in order, and the
+/// last one is repeated from then on, e.g. to fail twice and then succeed.
+#[derive(Debug, Default)]
+pub struct FakeNotionApi {
+    responses: Mutex<HashMap<PathBuf, Vec<FakeResponse>>>,
+    /// Every request made, keyed like `responses`, in the order they were made
+    requested: Mutex<Vec<PathBuf>>,
+    /// The body of every `create_a_page` request, in the order they were made
+    created_pages: Mutex<Vec<serde_json::Value>>,
+    /// The ID and body of every `append_block_children` request, in the order they were made
+    appended_children: Mutex<Vec<(String, serde_json::Value)>>,
+}
+
+impl FakeNotionApi {
+    #[must_use]
+    pub fn new() -> Self {
+        FakeNotionApi::default()
+    }
+
+    /// A fake that answers with the fixtures recorded in `fixtures_dir`
+    pub fn from_fixtures(fixtures_dir: &Path) -> io::Result<Self> {
+        let fake = FakeNotionApi::new();
+        let mut dirs = vec![fixtures_dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let body = fs::read_to_string(&path)?;
+                let response = match serde_json::from_str(&body) {
+                    Ok(json) => FakeResponse::Json(json),
+                    Err(_) => FakeResponse::Unreadable(body),
+                };
+                let key = path
+                    .strip_prefix(fixtures_dir)
+                    .expect("every fixture is inside the fixtures directory")
+                    .to_path_buf();
+                fake.responses
+                    .lock()
+                    .unwrap()
+                    .entry(key)
+                    .or_default()
+                    .push(response);
+            }
+        }
+        Ok(fake)
+    }
+
+    /// Adds `response` to the ones `request` is answered with
+    pub fn respond(&self, request: FixtureRequest<'_>, response: FakeResponse) {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(key(&request))
+            .or_default()
+            .push(response);
+    }
+
+    /// Answers both of the searches `Notion` makes with `pages`, on a single page of results
+    pub fn set_search_results(&self, pages: Vec<serde_json::Value>) {
+        for search in [all_pages_search(), last_edited_pages_search()] {
+            let search = search_key(&search.build().unwrap());
+            self.respond(
+                FixtureRequest::Search {
+                    search: &search,
+                    start_cursor: None,
+                },
+                FakeResponse::Json(list_json(pages.clone(), "page_or_database")),
+            );
+        }
+    }
+
+    /// Answers requests for the children of `block_id` with `blocks`, on a single page
+    pub fn set_children(&self, block_id: &str, blocks: Vec<serde_json::Value>) {
+        self.respond(
+            FixtureRequest::BlockChildren {
+                block_id,
+                start_cursor: None,
+            },
+            FakeResponse::Json(list_json(blocks, "block")),
+        );
+    }
+
+    /// Every request made so far, as the path its fixture would be recorded to
+    #[must_use]
+    pub fn requested(&self) -> Vec<PathBuf> {
+        self.requested.lock().unwrap().clone()
+    }
+
+    /// How many times `request` has been made
+    #[must_use]
+    pub fn request_count(&self, request: FixtureRequest<'_>) -> usize {
+        let key = key(&request);
+        self.requested
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|requested| **requested == key)
+            .count()
+    }
+
+    /// The body of every `create_a_page` request made so far
+    #[must_use]
+    pub fn created_pages(&self) -> Vec<serde_json::Value> {
+        self.created_pages.lock().unwrap().clone()
+    }
+
+    /// The Block ID and body of every `append_block_children` request made so far
+    #[must_use]
+    pub fn appended_children(&self) -> Vec<(String, serde_json::Value)> {
+        self.appended_children.lock().unwrap().clone()
+    }
+
+    fn reply<T: DeserializeOwned>(
+        &self,
+        request: FixtureRequest<'_>,
+    ) -> Result<T, NotionClientError> {
+        let key = key(&request);
+        self.requested.lock().unwrap().push(key.clone());
+        let response = match self.responses.lock().unwrap().get_mut(&key) {
+            Some(responses) if responses.len() > 1 => Some(responses.remove(0)),
+            Some(responses) => responses.first().cloned(),
+            None => None,
+        };
+        let body = match response {
+            Some(FakeResponse::Json(json)) => json.to_string(),
+            Some(FakeResponse::Unreadable(body)) => body,
+            Some(FakeResponse::Status(status)) => return Err(status_error(status)),
+            None => return Err(status_error(404)),
+        };
+        serde_json::from_str(&body)
+            .map_err(|source| NotionClientError::FailedToDeserialize { source, body })
+    }
+}
+
+/// Where `request`'s fixture would be recorded, relative to the fixtures directory
+fn key(request: &FixtureRequest<'_>) -> PathBuf {
+    request.path(Path::new(""))
+}
+
+/// The error notion-client returns for a response with `status`, with the error code the
+/// Notion API sends along with it
+#[must_use]
+pub fn status_error(status: u16) -> NotionClientError {
+    let code = match status {
+        400 => "validation_error",
+        401 => "unauthorized",
+        403 => "restricted_resource",
+        404 => "object_not_found",
+        429 => "rate_limited",
+        _ => "internal_server_error",
+    };
+    NotionClientError::InvalidStatusCode {
+        error: serde_json::from_value(serde_json::json!({
+            "object": "error",
+            "status": status,
+            "code": code,
+            "message": format!("fake {} response", status)
+        }))
+        .expect("the error body has the shape of a Notion API error"),
+    }
+}
+
+/// A paginated list response with every one of `results` on it, where `type_name` is the type
+/// of object listed, e.g. `block`
+#[must_use]
+pub fn list_json(results: Vec<serde_json::Value>, type_name: &str) -> serde_json::Value {
+    serde_json::json!({
+        "object": "list",
+        "results": results,
+        "next_cursor": null,
+        "has_more": false,
+        "type": type_name,
+        type_name: {}
+    })
+}
+
+/// A Page at the top of the workspace, as the API returns it
+#[must_use]
+pub fn page_json(id: &str, url: &str, edited: DateTime<Utc>) -> serde_json::Value {
+    serde_json::json!({
+        "object": "page",
+        "id": id,
+        "created_time": edited.to_rfc3339(),
+        "last_edited_time": edited.to_rfc3339(),
+        "created_by": {"object": "user", "id": FAKE_USER_ID},
+        "last_edited_by": {"object": "user", "id": FAKE_USER_ID},
+        "cover": null,
+        "icon": null,
+        "parent": {"type": "workspace", "workspace": true},
+        "archived": false,
+        "in_trash": false,
+        "properties": {},
+        "url": url,
+        "public_url": null
+    })
+}
+
+#[async_trait]
+impl NotionApi for FakeNotionApi {
+    async fn search_by_title(
+        &self,
+        request: SearchByTitleRequest,
+    ) -> Result<SearchByTitleResponse, NotionClientError> {
+        self.reply(FixtureRequest::Search {
+            search: &search_key(&request),
+            start_cursor: request.start_cursor.as_deref(),
+        })
+    }
+
+    async fn retrieve_block_children(
+        &self,
+        block_id: &str,
+        start_cursor: Option<&str>,
+        _page_size: Option<u32>,
+    ) -> Result<RetrieveBlockChilerenResponse, NotionClientError> {
+        self.reply(FixtureRequest::BlockChildren {
+            block_id,
+            start_cursor,
+        })
+    }
+
+    async fn retrieve_a_page(&self, page_id: &str) -> Result<NotionPage, NotionClientError> {
+        self.reply(FixtureRequest::Page { page_id })
+    }
+
+    async fn retrieve_a_database(
+        &self,
+        database_id: &str,
+    ) -> Result<NotionDatabase, NotionClientError> {
+        self.reply(FixtureRequest::Database { database_id })
+    }
+
+    /// Records the request and answers with a new, empty Page
+    async fn create_a_page(
+        &self,
+        request: CreateAPageRequest,
+    ) -> Result<NotionPage, NotionClientError> {
+        let mut created_pages = self.created_pages.lock().unwrap();
+        created_pages.push(serde_json::to_value(&request).unwrap_or_default());
+        let id = format!("c0ea7ed0-0000-4000-8000-{:012x}", created_pages.len());
+        let url = format!("https://www.notion.so/{}", id.replace('-', ""));
+        Ok(serde_json::from_value(page_json(&id, &url, Utc::now()))
+            .expect("page_json has the shape of a Notion Page"))
+    }
+
+    /// Records the request and answers with the appended Blocks
+    async fn append_block_children(
+        &self,
+        block_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<AppendBlockChildrenResponse, NotionClientError> {
+        let body = serde_json::to_value(&request).unwrap_or_default();
+        let children = body["children"].as_array().cloned().unwrap_or_default();
+        self.appended_children
+            .lock()
+            .unwrap()
+            .push((block_id.to_string(), body));
+        let body = list_json(children, "block").to_string();
+        serde_json::from_str(&body)
+            .map_err(|source| NotionClientError::FailedToDeserialize { source, body })
+    }
+
+    async fn list_all_users(
+        &self,
+        start_cursor: Option<&str>,
+        _page_size: Option<u32>,
+    ) -> Result<ListAllUsersResponse, NotionClientError> {
+        self.reply(FixtureRequest::Users { start_cursor })
+    }
+
+    async fn retrieve_your_token_s_bot_user(&self) -> Result<NotionUser, NotionClientError> {
+        self.reply(FixtureRequest::BotUser)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_responses_are_used_in_order_and_the_last_repeats() {
+        let fake = FakeNotionApi::new();
+        let request = || FixtureRequest::BlockChildren {
+            block_id: "abc",
+            start_cursor: None,
+        };
+        fake.respond(request(), FakeResponse::Status(500));
+        fake.respond(
+            request(),
+            FakeResponse::Json(list_json(Vec::new(), "block")),
+        );
+
+        assert!(fake
+            .retrieve_block_children("abc", None, None)
+            .await
+            .is_err());
+        assert!(fake
+            .retrieve_block_children("abc", None, None)
+            .await
+            .is_ok());
+        assert!(fake
+            .retrieve_block_children("abc", None, None)
+            .await
+            .is_ok());
+        assert_eq!(fake.request_count(request()), 3);
+        // nothing was set up for this one
+        assert!(matches!(
+            fake.retrieve_a_page("missing").await,
+            Err(NotionClientError::InvalidStatusCode { .. })
+        ));
+    }
+}
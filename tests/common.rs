@@ -0,0 +1,28 @@
+//! Helpers shared by the integration tests. Each test file includes this with `mod common;`,
+//! so not every helper is used by every test file.
+#![allow(dead_code)]
+
+use dross::notion::{fake::FakeNotionApi, Notion, TokenBucket};
+use std::path::{Path, PathBuf};
+
+/// A fake that replays the Notion API responses recorded in `tests/fixtures/<name>/` (see
+/// `FixtureRequest` for the layout, and `dross --record-fixtures` for recording new ones)
+/// instead of making HTTP requests
+pub fn fixture_api(name: &str) -> FakeNotionApi {
+    let fixtures_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+    FakeNotionApi::from_fixtures(&fixtures_dir)
+        .unwrap_or_else(|e| panic!("can't read fixtures {}: {}", fixtures_dir.display(), e))
+}
+
+/// A `Notion` that replays the fixtures in `tests/fixtures/<name>/`, without any rate limiting
+pub fn fixture_notion(name: &str) -> Notion {
+    Notion::with_api(fixture_api(name), TokenBucket::new(1000, 1000.0))
+}
+
+/// A path in the temp dir that's unique to this test process and `name`
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dross-{}-{}", name, std::process::id()))
+}
@@ -0,0 +1,312 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::{fixture_notion, temp_path};
+use dross::core::{
+    datatypes::{Page, PropertyType},
+    error::DrossError,
+    helpers::TraversalStrategy,
+    ingest::{ingest_notion, IngestOptions},
+};
+use futures::{pin_mut, StreamExt};
+
+/// Every Block in the `workspace` fixtures was edited on or after this date, except for
+/// the toggle on the Deep Notes Page
+fn options_with_cutoff_of_august_10th(test_name: &str) -> IngestOptions {
+    IngestOptions {
+        dur: Utc::now() - Utc.with_ymd_and_hms(2024, 8, 10, 0, 0, 0).unwrap(),
+        page_map_path: temp_path(&format!("{}-pages_map.json", test_name)),
+        ..IngestOptions::default()
+    }
+}
+
+#[tokio::test]
+async fn test_ingest_paginates_through_pages_and_blocks() {
+    let notion = fixture_notion("workspace");
+    let options = options_with_cutoff_of_august_10th("pagination");
+
+    let context = ingest_notion(&notion, &options).await.unwrap();
+    std::fs::remove_file(&options.page_map_path).ok();
+
+    // the third Page is on the second page of search results
+    let titles = context
+        .pages
+        .iter()
+        .map(|output| output.page.title.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, vec!["Journal", "Empty Page", "Deep Notes"]);
+
+    // and the Journal's third Block is on the second page of its children
+    let journal = &context.pages[0];
+    assert_eq!(journal.markdown, "First thought\nSecond thought\n- third\n");
+    assert_eq!(journal.block_count, 3);
+}
+
+#[tokio::test]
+async fn test_last_n_pages_applies_to_pages_selected_by_creation() {
+    let notion = fixture_notion("workspace");
+    // every Page in the fixtures was created on August 1st
+    let options = IngestOptions {
+        dur: Utc::now() - Utc.with_ymd_and_hms(2024, 7, 31, 0, 0, 0).unwrap(),
+        select_by_creation: true,
+        last_n_pages: Some(2),
+        ..options_with_cutoff_of_august_10th("created-last-n")
+    };
+
+    let context = ingest_notion(&notion, &options).await.unwrap();
+    std::fs::remove_file(&options.page_map_path).ok();
+
+    assert_eq!(context.pages.len(), 2);
+}
+
+#[tokio::test]
+async fn test_ingest_handles_empty_pages() {
+    let notion = fixture_notion("workspace");
+    let options = options_with_cutoff_of_august_10th("empty");
+
+    let context = ingest_notion(&notion, &options).await.unwrap();
+    std::fs::remove_file(&options.page_map_path).ok();
+
+    let empty_page = &context.pages[1];
+    assert_eq!(empty_page.page.title, "Empty Page");
+    assert!(empty_page.page.is_empty());
+    assert_eq!(empty_page.markdown, "");
+    assert_eq!(empty_page.block_count, 0);
+}
+
+#[tokio::test]
+async fn test_ingest_finds_recent_blocks_nested_under_old_ones() {
+    let notion = fixture_notion("workspace");
+    let options = options_with_cutoff_of_august_10th("nested");
+
+    let context = ingest_notion(&notion, &options).await.unwrap();
+    std::fs::remove_file(&options.page_map_path).ok();
+
+    // the old toggle is left out, but the recent Blocks nested under it are grown in full
+    let deep_notes = &context.pages[2];
+    assert_eq!(
+        deep_notes.markdown,
+        "First nested\n\t- deeper\n\t\t- deepest\n"
+    );
+    assert_eq!(context.total_blocks, 6);
+}
+
+#[tokio::test]
+async fn test_ingest_blocks_only_skips_nested_blocks() {
+    let notion = fixture_notion("workspace");
+    let options = IngestOptions {
+        blocks_only: true,
+        ..options_with_cutoff_of_august_10th("blocks-only")
+    };
+
+    let context = ingest_notion(&notion, &options).await.unwrap();
+    std::fs::remove_file(&options.page_map_path).ok();
+
+    let deep_notes = &context.pages[2];
+    assert_eq!(deep_notes.markdown, "First nested\n");
+    assert_eq!(context.total_blocks, 4);
+}
+
+#[tokio::test]
+async fn test_block_root_search_stops_at_abort_time() {
+    let notion = fixture_notion("workspace");
+    let options = options_with_cutoff_of_august_10th("abort");
+    let pages = notion.get_last_edited_pages(options.dur).await.unwrap();
+    let deep_notes = pages
+        .iter()
+        .find(|page| page.title == "Deep Notes")
+        .unwrap();
+
+    // with the abort time already passed only the Page's own children are searched, so the
+    // recent Block nested under the old toggle is never found
+    let block_roots = notion.stream_page_block_roots(deep_notes, options.dur, Utc::now());
+    pin_mut!(block_roots);
+    assert!(block_roots.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_block_root_search_stops_after_consecutive_old_blocks() {
+    let mut notion = fixture_notion("workspace");
+    notion.set_consecutive_old_blocks_abort_threshold(1);
+    let options = options_with_cutoff_of_august_10th("old-blocks");
+    let pages = notion.get_last_edited_pages(options.dur).await.unwrap();
+    let deep_notes = pages
+        .iter()
+        .find(|page| page.title == "Deep Notes")
+        .unwrap();
+
+    // the Page's only child is the old toggle, so the search gives up before looking under it
+    let block_roots = notion.stream_page_block_roots(
+        deep_notes,
+        options.dur,
+        Utc::now() + chrono::Duration::seconds(30),
+    );
+    pin_mut!(block_roots);
+    assert!(block_roots.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_block_root_search_stops_at_max_blocks_per_page() {
+    let mut notion = fixture_notion("workspace");
+    notion.set_max_blocks_per_page(Some(0));
+    let options = options_with_cutoff_of_august_10th("max-blocks");
+    let pages = notion.get_last_edited_pages(options.dur).await.unwrap();
+    let deep_notes = pages
+        .iter()
+        .find(|page| page.title == "Deep Notes")
+        .unwrap();
+
+    // there's no room to queue the old toggle, so the recent Block under it is never found
+    let block_roots = notion.stream_page_block_roots(
+        deep_notes,
+        options.dur,
+        Utc::now() + chrono::Duration::seconds(30),
+    );
+    pin_mut!(block_roots);
+    assert!(block_roots.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_block_roots_are_in_page_order() {
+    let options = options_with_cutoff_of_august_10th("page-order");
+    let page = Page {
+        id: "44444444-4444-4444-8444-444444444444".to_string(),
+        title: "Ordered".to_string(),
+        url: "https://www.notion.so/Ordered-44444444444444448444444444444444".to_string(),
+        creation_date: Utc.with_ymd_and_hms(2024, 8, 1, 10, 0, 0).unwrap(),
+        update_date: Utc.with_ymd_and_hms(2024, 8, 18, 10, 0, 0).unwrap(),
+        child_blocks: Vec::new(),
+        created_by_id: "d40e767c-d7af-4b18-a86d-55c61f1e39a4".to_string(),
+        parent_database_id: None,
+        parent_page_id: None,
+    };
+
+    // both strategies find the paragraph below the old toggle before the one inside it
+    for strategy in [TraversalStrategy::Bfs, TraversalStrategy::Dfs] {
+        let mut notion = fixture_notion("ordered");
+        notion.set_traversal_strategy(strategy);
+
+        let block_roots = notion
+            .get_page_block_roots(&page, options.dur)
+            .await
+            .unwrap();
+
+        let texts = block_roots
+            .iter()
+            .map(|block| block.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["Inside the toggle", "Below the toggle"]);
+    }
+}
+
+#[tokio::test]
+async fn test_duplicate_block_children_are_skipped() {
+    let notion = fixture_notion("workspace");
+
+    // the fixture response lists the same Block twice
+    let children = notion
+        .retrieve_all_block_children("page", "d0b1e000-0000-4000-8000-000000000000")
+        .await
+        .unwrap();
+
+    assert_eq!(children.len(), 1);
+    assert_eq!(children[0].text, "listed twice");
+}
+
+#[tokio::test]
+async fn test_search_with_no_shared_pages_is_an_empty_workspace() {
+    let notion = fixture_notion("unshared");
+
+    let result = notion
+        .get_last_edited_pages(chrono::Duration::days(7))
+        .await;
+
+    assert!(matches!(result, Err(DrossError::EmptyWorkspace)));
+}
+
+#[tokio::test]
+async fn test_api_calls_past_the_budget_fail() {
+    let mut notion = fixture_notion("workspace");
+    notion.set_api_call_budget(Some(1));
+
+    // the search results span two requests, and the budget only covers the first
+    let result = notion
+        .get_last_edited_pages(chrono::Duration::days(365 * 100))
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(DrossError::BudgetExhausted { calls_made: 1 })
+    ));
+    assert_eq!(notion.api_calls_made(), 1);
+}
+
+#[tokio::test]
+async fn test_search_by_date_range_leaves_out_pages_edited_after_the_end() {
+    let notion = fixture_notion("workspace");
+
+    let pages = notion
+        .search_by_date_range(
+            Utc.with_ymd_and_hms(2024, 8, 10, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 8, 19, 10, 0, 0).unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // the Journal was edited on August 20th, and the end is inclusive
+    let titles = pages
+        .iter()
+        .map(|page| page.title.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, vec!["Empty Page", "Deep Notes"]);
+}
+
+#[tokio::test]
+async fn test_get_page_public_url() {
+    let notion = fixture_notion("workspace");
+
+    let journal = notion
+        .get_page_public_url(&"11111111-1111-4111-8111-111111111111".to_string())
+        .await
+        .unwrap();
+    let empty_page = notion
+        .get_page_public_url(&"22222222-2222-4222-8222-222222222222".to_string())
+        .await
+        .unwrap();
+    let unshared = notion
+        .get_page_public_url(&"99999999-9999-4999-8999-999999999999".to_string())
+        .await;
+
+    assert_eq!(
+        journal.as_deref(),
+        Some("https://dross.notion.site/Journal-11111111111141118111111111111111")
+    );
+    assert_eq!(empty_page, None);
+    assert!(matches!(
+        unshared,
+        Err(DrossError::InsufficientPermissions { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_retrieve_database_schema() {
+    let notion = fixture_notion("workspace");
+
+    let schema = notion
+        .retrieve_database_schema("da7aba5e-0000-4000-8000-000000000001")
+        .await
+        .unwrap();
+    let missing = notion
+        .retrieve_database_schema("99999999-9999-4999-8999-999999999999")
+        .await;
+
+    assert_eq!(schema.title, "Reading List");
+    assert_eq!(schema.properties.len(), 3);
+    assert_eq!(schema.properties["Name"], PropertyType::Title);
+    assert_eq!(schema.properties["Status"], PropertyType::Select);
+    assert_eq!(schema.properties["Pages"], PropertyType::Number);
+    assert!(matches!(
+        missing,
+        Err(DrossError::InsufficientPermissions { .. })
+    ));
+}